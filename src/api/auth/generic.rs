@@ -0,0 +1,466 @@
+//! Login with any OpenID 2.0 provider, the user supplies an identifier to discover it
+
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::api::auth::{
+    ensure_not_replayed, ensure_return_to, redirect_to, rejected_assertion_summary,
+};
+use crate::error::{AppResponse, IntoAppError};
+use crate::openid::{
+    normalize_identifier, resolve_provider, same_endpoint, verify_against_provider, ClaimedId,
+    PositiveAssertion,
+};
+use crate::state::State;
+use crate::util::nonce::NonceError;
+use crate::util::pending_login::PendingLogin;
+use crate::util::timing::timed;
+
+/// Key under which the nonce of a pending login is stored in the session
+const GENERIC_AUTH_NONCE_KEY: &str = "generic-auth-nonce";
+
+/// Key under which the verified claimed identifier is stored in the session
+const GENERIC_AUTH_IDENTITY_KEY: &str = "generic-auth-identity";
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LoginQuery {
+    /// User-Supplied Identifier, see [`normalize_identifier`]
+    identifier: String,
+}
+
+/// Discover the provider of the identifier and redirect the user to it
+pub(crate) async fn start_generic_auth(
+    session: actix_session::Session,
+    data: web::Data<State>,
+    query: web::Query<LoginQuery>,
+) -> AppResponse {
+    let identifier = normalize_identifier(&query.identifier)
+        .context("invalid identifier")
+        .map_err(|err| err.into_app_error_bad_request())?;
+
+    let (provider, claimed_id) = timed!(
+        "resolve_provider",
+        resolve_provider(&data.client, &identifier).await
+    )
+    .map_err(|err| err.into_app_error_bad_request())?;
+
+    // the identifier the OP asserts is checked against discovery in the callback
+    let nonce = data.generic.nonces.insert_new();
+    let url = data
+        .generic
+        .auth_url_with_nonce(&provider, &claimed_id, nonce.as_str())
+        .context("couldn't create auth url with nonce")?;
+
+    session
+        .insert(GENERIC_AUTH_NONCE_KEY, &nonce)
+        .context("couldn't store nonce")?;
+    data.generic.pending.insert(
+        nonce,
+        PendingLogin {
+            provider,
+            claimed_id,
+        },
+    );
+    data.metrics.logins_started.inc();
+
+    Ok(redirect_to(&url, data.redirect_status))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CallbackQuery {
+    /// Appended to `return_to` in [`start_generic_auth`]
+    custom_nonce: String,
+    #[serde(flatten)]
+    assertion: PositiveAssertion,
+}
+
+/// Make sure the provider is authoritative for the claimed identifier it asserted
+///
+/// If the identifier differs from the one discovery was performed on, e.g. because the
+/// user selected it at the OP, it has to be discovered again and lead to the same OP.
+/// The asserted identity has to be the OP-Local Identifier discovered for it.
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.2>
+async fn verify_discovered_information(
+    client: &reqwest::Client,
+    login: &PendingLogin,
+    assertion: &PositiveAssertion,
+) -> anyhow::Result<String> {
+    let claimed_id = assertion
+        .claimed_id()
+        .context("assertion is missing a claimed id")?;
+    // the fragment isn't part of the identifier used for discovery
+    let normalized = normalize_identifier(claimed_id).context("invalid claimed id")?;
+
+    let expected = match &login.claimed_id {
+        ClaimedId::Delegated { claimed_id, .. } if *claimed_id == normalized => {
+            login.claimed_id.clone()
+        }
+        _ => {
+            let (provider, expected) = resolve_provider(client, &normalized)
+                .await
+                .context("couldn't discover asserted claimed id")?;
+            if !same_endpoint(provider.endpoint(), login.provider.endpoint()) {
+                anyhow::bail!("claimed id `{}` belongs to another provider", claimed_id);
+            }
+            expected
+        }
+    };
+
+    let ClaimedId::Delegated { local_id, .. } = &expected else {
+        anyhow::bail!("claimed id `{}` is an OP identifier", claimed_id);
+    };
+    if assertion.identity() != Some(local_id.as_str()) {
+        anyhow::bail!("identity doesn't match the discovered local identifier");
+    }
+
+    Ok(claimed_id.to_string())
+}
+
+/// Process a positive assertion of the provider the user was redirected to
+///
+/// The assertion has to return to where the login was sent, be verified by the OP
+/// and not have been used before. Only then is its claimed identifier discovered.
+pub(crate) async fn return_generic_auth(
+    session: actix_session::Session,
+    data: web::Data<State>,
+    query: web::Query<CallbackQuery>,
+) -> AppResponse {
+    data.metrics.callbacks_received.inc();
+
+    let state_nonce = session
+        .get::<String>(GENERIC_AUTH_NONCE_KEY)
+        .context("couldn't deserialize session nonce")?
+        .context("there is no pending login")
+        .map_err(|err| err.into_app_error_bad_request())?;
+
+    // consumed as soon as the callback is reached, like in `return_steam_auth`
+    session.remove(GENERIC_AUTH_NONCE_KEY);
+    let login = data.generic.pending.take(&state_nonce);
+    data.generic
+        .nonces
+        .validate_and_remove(&state_nonce)
+        .inspect_err(|_| data.metrics.nonce_mismatches.inc())
+        .map_err(NonceError::into_app_error)?;
+
+    if query.custom_nonce != state_nonce {
+        data.metrics.nonce_mismatches.inc();
+        return Err(
+            anyhow::anyhow!("query param nonce doesn't match state nonce")
+                .into_app_error_bad_request(),
+        );
+    }
+
+    let login = login
+        .context("login has expired")
+        .map_err(|err| err.into_app_error_bad_request())?;
+
+    // the OP must have returned to the url we sent it to
+    let assertion = &query.assertion;
+    ensure_return_to(
+        assertion,
+        &data.generic.open_id.return_to,
+        &query.custom_nonce,
+    )
+    .map_err(|err| {
+        data.metrics.nonce_mismatches.inc();
+        err.into_app_error_bad_request()
+            .with_code("return_to_mismatch")
+    })?;
+
+    assertion
+        .validate(&login.provider)
+        .and_then(|()| assertion.validate_nonce(data.generic.nonce_tolerance))
+        .context("invalid positive assertion")
+        .map_err(|err| err.into_app_error_bad_request())?;
+
+    // the OP has to vouch for the assertion before anything in it is acted upon,
+    // otherwise anyone could make us discover whatever url they claim as their id
+    let validation_result = timed!(
+        "verify_against_provider",
+        verify_against_provider(&data.client, &login.provider, assertion).await
+    )
+    .context("couldn't verify assertion against provider")
+    .inspect_err(|_| data.metrics.verify_unreachable.inc())
+    .map_err(|err| err.into_app_error_bad_request())?;
+
+    if !validation_result.is_valid() {
+        data.metrics.verify_invalid.inc();
+        log::warn!(
+            "provider rejected the assertion, someone might have tried to forge it: {}",
+            rejected_assertion_summary(assertion)
+        );
+        #[cfg(feature = "err-trace")]
+        log::warn!("validation: {:?}", validation_result);
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    ensure_not_replayed(assertion, &data.generic.replays)?;
+
+    let claimed_id = verify_discovered_information(&data.client, &login, assertion)
+        .await
+        .map_err(|err| err.into_app_error_bad_request())?;
+
+    session
+        .insert(GENERIC_AUTH_IDENTITY_KEY, claimed_id)
+        .context("couldn't update session to authenticate")?;
+
+    Ok(redirect_to(
+        &data.generic.open_id.success_redirect,
+        data.redirect_status,
+    ))
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/callback").route(web::get().to(return_generic_auth)))
+        .service(web::resource("/login").route(web::get().to(start_generic_auth)));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::openid::test_xrds::signon_xrds;
+    use crate::openid::{Provider, Service};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, read_body_json, TestRequest};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::util::mock_op::MockOp;
+    use crate::util::test_app::{location, path_and_query, session_cookie, test_app};
+
+    const OP_ENDPOINT: &str = "https://openid.example.com/login";
+
+    const LOCAL_ID: &str = "https://openid.example.com/u/1";
+
+    /// Serve the xrds of a claimed identifier and build an assertion for it
+    async fn asserted_identifier(
+        endpoint: &str,
+    ) -> anyhow::Result<(MockServer, PositiveAssertion)> {
+        asserted_delegated_identifier(endpoint, None, None).await
+    }
+
+    /// Like [`asserted_identifier`] with the identifier delegated to `local_id`,
+    /// the assertion is about `identity` (defaults to the discovered identity)
+    async fn asserted_delegated_identifier(
+        endpoint: &str,
+        local_id: Option<&str>,
+        identity: Option<&str>,
+    ) -> anyhow::Result<(MockServer, PositiveAssertion)> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(signon_xrds(endpoint, local_id), "application/xrds+xml"),
+            )
+            .mount(&server)
+            .await;
+
+        let claimed_id = format!("{}/user", server.uri());
+        let identity = identity.or(local_id).unwrap_or(&claimed_id);
+        let query = serde_urlencoded::to_string([
+            ("openid.ns", "http://specs.openid.net/auth/2.0"),
+            ("openid.mode", "id_res"),
+            ("openid.op_endpoint", OP_ENDPOINT),
+            ("openid.claimed_id", &claimed_id),
+            ("openid.identity", identity),
+            (
+                "openid.return_to",
+                "http://localhost:8080/api/auth/generic/callback",
+            ),
+            ("openid.response_nonce", "2023-09-15T11:23:46Zsalt"),
+            ("openid.assoc_handle", "1234567890"),
+            (
+                "openid.signed",
+                "op_endpoint,claimed_id,identity,return_to,response_nonce,assoc_handle",
+            ),
+            ("openid.sig", "c2lnbmF0dXJl"),
+        ])?;
+        Ok((server, serde_urlencoded::from_str(&query)?))
+    }
+
+    fn pending_login() -> PendingLogin {
+        PendingLogin {
+            provider: Provider {
+                service: Service {
+                    endpoint: OP_ENDPOINT.to_string(),
+                    ..Service::default()
+                },
+            },
+            claimed_id: ClaimedId::Select,
+        }
+    }
+
+    #[tokio::test]
+    async fn selected_identifier_is_discovered() -> anyhow::Result<()> {
+        let (_server, assertion) = asserted_identifier(OP_ENDPOINT).await?;
+        let claimed_id =
+            verify_discovered_information(&reqwest::Client::new(), &pending_login(), &assertion)
+                .await?;
+        assert_eq!(Some(claimed_id.as_str()), assertion.claimed_id());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn identifier_of_other_provider_is_rejected() -> anyhow::Result<()> {
+        let (_server, assertion) = asserted_identifier("https://evil.example.com/login").await?;
+        let result =
+            verify_discovered_information(&reqwest::Client::new(), &pending_login(), &assertion)
+                .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delegated_identifier_is_accepted() -> anyhow::Result<()> {
+        let (_server, assertion) =
+            asserted_delegated_identifier(OP_ENDPOINT, Some(LOCAL_ID), None).await?;
+        assert_ne!(assertion.claimed_id(), assertion.identity());
+        assertion.validate(&pending_login().provider)?;
+
+        let claimed_id =
+            verify_discovered_information(&reqwest::Client::new(), &pending_login(), &assertion)
+                .await?;
+        assert_eq!(Some(claimed_id.as_str()), assertion.claimed_id());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delegated_identifier_with_other_identity_is_rejected() -> anyhow::Result<()> {
+        let (_server, assertion) = asserted_delegated_identifier(
+            OP_ENDPOINT,
+            Some(LOCAL_ID),
+            Some("https://openid.example.com/u/2"),
+        )
+        .await?;
+        let result =
+            verify_discovered_information(&reqwest::Client::new(), &pending_login(), &assertion)
+                .await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    /// Serve an identifier at `/user` that is delegated to `op`
+    async fn identifier_at(op: &MockOp, expected_requests: u64) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(signon_xrds(&op.endpoint(), None), "application/xrds+xml"),
+            )
+            .expect(expected_requests)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn login_uri(identifier: &str) -> anyhow::Result<String> {
+        let query = serde_urlencoded::to_string([("identifier", identifier)])?;
+        Ok(format!("/api/auth/generic/login?{}", query))
+    }
+
+    /// Replace the value of the query param `key` in `url`
+    fn replace_param(url: &mut reqwest::Url, key: &str, value: &str) {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| match k == key {
+                true => (k.into_owned(), value.to_string()),
+                false => (k.into_owned(), v.into_owned()),
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    #[actix_web::test]
+    async fn login_with_delegated_identifier() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let identifier = identifier_at(&op, 1).await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+        let claimed_id = format!("{}/user", identifier.uri());
+
+        let req = TestRequest::get()
+            .uri(&login_uri(&claimed_id)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        let auth_url = location(&res)?;
+        assert!(auth_url.starts_with(&op.endpoint()));
+
+        let callback = op.positive_assertion_for(auth_url, &claimed_id)?;
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(location(&res)?, "/welcome");
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn forged_assertion_is_rejected_before_discovery() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let identifier = identifier_at(&op, 1).await;
+        // whatever the forged assertion claims must never be fetched
+        let target = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&target)
+            .await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+
+        let req = TestRequest::get()
+            .uri(&login_uri(&format!("{}/user", identifier.uri()))?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        let mut callback =
+            op.positive_assertion_for(location(&res)?, &format!("{}/admin", target.uri()))?;
+        replace_param(&mut callback, "openid.sig", "c2lnbmF0dXJl");
+
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn assertion_for_other_return_to_is_rejected() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let identifier = identifier_at(&op, 1).await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+        let claimed_id = format!("{}/user", identifier.uri());
+
+        let req = TestRequest::get()
+            .uri(&login_uri(&claimed_id)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        let mut callback = op.positive_assertion_for(location(&res)?, &claimed_id)?;
+        let (_, nonce) = callback
+            .query_pairs()
+            .find(|(key, _)| key == "custom_nonce")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .context("callback is missing the nonce")?;
+        let other = format!(
+            "http://localhost:8080/api/auth/steam/callback?custom_nonce={}",
+            nonce
+        );
+        replace_param(&mut callback, "openid.return_to", &other);
+
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["code"], "return_to_mismatch");
+        Ok(())
+    }
+}
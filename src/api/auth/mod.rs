@@ -1,9 +1,83 @@
-mod never;
+mod generic;
 mod steam;
 
-use actix_web::web;
+use crate::openid::{PositiveAssertion, ReturnTo};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+
+use crate::error::{AppResult, IntoAppError};
+use crate::util::replay_cache::ReplayCache;
+
+/// Redirect to `location`, `status` should be [`crate::state::State::redirect_status`]
+pub(crate) fn redirect_to(location: &str, status: StatusCode) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Make sure the `return_to` the OP signed is `expected` with the nonce we appended
+///
+/// The `custom_nonce` in the query could be appended by anyone,
+/// the one inside of [`PositiveAssertion::return_to`] is covered by the signature.
+pub(crate) fn ensure_return_to(
+    assertion: &PositiveAssertion,
+    expected: &ReturnTo,
+    custom_nonce: &str,
+) -> anyhow::Result<()> {
+    expected
+        .ensure_matches(assertion.return_to())
+        .context("assertion was issued for another return_to url")?;
+    let return_to =
+        reqwest::Url::parse(assertion.return_to()).context("couldn't parse return_to url")?;
+    let (_, nonce) = return_to
+        .query_pairs()
+        .find(|(key, _)| key == "custom_nonce")
+        .context("return_to is missing the custom nonce")?;
+    if nonce != custom_nonce {
+        anyhow::bail!("return_to nonce doesn't match query param nonce");
+    }
+    Ok(())
+}
+
+/// What operators need to tell an attack from a problem at the OP,
+/// the signature is left out
+pub(crate) fn rejected_assertion_summary(assertion: &PositiveAssertion) -> String {
+    format!(
+        "claimed_id {:?}, op_endpoint {:?}, assoc_handle {:?}, signed {:?}",
+        assertion.claimed_id(),
+        assertion.op_endpoint(),
+        assertion.association_handle(),
+        assertion.signed_fields().join(",")
+    )
+}
+
+/// Reject an assertion that has already been accepted once with a 400
+///
+/// Only genuine assertions are recorded, otherwise a forged copy of an assertion
+/// could keep the user it was issued to from logging in.
+pub(crate) fn ensure_not_replayed(
+    assertion: &PositiveAssertion,
+    replays: &ReplayCache,
+) -> AppResult<()> {
+    assertion
+        .response_nonce()
+        .context("missing response nonce")
+        .and_then(|nonce| {
+            replays.check_and_record((
+                assertion.op_endpoint(),
+                assertion.association_handle(),
+                nonce.as_str(),
+            ))
+        })
+        .map_err(|err| {
+            log::warn!("rejected a replayed assertion: {:#}", err);
+            err.into_app_error_bad_request()
+                .with_code("assertion_replayed")
+        })
+}
 
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(web::scope("/steam").configure(steam::configure))
-        .service(web::scope("/never").configure(never::configure));
+        .service(web::scope("/generic").configure(generic::configure));
 }
@@ -1,26 +1,35 @@
-use std::str::FromStr;
+use std::collections::{BTreeMap, HashMap};
 
-use actix_web::{http, web, HttpResponse};
+use actix_web::error::{QueryPayloadError, UrlencodedError};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
 use anyhow::Context;
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use steam_api_concurrent::SteamId;
 
+use crate::api::auth::{
+    ensure_not_replayed, ensure_return_to, redirect_to, rejected_assertion_summary,
+};
 use crate::api::session::{AuthSession, SteamAuthState};
+use crate::config::CallbackResponseMode;
 use crate::error::{AppResponse, AppResult, IntoAppError};
+use crate::openid::nonce::Nonce;
 use crate::openid::{
-    verify_against_provider, PositiveAssertion, VerifyResponse, STEAM_IDENTITY_PREFIX,
+    verify_against_provider, PositiveAssertion, SteamIdentity, VerifyOutcome, VerifyResponse,
 };
-use crate::State;
+use crate::state::State;
+use crate::util::nonce::NonceError;
+use crate::util::query_limit::MAX_QUERY_LEN;
+use crate::util::timing::timed;
 
 /// Initiate OpenID 2.0 authentication with Steam
 pub(crate) async fn start_steam_auth(
     session: actix_session::Session,
     data: web::Data<State>,
 ) -> AppResponse {
-    let state = session.steam_auth_state()?;
+    let state = session.steam_auth_state(&data)?;
 
-    let nonce = match state.as_ref() {
+    let attempt = match state.as_ref() {
         Some(SteamAuthState::Redirected { .. }) => {
             // the user should've been redirected to steam and not be on this page
             // give him a new nonce, remove the old one and move on.
@@ -30,10 +39,10 @@ pub(crate) async fn start_steam_auth(
         }
         Some(SteamAuthState::Authenticated { .. }) => {
             // the user is already authenticated, send him back to the home page
-            let redirect_to = data.steam.open_id.success_redirect.as_str();
-            return Ok(HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
-                .insert_header((http::header::LOCATION.as_str(), redirect_to))
-                .finish());
+            return Ok(redirect_to(
+                &data.steam.open_id.success_redirect,
+                data.redirect_status,
+            ));
         }
         None => {
             // the expected case, the user visists this page for the first time
@@ -45,25 +54,96 @@ pub(crate) async fn start_steam_auth(
 
     let url = data
         .steam
-        .auth_url_with_nonce(nonce.as_str())
+        .auth_url_with_nonce(attempt.nonce.as_str(), &attempt.csrf_state)
         .context("couldn't create auth url with nonce")?;
+    data.metrics.logins_started.inc();
 
-    // Could just use redirect but here we can see how redirects work.
-    // Just pray, that this is actually correct (●'◡'●)
-    Ok(HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
-        .insert_header((http::header::LOCATION.as_str(), url))
-        .finish())
+    Ok(redirect_to(&url, data.redirect_status))
 }
 
 pub(crate) async fn logout_steam_auth(
     session: actix_session::Session,
     data: web::Data<State>,
 ) -> AppResult<HttpResponse> {
-    session.logout().context("couldn't logout")?;
-    let redirect_to = data.steam.open_id.logout_redirect.as_str();
-    Ok(HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
-        .insert_header((http::header::LOCATION.as_str(), redirect_to))
-        .finish())
+    session.logout(&data).context("couldn't logout")?;
+    Ok(redirect_to(
+        &data.steam.open_id.logout_redirect,
+        data.redirect_status,
+    ))
+}
+
+/// Login state of the visitor as reported by [`status_steam_auth`]
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "state")]
+enum StatusResponse {
+    Authenticated { steam_id: SteamId },
+    Redirected,
+    Anonymous,
+}
+
+impl From<Option<&SteamAuthState>> for StatusResponse {
+    fn from(state: Option<&SteamAuthState>) -> StatusResponse {
+        match state {
+            Some(SteamAuthState::Authenticated { id, .. }) => {
+                StatusResponse::Authenticated { steam_id: *id }
+            }
+            Some(SteamAuthState::Redirected { .. }) => StatusResponse::Redirected,
+            None => StatusResponse::Anonymous,
+        }
+    }
+}
+
+/// Let the frontend know whether the visitor is logged in.
+///
+/// This is purely a read and never mutates the session.
+pub(crate) async fn status_steam_auth(
+    session: actix_session::Session,
+    data: web::Data<State>,
+) -> AppResponse {
+    let state = session.steam_auth_state(&data)?;
+    Ok(HttpResponse::Ok().json(StatusResponse::from(state.as_ref())))
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshNonceResponse {
+    auth_url: String,
+}
+
+/// Replace the nonce of a pending login and hand out the auth url for the new one,
+/// so a frontend can keep its login button fresh without reloading the page.
+///
+/// Responds with 409 if the user is already authenticated.
+pub(crate) async fn refresh_steam_nonce(
+    session: actix_session::Session,
+    data: web::Data<State>,
+) -> AppResponse {
+    let nonce = match session.steam_auth_state(&data)? {
+        Some(SteamAuthState::Redirected { nonce, .. }) => nonce,
+        Some(SteamAuthState::Authenticated { .. }) => {
+            return Err(anyhow::anyhow!("already authenticated").into_app_error_conflict());
+        }
+        None => {
+            return Err(anyhow::anyhow!("no login in progress").into_app_error_bad_request());
+        }
+    };
+
+    let attempt = match session.validate_replace_nonce(&data, nonce.as_str()) {
+        Ok(attempt) => attempt,
+        Err(err) => {
+            // the old nonce has been reaped already, which is what refreshing is for
+            log::debug!("couldn't replace nonce, inserting a new one: {:#}", err);
+            session
+                .insert_new_nonce(&data)
+                .context("couldn't create nonce")?
+        }
+    };
+    let auth_url = data
+        .steam
+        .auth_url_with_nonce(attempt.nonce.as_str(), &attempt.csrf_state)
+        .context("couldn't create auth url with nonce")?;
+
+    Ok(HttpResponse::Ok().json(RefreshNonceResponse { auth_url }))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -71,122 +151,1269 @@ pub(crate) struct CallbackQuery {
     /// We append this nonce to the auth request in [`start_steam_auth`]
     /// to [`PositiveAssertion::return_to`] and as per spec it must be preserved.
     custom_nonce: String,
+    /// Appended next to the nonce, must be the [`SteamAuthState::Redirected::csrf_state`]
+    /// of the session the login was started in
+    #[serde(default)]
+    state: Option<String>,
     /// Regular fields expected when callback is called
     #[serde(flatten)]
     assertion: PositiveAssertion,
+    /// Everything else, e.g. extensions or params steam started to send,
+    /// logged if [`crate::state::SteamState::strict_callback`] is set
+    #[serde(flatten, deserialize_with = "deserialize_unrecognized")]
+    unrecognized: HashMap<String, String>,
 }
 
+/// The assertion sees every param it is flattened with, leave out the ones it consumed
+fn deserialize_unrecognized<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut params = HashMap::<String, String>::deserialize(deserializer)?;
+    params.retain(|key, _| !PositiveAssertion::FIELDS.contains(&key.as_str()));
+    Ok(params)
+}
+
+/// Body of a successful callback, unless it redirects (see [`CallbackResponseMode`])
+///
+/// Only [`CallbackResponseMode::Json`] echoes the assertion back to the client, see [`EchoedAssertion`].
 #[derive(Debug, Serialize)]
 struct CallbackResponse<'a> {
-    response: &'a VerifyResponse,
-    custom_nonce: &'a str,
-    assertion: &'a PositiveAssertion,
+    steam_id: SteamId,
+    authenticated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<&'a VerifyResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    custom_nonce: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assertion: Option<EchoedAssertion<'a>>,
+}
+
+/// The assertion as the client may see it, the signature, the association it was made
+/// with and the list of signed fields stay on the server
+#[derive(Debug, Serialize)]
+struct EchoedAssertion<'a> {
+    #[serde(rename = "openid.op_endpoint")]
+    op_endpoint: &'a str,
+    #[serde(rename = "openid.claimed_id", skip_serializing_if = "Option::is_none")]
+    claimed_id: Option<&'a str>,
+    #[serde(rename = "openid.identity", skip_serializing_if = "Option::is_none")]
+    identity: Option<&'a str>,
+    #[serde(rename = "openid.return_to")]
+    return_to: &'a str,
+    #[serde(
+        rename = "openid.response_nonce",
+        skip_serializing_if = "Option::is_none"
+    )]
+    response_nonce: Option<&'a Nonce>,
+    #[serde(flatten)]
+    extensions: &'a BTreeMap<String, String>,
+}
+
+impl<'a> From<&'a PositiveAssertion> for EchoedAssertion<'a> {
+    fn from(assertion: &'a PositiveAssertion) -> EchoedAssertion<'a> {
+        EchoedAssertion {
+            op_endpoint: assertion.op_endpoint(),
+            claimed_id: assertion.claimed_id(),
+            identity: assertion.identity(),
+            return_to: assertion.return_to(),
+            response_nonce: assertion.response_nonce(),
+            extensions: assertion.extensions(),
+        }
+    }
 }
 
+impl<'a> CallbackResponse<'a> {
+    const fn minimal(steam_id: SteamId) -> CallbackResponse<'a> {
+        CallbackResponse {
+            steam_id,
+            authenticated: true,
+            response: None,
+            custom_nonce: None,
+            assertion: None,
+        }
+    }
+    fn full(
+        steam_id: SteamId,
+        response: &'a VerifyResponse,
+        query: &'a CallbackQuery,
+    ) -> CallbackResponse<'a> {
+        CallbackResponse {
+            response: Some(response),
+            custom_nonce: Some(query.custom_nonce.as_str()),
+            assertion: Some(EchoedAssertion::from(&query.assertion)),
+            ..CallbackResponse::minimal(steam_id)
+        }
+    }
+}
+
+/// Verify the assertion with a stored association, `None` if steam has to verify it
+fn verify_with_stored_association(
+    assertion: &PositiveAssertion,
+    state: &State,
+) -> Option<VerifyResponse> {
+    let associations = &state.steam.associations;
+    let handle = assertion.association_handle();
+    let association = associations.get(handle)?;
+    match assertion.verify_with_association(&association) {
+        VerifyOutcome::Valid => Some(VerifyResponse::local(true)),
+        VerifyOutcome::InvalidSignature => Some(VerifyResponse::local(false)),
+        VerifyOutcome::Expired => {
+            associations.remove(handle);
+            None
+        }
+        VerifyOutcome::HandleMismatch => None,
+    }
+}
+
+/// Let steam verify the assertion (502 if that fails)
+async fn verify_with_steam(
+    assertion: &PositiveAssertion,
+    state: &State,
+) -> AppResult<VerifyResponse> {
+    let validation_result = timed!(
+        "verify_against_provider",
+        verify_against_provider(&state.client, &state.steam.provider(), assertion).await
+    )
+    .context("couldn't verify assertion against provider")
+    .inspect_err(|_| state.metrics.verify_unreachable.inc())
+    .map_err(|err| {
+        err.into_app_error_bad_gateway()
+            .with_code("provider_unreachable")
+    })?;
+
+    if let Some(handle) = validation_result.invalidate_handle() {
+        state.steam.associations.remove(handle);
+    }
+
+    Ok(validation_result)
+}
+
+/// Check the assertion ourselves (400) and verify it with a stored association,
+/// or let steam verify it if there is none (502 if that fails)
+///
+/// A genuine assertion is only accepted once (400).
 async fn validate_positive_assertion(
     assertion: &PositiveAssertion,
     state: &State,
-) -> anyhow::Result<VerifyResponse> {
+) -> AppResult<VerifyResponse> {
     assertion
-        .validate(&state.steam.provider)
-        .context("invalid positive assertion (generic)")?;
-    assertion
-        .validate_steam()
-        .context("invalid positive assertion (steam)")?;
+        .validate(&state.steam.provider())
+        .context("invalid positive assertion (generic)")
+        .and_then(|()| {
+            assertion
+                .validate_steam(state.steam.nonce_tolerance)
+                .context("invalid positive assertion (steam)")
+        })
+        .map_err(|err| {
+            err.into_app_error_bad_request()
+                .with_code("invalid_assertion")
+        })?;
 
-    let validation_result =
-        verify_against_provider(&state.client, &state.steam.provider, assertion)
-            .await
-            .context("couldn't verify assertion against provider")?;
+    let validation_result = match verify_with_stored_association(assertion, state) {
+        Some(validation_result) => validation_result,
+        None => verify_with_steam(assertion, state).await?,
+    };
+
+    if validation_result.is_valid() {
+        ensure_not_replayed(assertion, &state.steam.replays)?;
+    }
 
     Ok(validation_result)
 }
 
+/// Reject steam ids that aren't on the allowlist with a 403
+fn ensure_allowed(state: &State, steam_id: SteamId) -> AppResult<()> {
+    if state.steam.allowlist.permits(steam_id) {
+        return Ok(());
+    }
+    Err(
+        anyhow::anyhow!("steam id {} is not allowed to log in", steam_id)
+            .into_app_error_forbidden()
+            .with_code("not_allowed"),
+    )
+}
+
 /// Process a possible OpenID 2.0 Positive Assertion
 /// after the user has granted **authentication**.
+///
+/// The nonce of the session is consumed as soon as the callback is reached,
+/// every login attempt gets exactly one callback no matter how it ends.
+///
+/// Failures respond with the usual error json and a `code`:
+/// - 400 if the assertion is malformed or doesn't match what we sent to steam
+/// - 401 if the callback doesn't belong to the login of the session or steam rejects the assertion
+/// - 403 if the steam id isn't on the allowlist
+/// - 410 if the login took too long, see [`NonceError`]
+/// - 502 if steam couldn't be asked to verify the assertion
 pub(crate) async fn return_steam_auth(
     session: actix_session::Session,
     data: web::Data<State>,
     query: web::Query<CallbackQuery>,
 ) -> AppResponse {
-    let state = session.steam_auth_state()?;
+    data.metrics.callbacks_received.inc();
+    if data.steam.strict_callback && !query.unrecognized.is_empty() {
+        log::warn!(
+            "callback has unrecognized query params: {:?}",
+            query.unrecognized
+        );
+    }
+    let state = session.steam_auth_state(&data)?;
 
-    let state_nonce = match state.as_ref() {
-        Some(SteamAuthState::Redirected { nonce }) => {
+    let (state_nonce, csrf_state) = match state.as_ref() {
+        Some(SteamAuthState::Redirected { nonce, csrf_state }) => {
             // we expect to see this nonce in the return_to for the open id response
             // and in the query parameters.
-            nonce
+            (nonce, csrf_state)
         }
         Some(SteamAuthState::Authenticated { .. }) => {
             // the user is already authenticated...?
-            let redirect_to = data.steam.open_id.success_redirect.as_str();
-            return Ok(HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
-                .insert_header((http::header::LOCATION.as_str(), redirect_to))
-                .finish());
+            return Ok(redirect_to(
+                &data.steam.open_id.success_redirect,
+                data.redirect_status,
+            ));
         }
         None => {
             // the user should visit the login page first
-            return Ok(HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
-                .insert_header((http::header::LOCATION.as_str(), "/api/auth/steam/login"))
-                .finish());
+            return Ok(redirect_to("/api/auth/steam/login", data.redirect_status));
         }
     };
 
+    // validate and remove the nonce before anything else can fail,
+    // a failed callback must not leave it around for another try
+    let nonces = &data.steam.nonces;
+    nonces
+        .validate_and_remove(state_nonce.as_str())
+        .inspect_err(|_| data.metrics.nonce_mismatches.inc())
+        .map_err(NonceError::into_app_error)?;
+
     // check that the nonces in the query parameters and in the cookie state match
     if query.custom_nonce != state_nonce.as_str() {
+        data.metrics.nonce_mismatches.inc();
         return Err(
-            anyhow::anyhow!("query param nonce doesn't match state nonce",)
-                .into_app_error_bad_request(),
+            anyhow::anyhow!("query param nonce doesn't match state nonce")
+                .into_app_error_unauthorized()
+                .with_code("nonce_mismatch"),
         );
     }
 
-    // validate and remove the nonce as it is now used
-    let nonces = &data.steam.nonces;
-    nonces
-        .validate_and_remove(&query.custom_nonce)
-        .context("couldn't validate the supplied nonce")
-        .map_err(|err| err.into_app_error_bad_request())?;
+    // the callback must belong to the login started in this session,
+    // protects against login csrf on top of the nonce
+    if csrf_state.is_none() || query.state != *csrf_state {
+        data.metrics.nonce_mismatches.inc();
+        return Err(
+            anyhow::anyhow!("query param state doesn't match session state")
+                .into_app_error_unauthorized()
+                .with_code("state_mismatch"),
+        );
+    }
+
+    // the OP must have returned to the url we sent it to
+    ensure_return_to(
+        &query.assertion,
+        &data.steam.open_id.return_to,
+        &query.custom_nonce,
+    )
+    .map_err(|err| {
+        data.metrics.nonce_mismatches.inc();
+        err.into_app_error_bad_request()
+            .with_code("return_to_mismatch")
+    })?;
 
     // extract the steam id from the positive asstion from steam
-    let steam_id_str = query
+    let steam_id = query
         .assertion
         .claimed_id()
-        .strip_prefix(STEAM_IDENTITY_PREFIX)
-        .context("assertion claimed id has invalid prefix")
-        .map_err(|err| err.into_app_error_bad_request())?;
-    let steam_id = SteamId::from_str(steam_id_str)
-        .context("couldn't parse steam id")
-        .map_err(|err| err.into_app_error_bad_request())?;
+        .context("assertion is missing a claimed id")
+        .and_then(SteamIdentity::from_claimed_id)
+        .map_err(|err| {
+            err.into_app_error_bad_request()
+                .with_code("invalid_claimed_id")
+        })?
+        .steam_id();
 
     // make another request to validate the positive assertion
     //
     // without this, another user could spoof a valid
     // openid endpoint and impersonate other users!
-    let validation_result = validate_positive_assertion(&query.assertion, &data)
-        .await
-        .map_err(|err| err.into_app_error_bad_request())?;
+    let validation_result = validate_positive_assertion(&query.assertion, &data).await?;
 
     // the positive assertion was not genuine but has been forged
     if !validation_result.is_valid() {
-        log::warn!("someone tried to forge a request!");
-        log::warn!("query: {:?}", query);
-        log::warn!("validation: {:?}", validation_result);
-        return Ok(HttpResponse::BadRequest().finish());
+        data.metrics.verify_invalid.inc();
+        log::warn!(
+            "provider rejected the assertion, someone might have tried to forge it: {}",
+            rejected_assertion_summary(&query.assertion)
+        );
+        #[cfg(feature = "err-trace")]
+        {
+            log::warn!("return_to: {:?}", query.assertion.return_to());
+            log::warn!("validation: {:?}", validation_result);
+        }
+        return Err(anyhow::anyhow!("steam rejected the assertion")
+            .into_app_error_unauthorized()
+            .with_code("assertion_rejected"));
     }
 
+    // the user is genuine but might not be allowed in
+    ensure_allowed(&data, steam_id)?;
+
     // everything has been checked, the user is good to go!
     session
-        .authenticate(steam_id)
+        .authenticate(&data, steam_id)
         .context("couldn't update session to authenticate")?;
 
-    let redirect_to = data.steam.open_id.success_redirect.as_str();
-    Ok(HttpResponse::build(StatusCode::TEMPORARY_REDIRECT)
-        .insert_header((http::header::LOCATION.as_str(), redirect_to))
-        .finish())
+    Ok(match data.steam.callback_response {
+        CallbackResponseMode::Redirect => {
+            redirect_to(&data.steam.open_id.success_redirect, data.redirect_status)
+        }
+        CallbackResponseMode::Json => {
+            HttpResponse::Ok().json(CallbackResponse::full(steam_id, &validation_result, &query))
+        }
+        CallbackResponseMode::Minimal => {
+            HttpResponse::Ok().json(CallbackResponse::minimal(steam_id))
+        }
+    })
+}
+
+/// Send an indirect response delivered as a form on to [`return_steam_auth`] as a query
+///
+/// The OP posts the form cross-site and browsers don't send a `SameSite=Lax` session
+/// cookie along with it. The `303` back to the callback is a same-site `GET`
+/// that carries the cookie again, the session isn't touched before that.
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.5.1.2>
+pub(crate) async fn return_steam_auth_form(
+    req: HttpRequest,
+    form: web::Form<Vec<(String, String)>>,
+) -> AppResponse {
+    let query =
+        serde_urlencoded::to_string(form.into_inner()).context("couldn't encode form as query")?;
+    let location = format!("{}?{}", req.path(), query);
+    Ok(redirect_to(&location, StatusCode::SEE_OTHER))
+}
+
+/// A form callback carries the same assertion as a query, so it is bounded the same way
+const MAX_FORM_LEN: usize = MAX_QUERY_LEN;
+
+/// Reject a query that doesn't fit the handler with the standard error body
+fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    anyhow::anyhow!("{}", err)
+        .context("couldn't parse query")
+        .into_app_error_bad_request()
+        .with_code("invalid_query")
+        .into()
+}
+
+/// Like [`query_error_handler`], a form larger than [`MAX_FORM_LEN`] gets a 413
+fn form_error_handler(err: UrlencodedError, _req: &HttpRequest) -> actix_web::Error {
+    let app_error = match err {
+        UrlencodedError::Overflow { size, limit } => {
+            anyhow::anyhow!("form is {} bytes long, at most {} are allowed", size, limit)
+                .into_app_error_with_status(StatusCode::PAYLOAD_TOO_LARGE)
+                .with_code("form_too_large")
+        }
+        err => anyhow::anyhow!("{}", err)
+            .context("couldn't parse form")
+            .into_app_error_bad_request()
+            .with_code("invalid_form"),
+    };
+    app_error.into()
 }
 
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/callback").route(web::get().to(return_steam_auth)))
-        .service(web::resource("/login").route(web::get().to(start_steam_auth)))
-        .service(web::resource("/logout").route(web::get().to(logout_steam_auth)));
+    cfg.app_data(web::QueryConfig::default().error_handler(query_error_handler))
+        .app_data(
+            web::FormConfig::default()
+                .limit(MAX_FORM_LEN)
+                .error_handler(form_error_handler),
+        );
+    cfg.service(
+        web::resource("/callback")
+            .route(web::get().to(return_steam_auth))
+            .route(web::post().to(return_steam_auth_form)),
+    )
+    .service(web::resource("/login").route(web::get().to(start_steam_auth)))
+    .service(web::resource("/logout").route(web::get().to(logout_steam_auth)))
+    .service(web::resource("/refresh-nonce").route(web::post().to(refresh_steam_nonce)))
+    .service(web::resource("/status").route(web::get().to(status_steam_auth)));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::openid::constants::OPENID_ASSOCIATION_HANDLE;
+    use crate::openid::{Association, ClaimedId, Provider, Realm, ReturnTo};
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::header;
+    use actix_web::test::{call_service, TestRequest};
+    use chrono::Utc;
+
+    use super::*;
+    use crate::util::mock_op::MockOp;
+    use crate::util::test_app::{location, path_and_query, session_cookie, test_app, STEAM_ID};
+
+    const STEAM_IDENTITY_URL: &str = "https://steamcommunity.com/openid/id/76561198181282063";
+
+    /// Assertion as steam would send it, the nonce is appended by the tests
+    const ASSERTION_QUERY: &str = "openid.ns=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0&openid.mode=id_res&openid.op_endpoint=https%3A%2F%2Fsteamcommunity.com%2Fopenid%2Flogin&openid.claimed_id=https%3A%2F%2Fsteamcommunity.com%2Fopenid%2Fid%2F76561198181282063&openid.identity=https%3A%2F%2Fsteamcommunity.com%2Fopenid%2Fid%2F76561198181282063&openid.return_to=http%3A%2F%2Flocalhost%3A8080%2Fapi%2Fauth%2Fsteam%2Fcallback&openid.response_nonce=2023-09-15T11%3A23%3A46Z7RPb74voq1sqY2sKMcnOe%2FrxwQg%3D&openid.assoc_handle=1234567890&openid.signed=signed%2Cop_endpoint%2Cclaimed_id%2Cidentity%2Creturn_to%2Cresponse_nonce%2Cassoc_handle&openid.sig=SPaIMgwuYCQ2zVlgYmbSAKfD8Ps%3D";
+
+    #[actix_web::test]
+    async fn login_redirects_to_provider() -> anyhow::Result<()> {
+        let app = test_app!(web::Data::new(State::for_test(Provider::steam())?));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let first = location(&res)?.to_string();
+        assert!(first.starts_with(Provider::steam().endpoint()));
+        assert!(first.contains("custom_nonce"));
+
+        // visiting the login page again hands out a new nonce
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/login")
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        let second = location(&res)?;
+        assert!(second.starts_with(Provider::steam().endpoint()));
+        assert_ne!(first, second);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn login_with_mock_op() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let auth_url = location(&res)?;
+        assert!(auth_url.starts_with(&op.endpoint()));
+
+        let callback = op.positive_assertion(auth_url, STEAM_ID)?;
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(location(&res)?, "/welcome");
+
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/status")
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let status: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(
+            status,
+            serde_json::json!({ "state": "authenticated", "steam_id": STEAM_ID.0 })
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn login_with_mock_op_form() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+
+        let cookie = session_cookie(&res)?;
+        let callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+
+        // the OP posts cross-site, a `SameSite=Lax` session cookie isn't sent along
+        let req = TestRequest::post()
+            .uri(callback.path())
+            .insert_header(header::ContentType::form_url_encoded())
+            .set_payload(callback.query().unwrap_or_default().to_string())
+            .to_request();
+        let res = call_service(&app, req).await;
+        let redirect = location(&res)?.to_string();
+        assert_eq!(redirect, path_and_query(&callback));
+        assert!(res.response().cookies().next().is_none());
+
+        // the redirect back to the callback is same-site and carries it again
+        let req = TestRequest::get()
+            .uri(&redirect)
+            .cookie(cookie)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(location(&res)?, "/welcome");
+
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/status")
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let status: serde_json::Value =
+            actix_web::test::read_body_json(call_service(&app, req).await).await;
+        assert_eq!(
+            status,
+            serde_json::json!({ "state": "authenticated", "steam_id": STEAM_ID.0 })
+        );
+
+        Ok(())
+    }
+
+    /// Replace the `state` query param of `url`, remove it if `state` is `None`
+    fn with_state(url: &reqwest::Url, state: Option<&str>) -> reqwest::Url {
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != "state")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .chain(state.map(|state| ("state".to_string(), state.to_string())))
+            .collect();
+        let mut url = url.clone();
+        url.query_pairs_mut().clear().extend_pairs(query);
+        url
+    }
+
+    #[actix_web::test]
+    async fn callback_without_session_state_is_rejected() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let data = web::Data::new(State::for_test(provider)?);
+        let app = test_app!(web::Data::clone(&data));
+
+        // genuine assertions, but without the state of the session that started the login
+        for state in [None, Some("forged")] {
+            let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+            let res = call_service(&app, req).await;
+            let callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+
+            let req = TestRequest::get()
+                .uri(&path_and_query(&with_state(&callback, state)))
+                .cookie(session_cookie(&res)?)
+                .to_request();
+            let res = call_service(&app, req).await;
+            assert_eq!(
+                error_status_and_code(res).await?,
+                (StatusCode::UNAUTHORIZED, Some("state_mismatch".to_string()))
+            );
+            assert_eq!(data.steam.nonces.len(), 0);
+        }
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn verify_against_configured_endpoint() -> anyhow::Result<()> {
+        use crate::openid::{make_auth_req_url, Realm, ReturnTo};
+
+        let op = MockOp::start().await;
+        let client = reqwest::Client::new();
+
+        // the mock op only speaks http, so only its endpoint is swapped in
+        let mut provider = Provider::from_endpoint("https://steamcommunity.com/openid/login")?;
+        provider.service.endpoint = op.endpoint();
+
+        let realm = Realm::parse("http://localhost:8080")?;
+        let return_to = ReturnTo::parse(&realm, "http://localhost:8080/cb")?;
+        let auth_url = make_auth_req_url(&provider, &ClaimedId::Select, &realm, &return_to)?;
+        let callback = op.positive_assertion(&auth_url, STEAM_ID)?;
+        let assertion: PositiveAssertion =
+            serde_urlencoded::from_str(callback.query().unwrap_or_default())?;
+
+        assertion.validate(&provider)?;
+        let response = verify_against_provider(&client, &provider, &assertion).await?;
+        assert!(response.is_valid());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn verify_steam_query_with_mock_op() -> anyhow::Result<()> {
+        use crate::openid::{make_auth_req_url, verify_steam_query, Realm, ReturnTo};
+
+        let client = reqwest::Client::new();
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&client, &op.identifier()).await?;
+        let realm = Realm::parse("http://localhost:8080")?;
+        let return_to = ReturnTo::parse(&realm, "http://localhost:8080/api/auth/steam/callback")?;
+        let auth_url = make_auth_req_url(&provider, &ClaimedId::Select, &realm, &return_to)?;
+
+        let callback = op.positive_assertion(&auth_url, STEAM_ID)?;
+        let query = callback.query().unwrap_or_default();
+        assert_eq!(
+            verify_steam_query(&client, &provider, query).await?,
+            STEAM_ID
+        );
+
+        let forged = query.replace("76561198181282063", "76561197960287930");
+        assert!(verify_steam_query(&client, &provider, &forged)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn forged_assertion_is_rejected() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+
+        // the mock OP signed the assertion for someone else
+        let callback = op.positive_assertion(location(&res)?, SteamId(76561197960287930))?;
+        let forged = path_and_query(&callback).replace("76561197960287930", "76561198181282063");
+        let req = TestRequest::get()
+            .uri(&forged)
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (
+                StatusCode::UNAUTHORIZED,
+                Some("assertion_rejected".to_string())
+            )
+        );
+
+        // only the path that logs the rejected assertion counts it
+        let req = TestRequest::get().uri("/api/health/metrics").to_request();
+        let metrics = actix_web::test::call_and_read_body(&app, req).await;
+        assert!(std::str::from_utf8(&metrics)?.contains("complainer_verify_invalid_total 1\n"));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn unreachable_provider_is_bad_gateway() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+        op.go_down().await;
+
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (
+                StatusCode::BAD_GATEWAY,
+                Some("provider_unreachable".to_string())
+            )
+        );
+
+        Ok(())
+    }
+
+    /// Log in at the mock OP while it is down, with `association` stored
+    async fn login_with_association(
+        association: impl FnOnce(&MockOp) -> Association,
+    ) -> anyhow::Result<StatusCode> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let state = State::for_test(provider)?;
+        state.steam.associations.insert(association(&op));
+        let app = test_app!(web::Data::new(state));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+        op.go_down().await;
+
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        Ok(call_service(&app, req).await.status())
+    }
+
+    #[actix_web::test]
+    async fn stored_association_skips_provider() -> anyhow::Result<()> {
+        let status = login_with_association(MockOp::association).await?;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+
+        // the assertion is signed with another handle, so steam is asked after all
+        let status = login_with_association(|op| Association {
+            handle: "other".to_string(),
+            ..op.association()
+        })
+        .await?;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+        // a wrong key means the signature is forged, no need to ask steam
+        let status = login_with_association(|op| Association {
+            mac_key: "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+            ..op.association()
+        })
+        .await?;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn renewed_association_skips_provider() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let state = State::for_test(provider)?;
+        crate::server::renew_association(&state).await;
+        assert_eq!(
+            state
+                .steam
+                .associations
+                .current()
+                .map(|association| association.handle),
+            Some(op.association().handle)
+        );
+        let app = test_app!(web::Data::new(state));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let auth_url = reqwest::Url::parse(location(&res)?)?;
+        assert!(auth_url.query_pairs().any(
+            |(key, value)| key == OPENID_ASSOCIATION_HANDLE && value == op.association().handle
+        ));
+        let callback = op.positive_assertion(auth_url.as_str(), STEAM_ID)?;
+        op.go_down().await;
+
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        assert_eq!(location(&call_service(&app, req).await)?, "/welcome");
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn replayed_assertion_is_rejected() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let state = State::for_test(provider)?;
+        let auth_url = state.steam.open_id.auth_url_with_nonce(
+            &state.steam.provider(),
+            &ClaimedId::Select,
+            "n",
+        )?;
+        let callback = op.positive_assertion(&auth_url, STEAM_ID)?;
+        let assertion: PositiveAssertion =
+            serde_urlencoded::from_str(callback.query().unwrap_or_default())?;
+
+        let first = validate_positive_assertion(&assertion, &state).await;
+        assert!(first.is_ok_and(|response| response.is_valid()));
+        let replayed = validate_positive_assertion(&assertion, &state).await;
+        assert_eq!(
+            replayed.err().and_then(|err| err.code()),
+            Some("assertion_replayed")
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn oversized_assertion_is_rejected_before_verification() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let app = test_app!(web::Data::new(State::for_test(provider)?));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let mut callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+        // the mock OP would reject the signature with a 401 if it was asked
+        let pairs: Vec<(String, String)> = callback
+            .query_pairs()
+            .map(|(key, value)| match key.as_ref() {
+                "openid.sig" => (key.into_owned(), "A".repeat(2048)),
+                _ => (key.into_owned(), value.into_owned()),
+            })
+            .collect();
+        callback.query_pairs_mut().clear().extend_pairs(pairs);
+
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (
+                StatusCode::BAD_REQUEST,
+                Some("invalid_assertion".to_string())
+            )
+        );
+
+        let req = TestRequest::get()
+            .uri(&format!(
+                "/api/auth/steam/callback?custom_nonce={}",
+                "a".repeat(16 * 1024)
+            ))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (StatusCode::BAD_REQUEST, Some("query_too_long".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn malformed_callback_has_error_body() -> anyhow::Result<()> {
+        let app = test_app!(web::Data::new(State::for_test(Provider::steam())?));
+
+        // the assertion is missing
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/callback?custom_nonce=steam.x")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (StatusCode::BAD_REQUEST, Some("invalid_query".to_string()))
+        );
+
+        let uri = format!(
+            "/api/auth/steam/callback?custom_nonce=steam.x&{}{}",
+            ASSERTION_QUERY,
+            "&openid.ext=1".repeat(64)
+        );
+        let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (
+                StatusCode::BAD_REQUEST,
+                Some("query_too_complex".to_string())
+            )
+        );
+
+        let req = TestRequest::post()
+            .uri("/api/auth/steam/callback")
+            .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+            .set_payload(format!(
+                "custom_nonce=steam.x&{}&openid.ext={}",
+                ASSERTION_QUERY,
+                "a".repeat(MAX_FORM_LEN)
+            ))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Some("form_too_large".to_string())
+            )
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn steam_id_not_on_allowlist_is_forbidden() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let mut state = State::for_test(provider)?;
+        state.steam.allowlist =
+            crate::state::SteamIdAllowlist::from_value(Some("76561197960287930"))?;
+        let app = test_app!(web::Data::new(state));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            error_status_and_code(res).await?,
+            (StatusCode::FORBIDDEN, Some("not_allowed".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn minimal_callback_response() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let provider = Provider::from_url(&reqwest::Client::new(), &op.identifier()).await?;
+        let mut state = State::for_test(provider)?;
+        state.steam.callback_response = CallbackResponseMode::Minimal;
+        let app = test_app!(web::Data::new(state));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let callback = op.positive_assertion(location(&res)?, STEAM_ID)?;
+        let req = TestRequest::get()
+            .uri(&path_and_query(&callback))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "steam_id": STEAM_ID.0, "authenticated": true })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn full_callback_response_echoes_assertion() -> anyhow::Result<()> {
+        let query: CallbackQuery =
+            serde_urlencoded::from_str(&format!("custom_nonce=steam.x&{}", ASSERTION_QUERY))?;
+        let response: VerifyResponse = serde_json::from_value(
+            serde_json::json!({ "ns": "http://specs.openid.net/auth/2.0", "is_valid": true }),
+        )?;
+
+        let minimal = serde_json::to_value(CallbackResponse::minimal(STEAM_ID))?;
+        assert!(minimal.get("assertion").is_none());
+        assert!(minimal.get("response").is_none());
+
+        let full = serde_json::to_value(CallbackResponse::full(STEAM_ID, &response, &query))?;
+        assert_eq!(full["custom_nonce"], "steam.x");
+        let assertion = &full["assertion"];
+        assert_eq!(assertion["openid.claimed_id"], STEAM_IDENTITY_URL);
+        assert!(assertion["openid.response_nonce"].is_string());
+        for key in ["openid.sig", "openid.assoc_handle", "openid.signed"] {
+            assert!(assertion.get(key).is_none(), "{} was echoed", key);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_assertion_summary_omits_signature() -> anyhow::Result<()> {
+        let assertion: PositiveAssertion = serde_urlencoded::from_str(ASSERTION_QUERY)?;
+        let summary = rejected_assertion_summary(&assertion);
+        assert_eq!(
+            summary,
+            format!(
+                "claimed_id Some({:?}), op_endpoint \"https://steamcommunity.com/openid/login\", \
+                 assoc_handle \"1234567890\", \
+                 signed \"signed,op_endpoint,claimed_id,identity,return_to,response_nonce,assoc_handle\"",
+                STEAM_IDENTITY_URL
+            )
+        );
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn callback_without_login_redirects_to_login() -> anyhow::Result<()> {
+        let app = test_app!(web::Data::new(State::for_test(Provider::steam())?));
+
+        let uri = format!(
+            "/api/auth/steam/callback?custom_nonce=steam.x&{}",
+            ASSERTION_QUERY
+        );
+        let res = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(location(&res)?, "/api/auth/steam/login");
+
+        Ok(())
+    }
+
+    /// `custom_nonce` of the `return_to` of `auth_url`
+    fn return_to_nonce(auth_url: &str) -> anyhow::Result<String> {
+        let auth_url = reqwest::Url::parse(auth_url)?;
+        let (_, return_to) = auth_url
+            .query_pairs()
+            .find(|(key, _)| key == "openid.return_to")
+            .context("auth url is missing return_to")?;
+        let return_to = reqwest::Url::parse(&return_to)?;
+        let (_, nonce) = return_to
+            .query_pairs()
+            .find(|(key, _)| key == "custom_nonce")
+            .context("return_to is missing the nonce")?;
+        Ok(nonce.into_owned())
+    }
+
+    #[actix_web::test]
+    async fn refresh_nonce_replaces_stored_nonce() -> anyhow::Result<()> {
+        let data = web::Data::new(State::for_test(Provider::steam())?);
+        let app = test_app!(web::Data::clone(&data));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let old = return_to_nonce(location(&res)?)?;
+
+        let req = TestRequest::post()
+            .uri("/api/auth/steam/refresh-nonce")
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let cookie = session_cookie(&res)?;
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        let auth_url = body["auth_url"].as_str().context("auth_url is missing")?;
+        let new = return_to_nonce(auth_url)?;
+        assert_ne!(old, new);
+        assert!(data.steam.nonces.validate(&old).is_err());
+        assert!(data.steam.nonces.validate(&new).is_ok());
+
+        // the session holds the new nonce, so it is replaced instead of adding another one
+        let req = TestRequest::post()
+            .uri("/api/auth/steam/refresh-nonce")
+            .cookie(cookie)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(data.steam.nonces.len(), 1);
+        assert!(data.steam.nonces.validate(&new).is_err());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn refresh_nonce_requires_pending_login() -> anyhow::Result<()> {
+        let app = test_app!(web::Data::new(State::for_test(Provider::steam())?));
+
+        let req = TestRequest::post()
+            .uri("/api/auth/steam/refresh-nonce")
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+
+        let req = TestRequest::get().uri("/test/authenticate").to_request();
+        let res = call_service(&app, req).await;
+        let req = TestRequest::post()
+            .uri("/api/auth/steam/refresh-nonce")
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::CONFLICT);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn authenticated_user_is_sent_home() -> anyhow::Result<()> {
+        let app = test_app!(web::Data::new(State::for_test(Provider::steam())?));
+
+        let req = TestRequest::get().uri("/test/authenticate").to_request();
+        let res = call_service(&app, req).await;
+        let cookie = session_cookie(&res)?;
+
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/login")
+            .cookie(cookie.clone())
+            .to_request();
+        assert_eq!(location(&call_service(&app, req).await)?, "/welcome");
+
+        let uri = format!(
+            "/api/auth/steam/callback?custom_nonce=steam.x&{}",
+            ASSERTION_QUERY
+        );
+        let req = TestRequest::get()
+            .uri(&uri)
+            .cookie(cookie.clone())
+            .to_request();
+        assert_eq!(location(&call_service(&app, req).await)?, "/welcome");
+
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/logout")
+            .cookie(cookie)
+            .to_request();
+        assert_eq!(location(&call_service(&app, req).await)?, "/goodbye");
+
+        Ok(())
+    }
+
+    /// Status and `code` of an error response, which must have the standard error json body
+    async fn error_status_and_code<B>(
+        res: ServiceResponse<B>,
+    ) -> anyhow::Result<(StatusCode, Option<String>)>
+    where
+        B: actix_web::body::MessageBody,
+    {
+        let status = res.status();
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert!(body["error_chain"]
+            .as_array()
+            .is_some_and(|chain| !chain.is_empty()));
+        assert_eq!(
+            body["status_cat"],
+            format!("https://http.cat/{}", status.as_u16())
+        );
+        Ok((status, body["code"].as_str().map(str::to_string)))
+    }
+
+    /// Start a login and send the callback with the query built by `callback_query`
+    /// from the nonce of the login, returns the status and code of the error and the nonces left
+    ///
+    /// The state of the session is sent along, so only the nonce and the assertion can fail.
+    async fn callback_after_login(
+        callback_query: impl FnOnce(&str) -> String,
+    ) -> anyhow::Result<(StatusCode, Option<String>, usize)> {
+        let data = web::Data::new(State::for_test(Provider::steam())?);
+        let app = test_app!(web::Data::clone(&data));
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        let auth_url = reqwest::Url::parse(location(&res)?)?;
+        let (_, return_to) = auth_url
+            .query_pairs()
+            .find(|(key, _)| key == "openid.return_to")
+            .context("auth url is missing return_to")?;
+        let return_to = reqwest::Url::parse(&return_to)?;
+        let (_, nonce) = return_to
+            .query_pairs()
+            .find(|(key, _)| key == "custom_nonce")
+            .context("return_to is missing the nonce")?;
+        let (_, csrf_state) = return_to
+            .query_pairs()
+            .find(|(key, _)| key == "state")
+            .context("return_to is missing the state")?;
+        assert_eq!(data.steam.nonces.len(), 1);
+
+        let req = TestRequest::get()
+            .uri(&format!(
+                "/api/auth/steam/callback?state={}&{}",
+                csrf_state,
+                callback_query(&nonce)
+            ))
+            .cookie(session_cookie(&res)?)
+            .to_request();
+        let res = call_service(&app, req).await;
+        let (status, code) = error_status_and_code(res).await?;
+        Ok((status, code, data.steam.nonces.len()))
+    }
+
+    /// [`ASSERTION_QUERY`] returning to the callback with `nonce`
+    fn assertion_query_with_nonce(nonce: &str) -> String {
+        let return_to = "http%3A%2F%2Flocalhost%3A8080%2Fapi%2Fauth%2Fsteam%2Fcallback";
+        ASSERTION_QUERY.replace(
+            return_to,
+            &format!("{}%3Fcustom_nonce%3D{}", return_to, nonce),
+        )
+    }
+
+    #[actix_web::test]
+    async fn failed_callbacks_consume_nonce() -> anyhow::Result<()> {
+        let failed = |status, code: &str| (status, Some(code.to_string()), 0);
+
+        // query param nonce doesn't match the session
+        let outcome =
+            callback_after_login(|_| format!("custom_nonce=steam.x&{}", ASSERTION_QUERY)).await?;
+        assert_eq!(outcome, failed(StatusCode::UNAUTHORIZED, "nonce_mismatch"));
+
+        // return_to doesn't carry the nonce
+        let outcome =
+            callback_after_login(|nonce| format!("custom_nonce={}&{}", nonce, ASSERTION_QUERY))
+                .await?;
+        assert_eq!(
+            outcome,
+            failed(StatusCode::BAD_REQUEST, "return_to_mismatch")
+        );
+
+        // claimed id isn't a steam id
+        let outcome = callback_after_login(|nonce| {
+            format!(
+                "custom_nonce={}&{}",
+                nonce,
+                assertion_query_with_nonce(nonce).replace("%2Fid%2F", "%2Fuser%2F")
+            )
+        })
+        .await?;
+        assert_eq!(
+            outcome,
+            failed(StatusCode::BAD_REQUEST, "invalid_claimed_id")
+        );
+
+        // the response nonce of the assertion is way too old
+        let outcome = callback_after_login(|nonce| {
+            format!(
+                "custom_nonce={}&{}",
+                nonce,
+                assertion_query_with_nonce(nonce)
+            )
+        })
+        .await?;
+        assert_eq!(
+            outcome,
+            failed(StatusCode::BAD_REQUEST, "invalid_assertion")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unrecognized_params_are_captured() -> anyhow::Result<()> {
+        let query = format!(
+            "custom_nonce=steam.x&{}&openid.ns.ext1=http%3A%2F%2Fexample.com&utm_source=steam",
+            ASSERTION_QUERY
+        );
+        let query: CallbackQuery = serde_urlencoded::from_str(&query)?;
+        assert_eq!(query.custom_nonce, "steam.x");
+        assert_eq!(query.assertion.claimed_id(), Some(STEAM_IDENTITY_URL));
+        assert_eq!(
+            query
+                .assertion
+                .extensions()
+                .get("openid.ns.ext1")
+                .map(String::as_str),
+            Some("http://example.com")
+        );
+        assert_eq!(
+            query.unrecognized,
+            HashMap::from([
+                (
+                    "openid.ns.ext1".to_string(),
+                    "http://example.com".to_string()
+                ),
+                ("utm_source".to_string(), "steam".to_string()),
+            ])
+        );
+
+        let query: CallbackQuery =
+            serde_urlencoded::from_str(&format!("custom_nonce=steam.x&{}", ASSERTION_QUERY))?;
+        assert!(query.unrecognized.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn return_to_nonce_must_match() -> anyhow::Result<()> {
+        let with_nonce = |nonce: &str| -> anyhow::Result<PositiveAssertion> {
+            Ok(serde_urlencoded::from_str(&assertion_query_with_nonce(
+                nonce,
+            ))?)
+        };
+
+        let realm = Realm::parse("http://localhost:8080")?;
+        let return_to = ReturnTo::parse(&realm, "http://localhost:8080/api/auth/steam/callback")?;
+
+        ensure_return_to(&with_nonce("steam.a")?, &return_to, "steam.a")?;
+        assert!(ensure_return_to(&with_nonce("steam.b")?, &return_to, "steam.a").is_err());
+        let without_nonce: PositiveAssertion = serde_urlencoded::from_str(ASSERTION_QUERY)?;
+        assert!(ensure_return_to(&without_nonce, &return_to, "steam.a").is_err());
+
+        let other_return_to = ReturnTo::parse(&realm, "http://localhost:8080/api/other")?;
+        assert!(ensure_return_to(&with_nonce("steam.a")?, &other_return_to, "steam.a").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn status_response_shape() -> anyhow::Result<()> {
+        let authenticated = SteamAuthState::Authenticated {
+            id: STEAM_ID,
+            authenticated_at: Utc::now(),
+            session_version: 0,
+        };
+        assert_eq!(
+            serde_json::to_value(StatusResponse::from(Some(&authenticated)))?,
+            serde_json::json!({ "state": "authenticated", "steam_id": 76561198181282063u64 })
+        );
+        assert_eq!(
+            serde_json::to_value(StatusResponse::from(None))?,
+            serde_json::json!({ "state": "anonymous" })
+        );
+        Ok(())
+    }
 }
@@ -1,17 +1,179 @@
+use std::time::Duration;
+
 use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 
+#[cfg(feature = "debug-endpoints")]
 use super::session::AuthSession;
-use crate::error::{AppResult, IntoAppError};
+use crate::error::AppResult;
+#[cfg(feature = "debug-endpoints")]
+use crate::error::IntoAppError;
+#[cfg(feature = "debug-endpoints")]
+use crate::openid::Provider;
+use crate::state::{State, SteamState};
+use crate::util::metrics::{nonce_sets_to_prometheus, PROMETHEUS_CONTENT_TYPE};
+use crate::util::nonce::NonceSet;
+use crate::util::redis;
+
+/// A readiness probe shouldn't hang on an unresponsive redis
+const REDIS_PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A dependency that failed its readiness check
+#[derive(Debug, Serialize)]
+struct FailedCheck {
+    dependency: &'static str,
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    failed: Vec<FailedCheck>,
+}
+
+impl Readiness {
+    fn from_checks(
+        checks: impl IntoIterator<Item = (&'static str, anyhow::Result<()>)>,
+    ) -> Readiness {
+        let failed: Vec<_> = checks
+            .into_iter()
+            .filter_map(|(dependency, result)| {
+                result.err().map(|err| FailedCheck {
+                    dependency,
+                    reason: format!("{:#}", err),
+                })
+            })
+            .collect();
+        Readiness {
+            ready: failed.is_empty(),
+            failed,
+        }
+    }
+}
+
+/// Steam is rediscovered in the background and the last provider is kept if that fails,
+/// so this only checks that discovery produced a usable endpoint
+fn check_discovery(steam: &SteamState) -> anyhow::Result<()> {
+    reqwest::Url::parse(steam.provider().endpoint())
+        .context("discovered endpoint is not a valid url")?;
+    Ok(())
+}
+
+fn check_api_key(steam: &SteamState) -> anyhow::Result<()> {
+    if !steam.has_api_key {
+        anyhow::bail!("STEAM_API_KEY is empty");
+    }
+    Ok(())
+}
 
 pub(crate) async fn health_live() -> AppResult<HttpResponse> {
     Ok(HttpResponse::Ok().body("LIVE"))
 }
 
-pub(crate) async fn health_ready() -> AppResult<HttpResponse> {
-    Ok(HttpResponse::Ok().body("READY"))
+/// Check the dependencies needed to serve logins,
+/// responds with 503 and the failed checks if any of them is unavailable
+pub(crate) async fn health_ready(data: web::Data<State>) -> AppResult<HttpResponse> {
+    let redis = redis::ping(&data.redis_url, REDIS_PING_TIMEOUT).await;
+    let readiness = Readiness::from_checks([
+        ("redis", redis),
+        ("steam_openid", check_discovery(&data.steam)),
+        ("steam_api", check_api_key(&data.steam)),
+    ]);
+
+    if !readiness.ready {
+        log::warn!("not ready: {:?}", readiness.failed);
+        return Ok(HttpResponse::ServiceUnavailable().json(readiness));
+    }
+    Ok(HttpResponse::Ok().json(readiness))
+}
+
+#[derive(Debug, Serialize)]
+struct CacheStats {
+    entries: usize,
+    /// `None` if the cache doesn't count lookups
+    hits: Option<u64>,
+    /// `None` if the cache doesn't count lookups
+    misses: Option<u64>,
+    oldest_entry_age_secs: Option<i64>,
+}
+
+impl CacheStats {
+    fn nonces(nonces: &NonceSet) -> CacheStats {
+        CacheStats {
+            entries: nonces.len(),
+            hits: Some(nonces.hits()),
+            misses: Some(nonces.misses()),
+            oldest_entry_age_secs: nonces.oldest_age().map(|age| age.num_seconds()),
+        }
+    }
+    /// The provider is rediscovered periodically and always served from memory
+    fn provider(discovered_at: DateTime<Utc>, now: DateTime<Utc>) -> CacheStats {
+        CacheStats {
+            entries: 1,
+            hits: None,
+            misses: None,
+            oldest_entry_age_secs: Some(now.signed_duration_since(discovered_at).num_seconds()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Caches {
+    provider: CacheStats,
+    nonces: CacheStats,
+}
+
+/// Let operators see how full the caches are and how often they are hit
+pub(crate) async fn health_caches(data: web::Data<State>) -> AppResult<HttpResponse> {
+    let caches = Caches {
+        provider: CacheStats::provider(data.steam.discovered_at(), Utc::now()),
+        nonces: CacheStats::nonces(&data.steam.nonces),
+    };
+    Ok(HttpResponse::Ok().json(caches))
+}
+
+/// Login funnel counters and nonce set stats for scraping by Prometheus
+pub(crate) async fn health_metrics(data: web::Data<State>) -> AppResult<HttpResponse> {
+    let mut body = data.metrics.to_prometheus();
+    body.push_str(&nonce_sets_to_prometheus(&[
+        ("steam", data.steam.nonces.stats()),
+        ("generic", data.generic.nonces.stats()),
+    ]));
+    Ok(HttpResponse::Ok()
+        .content_type(PROMETHEUS_CONTENT_TYPE)
+        .body(body))
+}
+
+/// The steam provider in use and how old its discovery is
+#[cfg(feature = "debug-endpoints")]
+#[derive(Debug, Serialize)]
+struct ProviderHealth<'a> {
+    op_endpoint: &'a str,
+    discovered_at: DateTime<Utc>,
+    age_secs: i64,
+    provider: &'a Provider,
+}
+
+/// Let operators check which steam endpoint is used and when steam last confirmed it
+#[cfg(feature = "debug-endpoints")]
+pub(crate) async fn health_provider(data: web::Data<State>) -> AppResult<HttpResponse> {
+    let provider = data.steam.provider();
+    let discovered_at = data.steam.discovered_at();
+    let health = ProviderHealth {
+        op_endpoint: provider.endpoint(),
+        discovered_at,
+        age_secs: Utc::now()
+            .signed_duration_since(discovered_at)
+            .num_seconds(),
+        provider: &provider,
+    };
+    Ok(HttpResponse::Ok().json(health))
 }
 
 /// Provide an example for an error response
+#[cfg(feature = "debug-endpoints")]
 pub(crate) async fn health_error() -> AppResult<HttpResponse> {
     Err(anyhow::anyhow!("stubbed toe 😖")
         .context("lost focus 😵")
@@ -21,14 +183,101 @@ pub(crate) async fn health_error() -> AppResult<HttpResponse> {
 }
 
 /// Let the user view the encrypted cookies
-pub(crate) async fn health_cookies(session: actix_session::Session) -> AppResult<HttpResponse> {
-    let auth_state = session.steam_auth_state()?;
+///
+/// This dumps the whole session state, never enable it in production!
+#[cfg(feature = "debug-endpoints")]
+pub(crate) async fn health_cookies(
+    session: actix_session::Session,
+    data: web::Data<State>,
+) -> AppResult<HttpResponse> {
+    let auth_state = session.steam_auth_state(&data)?;
     Ok(HttpResponse::Ok().json(&auth_state))
 }
 
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(web::resource("/live").route(web::get().to(health_live)))
         .service(web::resource("/ready").route(web::get().to(health_ready)))
-        .service(web::resource("/error").route(web::get().to(health_error)))
-        .service(web::resource("/cookies").route(web::get().to(health_cookies)));
+        .service(web::resource("/caches").route(web::get().to(health_caches)))
+        .service(web::resource("/metrics").route(web::get().to(health_metrics)));
+
+    #[cfg(feature = "debug-endpoints")]
+    cfg.service(web::resource("/error").route(web::get().to(health_error)))
+        .service(web::resource("/cookies").route(web::get().to(health_cookies)))
+        .service(web::resource("/provider").route(web::get().to(health_provider)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn readiness_lists_failed_dependencies() -> anyhow::Result<()> {
+        let readiness = Readiness::from_checks([
+            ("redis", Err(anyhow::anyhow!("connection refused"))),
+            ("steam_openid", Ok(())),
+            ("steam_api", Ok(())),
+        ]);
+        assert_eq!(
+            serde_json::to_value(readiness)?,
+            serde_json::json!({
+                "ready": false,
+                "failed": [{ "dependency": "redis", "reason": "connection refused" }],
+            })
+        );
+
+        let readiness = Readiness::from_checks([("redis", Ok(()))]);
+        assert!(readiness.ready);
+        assert!(readiness.failed.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_stats() -> anyhow::Result<()> {
+        let discovered_at = Utc::now();
+        let now = discovered_at + chrono::Duration::seconds(90);
+        assert_eq!(
+            serde_json::to_value(CacheStats::provider(discovered_at, now))?,
+            serde_json::json!({
+                "entries": 1,
+                "hits": null,
+                "misses": null,
+                "oldest_entry_age_secs": 90,
+            })
+        );
+
+        let nonces = NonceSet::new("test");
+        assert!(CacheStats::nonces(&nonces).oldest_entry_age_secs.is_none());
+
+        let nonce = nonces.insert_new();
+        nonces.insert_new();
+        nonces.validate_and_remove(nonce.as_str())?;
+        let stats = CacheStats::nonces(&nonces);
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.hits, Some(1));
+        assert_eq!(stats.misses, Some(0));
+        assert!(stats.oldest_entry_age_secs.is_some());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "debug-endpoints")]
+    #[actix_web::test]
+    async fn provider_reports_steam_endpoint() -> anyhow::Result<()> {
+        use actix_web::test::{call_and_read_body_json, init_service, TestRequest};
+
+        let data = web::Data::new(State::for_test(Provider::steam())?);
+        let app = init_service(actix_web::App::new().app_data(data).configure(configure)).await;
+
+        let req = TestRequest::get().uri("/provider").to_request();
+        let health: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            health["op_endpoint"],
+            serde_json::json!(Provider::steam().endpoint())
+        );
+        assert_eq!(health["provider"], serde_json::to_value(Provider::steam())?);
+        assert_eq!(health["age_secs"], 0);
+
+        Ok(())
+    }
 }
@@ -1,12 +1,83 @@
-use actix_web::web;
+use actix_web::http::header;
+use actix_web::{middleware, web};
+
+use crate::util::query_limit::limit_query;
+use crate::util::rate_limit::rate_limit;
 
 mod auth;
 mod health;
-mod session;
+pub(crate) mod session;
 mod steam;
 
+/// Responses that depend on the session must not be stored by shared caches,
+/// they'd serve the profile of one user to another
+fn no_store() -> middleware::DefaultHeaders {
+    middleware::DefaultHeaders::new()
+        .add((header::CACHE_CONTROL, "no-store"))
+        .add((header::PRAGMA, "no-cache"))
+}
+
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::scope("/auth").configure(auth::configure))
-        .service(web::scope("/health").configure(health::configure))
-        .service(web::scope("/steam").configure(steam::configure));
+    cfg.service(
+        web::scope("/auth")
+            .wrap_fn(limit_query)
+            .wrap_fn(rate_limit)
+            .wrap(no_store())
+            .configure(auth::configure),
+    )
+    .service(web::scope("/health").configure(health::configure))
+    .service(
+        web::scope("/steam")
+            .wrap(no_store())
+            .configure(steam::configure),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use crate::openid::Provider;
+    use actix_web::cookie::Key;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+    use crate::state::State;
+
+    #[actix_web::test]
+    async fn session_dependent_responses_are_not_stored() -> anyhow::Result<()> {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(State::for_test(Provider::steam())?))
+                .wrap(crate::server::create_cookie_session_mw(Key::generate()))
+                .service(web::scope("/api").configure(configure)),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/api/steam/player-summaries?steam_ids=76561198181282063")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+        assert_eq!(res.headers().get(header::PRAGMA).unwrap(), "no-cache");
+
+        let req = TestRequest::get()
+            .uri("/api/auth/steam/status")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+
+        let req = TestRequest::get().uri("/api/health/live").to_request();
+        let res = call_service(&app, req).await;
+        assert!(res.headers().get(header::CACHE_CONTROL).is_none());
+
+        Ok(())
+    }
 }
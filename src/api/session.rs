@@ -1,87 +1,378 @@
+use std::str::FromStr;
+
 use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use steam_api_concurrent::SteamId;
 
+use crate::state::State;
 use crate::util::nonce::Nonce;
-use crate::State;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Key under which [`SteamAuthState`] is stored in the session
+const STEAM_AUTH_STATE_KEY: &str = "steam-auth-state";
+
+/// Sessions older than this are treated as logged out,
+/// regardless of how long the cookie or the session store keeps them.
+const MAX_SESSION_AGE_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Version of the [`SteamAuthState`] layout written to new sessions.
+///
+/// Sessions written before the version was stored are version `0`,
+/// bump this whenever a migration in [`StoredSteamAuthState::migrate`] is needed.
+const STEAM_AUTH_STATE_VERSION: u32 = 1;
+
+/// Random bytes of [`SteamAuthState::Redirected::csrf_state`]
+const CSRF_STATE_BYTES: usize = 32;
+
+/// Sessions created before `authenticated_at` was added to [`SteamAuthState`]
+/// don't have it, treat them as expired instead of failing to parse.
+const fn expired_timestamp() -> DateTime<Utc> {
+    DateTime::<Utc>::MIN_UTC
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub(crate) enum SteamAuthState {
-    Redirected { nonce: Nonce },
-    Authenticated { id: SteamId },
-}
-
-pub(crate) trait AuthSession {
-    fn steam_auth_state(&self) -> anyhow::Result<Option<SteamAuthState>>;
-    fn redirected(&self) -> Option<Nonce>;
-    fn replace_session(&self, state: &State) -> anyhow::Result<Nonce>;
-    fn authenticated(&self) -> Option<SteamId>;
-    fn validate_replace_nonce(&self, state: &State, old: &str) -> anyhow::Result<Nonce>;
-    fn insert_new_nonce(&self, state: &State) -> anyhow::Result<Nonce>;
-    fn authenticate(&self, steam_id: SteamId) -> anyhow::Result<()>;
-    fn logout(&self) -> anyhow::Result<SteamId>;
+    Redirected {
+        nonce: Nonce,
+        /// Appended to `return_to` as `state`, the callback must carry the one of its session.
+        ///
+        /// Sessions redirected before it was added don't have it and can't complete the login.
+        #[serde(default)]
+        csrf_state: Option<String>,
+    },
+    Authenticated {
+        id: SteamId,
+        #[serde(default = "expired_timestamp")]
+        authenticated_at: DateTime<Utc>,
+        /// See [`State::session_version`]
+        #[serde(default)]
+        session_version: u32,
+    },
 }
 
-// TODO: Clean this up
-impl AuthSession for actix_session::Session {
-    fn authenticated(&self) -> Option<SteamId> {
-        let state = self.steam_auth_state().ok().flatten()?;
-        match state {
+impl SteamAuthState {
+    /// Whether an authenticated session is older than [`MAX_SESSION_AGE_SECS`]
+    pub(crate) fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match self {
+            SteamAuthState::Redirected { .. } => false,
+            SteamAuthState::Authenticated {
+                authenticated_at, ..
+            } => now.signed_duration_since(*authenticated_at) > max_session_age(),
+        }
+    }
+    /// Whether an authenticated session was issued for another session version
+    pub(crate) const fn is_stale(&self, current_version: u32) -> bool {
+        match self {
+            SteamAuthState::Redirected { .. } => false,
+            SteamAuthState::Authenticated {
+                session_version, ..
+            } => *session_version != current_version,
+        }
+    }
+    /// The steam id, if the user is authenticated
+    pub(crate) const fn steam_id(&self) -> Option<SteamId> {
+        match self {
             SteamAuthState::Redirected { .. } => None,
-            SteamAuthState::Authenticated { id } => Some(id),
+            SteamAuthState::Authenticated { id, .. } => Some(*id),
         }
     }
-    fn redirected(&self) -> Option<Nonce> {
-        let state = self.steam_auth_state().ok().flatten()?;
-        match state {
-            SteamAuthState::Redirected { nonce } => Some(nonce),
+    /// The nonce, if the user has been redirected to steam
+    pub(crate) fn into_nonce(self) -> Option<Nonce> {
+        match self {
+            SteamAuthState::Redirected { nonce, .. } => Some(nonce),
             SteamAuthState::Authenticated { .. } => None,
         }
     }
-    fn replace_session(&self, state: &State) -> anyhow::Result<Nonce> {
-        let nonces = &state.steam.nonces;
-        let nonce = nonces.insert_new();
+}
+
+/// [`SteamAuthState`] as it is stored in the session, tagged with the layout version
+///
+/// Fields added to [`SteamAuthState`] later on must have a serde default,
+/// so sessions written by an older version still deserialize.
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredSteamAuthState {
+    #[serde(default)]
+    version: u32,
+    #[serde(flatten)]
+    state: SteamAuthState,
+}
+
+impl StoredSteamAuthState {
+    const fn current(state: SteamAuthState) -> StoredSteamAuthState {
+        StoredSteamAuthState {
+            version: STEAM_AUTH_STATE_VERSION,
+            state,
+        }
+    }
+    /// Bring a state written by an older version up to date
+    fn migrate(self) -> anyhow::Result<SteamAuthState> {
+        match self.version {
+            // version 0 only lacks fields that have defaults
+            0 | STEAM_AUTH_STATE_VERSION => Ok(self.state),
+            version => anyhow::bail!("unknown steam-auth-state version {}", version),
+        }
+    }
+}
+
+/// Parse the json stored in the session, migrating older layouts
+impl FromStr for SteamAuthState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<SteamAuthState> {
+        serde_json::from_str::<StoredSteamAuthState>(s)
+            .context("couldn't deserialize steam-auth-state")?
+            .migrate()
+    }
+}
+
+/// The nonce and state of a login that has just been started,
+/// both have to come back in the callback
+#[derive(Debug)]
+pub(crate) struct LoginAttempt {
+    pub(crate) nonce: Nonce,
+    pub(crate) csrf_state: String,
+}
+
+impl LoginAttempt {
+    /// Pair `nonce` with a new random state
+    fn new(nonce: Nonce) -> LoginAttempt {
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD as Base64;
+        use base64::Engine;
+        use rand::RngCore;
+
+        let mut bytes = [0u8; CSRF_STATE_BYTES];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        LoginAttempt {
+            nonce,
+            csrf_state: Base64.encode(bytes),
+        }
+    }
+    /// Store the attempt in the session, returns it for building the auth url
+    fn store(self, session: &actix_session::Session) -> anyhow::Result<LoginAttempt> {
         let state = SteamAuthState::Redirected {
-            nonce: nonce.clone(),
+            nonce: self.nonce.clone(),
+            csrf_state: Some(self.csrf_state.clone()),
         };
-        self.insert("steam-auth-state", state)
-            .context("couldn't serialize nonce to json")?;
-        Ok(nonce)
+        store_steam_auth_state(session, state).context("couldn't store nonce")?;
+        Ok(self)
     }
-    fn logout(&self) -> anyhow::Result<SteamId> {
-        let id = self.authenticated().context("not logged in")?;
+}
+
+fn max_session_age() -> Duration {
+    Duration::seconds(MAX_SESSION_AGE_SECS)
+}
+
+/// Load the state and drop it if it is expired or stale
+///
+/// A state that can't be parsed, e.g. after a breaking change of the layout,
+/// is logged and removed instead of failing every request of that user.
+fn load_steam_auth_state(
+    session: &actix_session::Session,
+    session_version: u32,
+) -> Option<SteamAuthState> {
+    let raw = session.entries().get(STEAM_AUTH_STATE_KEY).cloned()?;
+    let state = match raw.parse::<SteamAuthState>() {
+        Ok(state) => state,
+        Err(err) => {
+            log::warn!("dropping undeserializable session state: {:#}", err);
+            session.remove(STEAM_AUTH_STATE_KEY);
+            return None;
+        }
+    };
+    let now = Utc::now();
+    Some(state).filter(|state| !state.is_expired(now) && !state.is_stale(session_version))
+}
+
+/// Store the state tagged with the current layout version
+fn store_steam_auth_state(
+    session: &actix_session::Session,
+    state: SteamAuthState,
+) -> anyhow::Result<()> {
+    session
+        .insert(STEAM_AUTH_STATE_KEY, StoredSteamAuthState::current(state))
+        .context("couldn't serialize steam-auth-state to json")
+}
+
+/// All getters deserialize the session state exactly once.
+///
+/// An expired, stale or undeserializable session is reported as no session at all.
+///
+/// Prefer matching on [`AuthSession::steam_auth_state`] if more than one state is of interest.
+pub(crate) trait AuthSession {
+    fn steam_auth_state(&self, state: &State) -> anyhow::Result<Option<SteamAuthState>>;
+    fn redirected(&self, state: &State) -> anyhow::Result<Option<Nonce>>;
+    fn replace_session(&self, state: &State) -> anyhow::Result<LoginAttempt>;
+    fn authenticated(&self, state: &State) -> anyhow::Result<Option<SteamId>>;
+    fn validate_replace_nonce(&self, state: &State, old: &str) -> anyhow::Result<LoginAttempt>;
+    fn insert_new_nonce(&self, state: &State) -> anyhow::Result<LoginAttempt>;
+    fn authenticate(&self, state: &State, steam_id: SteamId) -> anyhow::Result<()>;
+    fn logout(&self, state: &State) -> anyhow::Result<SteamId>;
+}
+
+impl AuthSession for actix_session::Session {
+    fn authenticated(&self, state: &State) -> anyhow::Result<Option<SteamId>> {
+        let state = self.steam_auth_state(state)?;
+        Ok(state.as_ref().and_then(SteamAuthState::steam_id))
+    }
+    fn redirected(&self, state: &State) -> anyhow::Result<Option<Nonce>> {
+        let state = self.steam_auth_state(state)?;
+        Ok(state.and_then(SteamAuthState::into_nonce))
+    }
+    fn replace_session(&self, state: &State) -> anyhow::Result<LoginAttempt> {
+        self.insert_new_nonce(state)
+    }
+    fn logout(&self, state: &State) -> anyhow::Result<SteamId> {
+        let id = self.authenticated(state)?.context("not logged in")?;
         self.clear();
         Ok(id)
     }
-    fn validate_replace_nonce(&self, state: &State, old: &str) -> anyhow::Result<Nonce> {
+    fn validate_replace_nonce(&self, state: &State, old: &str) -> anyhow::Result<LoginAttempt> {
         let nonces = &state.steam.nonces;
         let nonce = nonces.replace(old).context("couldn't replace old nonce")?;
-        let state = SteamAuthState::Redirected {
-            nonce: nonce.clone(),
-        };
-        self.insert("steam-auth-state", state)
-            .context("couldn't serialize nonce to json")?;
-        Ok(nonce)
+        LoginAttempt::new(nonce).store(self)
     }
-    fn insert_new_nonce(&self, state: &State) -> anyhow::Result<Nonce> {
+    fn insert_new_nonce(&self, state: &State) -> anyhow::Result<LoginAttempt> {
         let nonces = &state.steam.nonces;
-        let nonce = nonces.insert_new();
-        let state = SteamAuthState::Redirected {
-            nonce: nonce.clone(),
+        LoginAttempt::new(nonces.insert_new()).store(self)
+    }
+    fn steam_auth_state(&self, state: &State) -> anyhow::Result<Option<SteamAuthState>> {
+        Ok(load_steam_auth_state(self, state.session_version))
+    }
+    fn authenticate(&self, state: &State, steam_id: SteamId) -> anyhow::Result<()> {
+        let state = SteamAuthState::Authenticated {
+            id: steam_id,
+            authenticated_at: Utc::now(),
+            session_version: state.session_version,
         };
-        self.insert("steam-auth-state", state)
-            .context("couldn't serialize nonce to json")?;
-        Ok(nonce)
-    }
-    fn steam_auth_state(&self) -> anyhow::Result<Option<SteamAuthState>> {
-        self.get::<SteamAuthState>("steam-auth-state")
-            .context("couldn't deserialize steam-auth-state")
-    }
-    fn authenticate(&self, steam_id: SteamId) -> anyhow::Result<()> {
-        let state = SteamAuthState::Authenticated { id: steam_id };
-        self.insert("steam-auth-state", state)
-            .context("couldn't serialize steam id to json")
+        store_steam_auth_state(self, state).context("couldn't store steam id")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_session::SessionExt;
+    use actix_web::test::TestRequest;
+    use anyhow::Context;
+
+    use super::*;
+
+    const STEAM_ID: SteamId = SteamId(76561198181282063);
+
+    fn empty_session() -> actix_session::Session {
+        TestRequest::default().to_srv_request().get_session()
+    }
+
+    fn authenticated(
+        authenticated_at: DateTime<Utc>,
+        session_version: u32,
+    ) -> anyhow::Result<actix_session::Session> {
+        let session = empty_session();
+        let state = SteamAuthState::Authenticated {
+            id: STEAM_ID,
+            authenticated_at,
+            session_version,
+        };
+        store_steam_auth_state(&session, state)?;
+        Ok(session)
+    }
+
+    #[test]
+    fn anonymous_session() {
+        let session = empty_session();
+        assert!(load_steam_auth_state(&session, 0).is_none());
+    }
+
+    #[test]
+    fn authenticated_session() -> anyhow::Result<()> {
+        let session = authenticated(Utc::now(), 0)?;
+
+        let state = load_steam_auth_state(&session, 0).context("state is missing")?;
+        assert_eq!(state.steam_id(), Some(STEAM_ID));
+        assert!(state.into_nonce().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn expired_session_is_logged_out() -> anyhow::Result<()> {
+        let authenticated_at = Utc::now() - max_session_age() - Duration::seconds(1);
+        let session = authenticated(authenticated_at, 0)?;
+        assert!(load_steam_auth_state(&session, 0).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn stale_session_is_logged_out() -> anyhow::Result<()> {
+        let session = authenticated(Utc::now(), 1)?;
+        assert!(load_steam_auth_state(&session, 1).is_some());
+        assert!(load_steam_auth_state(&session, 2).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_session_without_timestamp_is_expired() -> anyhow::Result<()> {
+        let session = empty_session();
+        let legacy = serde_json::json!({ "type": "authenticated", "id": 76561198181282063u64 });
+        session.insert(STEAM_AUTH_STATE_KEY, legacy)?;
+
+        assert!(load_steam_auth_state(&session, 0).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_session_without_version() -> anyhow::Result<()> {
+        let session = empty_session();
+        let legacy = serde_json::json!({
+            "type": "authenticated",
+            "id": 76561198181282063u64,
+            "authenticated_at": Utc::now(),
+        });
+        session.insert(STEAM_AUTH_STATE_KEY, legacy)?;
+
+        let state = load_steam_auth_state(&session, 0).context("state is missing")?;
+        assert_eq!(state.steam_id(), Some(STEAM_ID));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stored_state_is_versioned() -> anyhow::Result<()> {
+        let session = authenticated(Utc::now(), 0)?;
+        let raw = session
+            .entries()
+            .get(STEAM_AUTH_STATE_KEY)
+            .cloned()
+            .context("state is missing")?;
+        let raw: serde_json::Value = serde_json::from_str(&raw)?;
+        assert_eq!(raw["version"], STEAM_AUTH_STATE_VERSION);
+        assert_eq!(raw["type"], "authenticated");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_version_is_logged_out() -> anyhow::Result<()> {
+        let session = empty_session();
+        let future = serde_json::json!({
+            "version": STEAM_AUTH_STATE_VERSION + 1,
+            "type": "authenticated",
+            "id": 76561198181282063u64,
+            "authenticated_at": Utc::now(),
+        });
+        session.insert(STEAM_AUTH_STATE_KEY, future)?;
+        assert!(load_steam_auth_state(&session, 0).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_session_is_logged_out() -> anyhow::Result<()> {
+        let session = empty_session();
+        session.insert(STEAM_AUTH_STATE_KEY, "garbage")?;
+        assert!(load_steam_auth_state(&session, 0).is_none());
+        // the broken state is removed so it isn't logged again
+        assert!(session.entries().get(STEAM_AUTH_STATE_KEY).is_none());
+        Ok(())
     }
 }
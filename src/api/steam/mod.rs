@@ -9,3 +9,55 @@ pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
         .configure(steam_level::configure)
         .configure(player_summaries::configure);
 }
+
+#[cfg(test)]
+mod test {
+    use crate::openid::Provider;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_and_read_body_json, call_service, TestRequest};
+
+    use super::*;
+    use crate::state::{Dependencies, State};
+    use crate::util::mock_steam_api::MockSteamApi;
+    use crate::util::test_app::{session_cookie, test_app, STEAM_ID};
+
+    #[actix_web::test]
+    async fn handlers_use_steam_api() -> anyhow::Result<()> {
+        let deps = Dependencies {
+            api: Box::new(MockSteamApi::default().with_player(STEAM_ID, "oof")),
+            ..Dependencies::for_test(Provider::steam())
+        };
+        let app = test_app!(web::Data::new(State::for_test_with(deps)?));
+
+        let req = TestRequest::get().uri("/test/authenticate").to_request();
+        let res = call_service(&app, req).await;
+        let cookie = session_cookie(&res)?;
+
+        // unknown ids are left out
+        let req = TestRequest::get()
+            .uri("/api/steam/player-summaries?steam_ids=76561198181282063,76561197960287930")
+            .cookie(cookie.clone())
+            .to_request();
+        let summaries: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            summaries,
+            serde_json::json!([{ "steamid": "76561198181282063", "personaname": "oof" }])
+        );
+
+        let req = TestRequest::get()
+            .uri("/api/steam/steam-level?steam_id=76561198181282063")
+            .cookie(cookie.clone())
+            .to_request();
+        let level: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(level["player_level"], 1);
+
+        let req = TestRequest::get()
+            .uri("/api/steam/steam-level?steam_id=76561197960287930")
+            .cookie(cookie)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        Ok(())
+    }
+}
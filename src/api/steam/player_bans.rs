@@ -1,14 +1,11 @@
-use std::borrow::Cow;
-
 use actix_web::{web, HttpResponse};
-use anyhow::Context;
 use serde::Deserialize;
 use steam_api_concurrent::SteamId;
 
 use crate::api::session::AuthSession;
 use crate::error::AppResponse;
 use crate::openid::comma_separated::CommaSeparated;
-use crate::State;
+use crate::state::State;
 
 #[derive(Deserialize)]
 pub(crate) struct Query {
@@ -20,7 +17,7 @@ pub(crate) async fn player_bans(
     data: web::Data<State>,
     query: web::Query<Query>,
 ) -> AppResponse {
-    if session.authenticated().is_none() {
+    if session.authenticated(&data)?.is_none() {
         return Ok(HttpResponse::Unauthorized().finish());
     }
 
@@ -29,11 +26,9 @@ pub(crate) async fn player_bans(
         return Ok(HttpResponse::BadRequest().finish());
     }
 
-    let steam_ids = Cow::Owned(steam_ids);
-    let resp = data.steam.api.get_player_bans(steam_ids).await;
-    let resp = resp.context("couldn't fetch from steam api")?;
+    let bans = data.steam.api.player_bans(steam_ids).await?;
 
-    Ok(HttpResponse::Ok().json(resp.into_inner()))
+    Ok(HttpResponse::Ok().json(bans))
 }
 
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
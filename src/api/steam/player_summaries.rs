@@ -1,18 +1,18 @@
-use std::borrow::Cow;
-
 use actix_web::{web, HttpResponse};
-use anyhow::Context;
 use serde::Deserialize;
 use steam_api_concurrent::SteamId;
 
 use crate::api::session::AuthSession;
 use crate::error::AppResponse;
 use crate::openid::comma_separated::CommaSeparated;
-use crate::State;
+use crate::state::State;
+use crate::util::timing::timed;
 
 #[derive(Deserialize)]
 pub(crate) struct Query {
-    steam_ids: CommaSeparated<SteamId>,
+    /// Parsed with [`CommaSeparated::from_str_partial`] so a single
+    /// invalid id doesn't fail the whole request
+    steam_ids: String,
 }
 
 pub(crate) async fn player_summaries(
@@ -20,20 +20,36 @@ pub(crate) async fn player_summaries(
     data: web::Data<State>,
     query: web::Query<Query>,
 ) -> AppResponse {
-    if session.authenticated().is_none() {
+    if session.authenticated(&data)?.is_none() {
         return Ok(HttpResponse::Unauthorized().finish());
     }
 
-    let steam_ids = query.into_inner().steam_ids.into_inner();
+    let (steam_ids, invalid) = CommaSeparated::<SteamId>::from_str_partial(&query.steam_ids);
+    if !invalid.is_empty() {
+        log::warn!("ignoring invalid steam ids (index, value): {:?}", invalid);
+    }
     if steam_ids.is_empty() {
         return Ok(HttpResponse::BadRequest().finish());
     }
 
-    let steam_ids = Cow::Owned(steam_ids);
-    let resp = data.steam.api.get_player_summaries(steam_ids).await;
-    let resp = resp.context("couldn't fetch from steam api")?;
+    let summaries = data
+        .profiles
+        .get_or_fetch(&steam_ids, |misses| fetch(&data, misses))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(summaries))
+}
 
-    Ok(HttpResponse::Ok().json(resp.into_inner()))
+/// Fetch the summaries of the profiles [`crate::util::profile_cache::ProfileCache`]
+/// doesn't have in a single request, ids steam doesn't know are left out
+async fn fetch(
+    data: &State,
+    steam_ids: Vec<SteamId>,
+) -> anyhow::Result<Vec<(SteamId, serde_json::Value)>> {
+    timed!(
+        "get_player_summaries",
+        data.steam.api.player_summaries(steam_ids).await
+    )
 }
 
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
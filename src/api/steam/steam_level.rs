@@ -1,11 +1,10 @@
 use actix_web::{web, HttpResponse};
-use anyhow::Context;
 use serde::Deserialize;
 use steam_api_concurrent::SteamId;
 
 use crate::api::session::AuthSession;
 use crate::error::AppResponse;
-use crate::State;
+use crate::state::State;
 
 #[derive(Deserialize)]
 pub(crate) struct Query {
@@ -17,14 +16,13 @@ pub(crate) async fn steam_level(
     data: web::Data<State>,
     query: web::Query<Query>,
 ) -> AppResponse {
-    if session.authenticated().is_none() {
+    if session.authenticated(&data)?.is_none() {
         return Ok(HttpResponse::Unauthorized().finish());
     }
 
-    let resp = data.steam.api.get_player_steam_level(query.steam_id).await;
-    let resp = resp.context("couldn't fetch from steam api")?;
+    let level = data.steam.api.steam_level(query.steam_id).await?;
 
-    Ok(HttpResponse::Ok().json(resp.into_inner()))
+    Ok(HttpResponse::Ok().json(level))
 }
 
 pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
@@ -0,0 +1,438 @@
+//! Configuration read from the environment at startup: the http client for OpenID
+//! providers, the session cookie and how the steam callback responds
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_web::cookie::{self, SameSite};
+use actix_web::http::StatusCode;
+use anyhow::Context;
+
+/// Default for `REDIRECT_STATUS`, the conventional choice to redirect after a login
+pub(crate) const DEFAULT_REDIRECT_STATUS: StatusCode = StatusCode::SEE_OTHER;
+
+/// Default for `DISCOVERY_ATTEMPTS`
+pub(crate) const DEFAULT_DISCOVERY_ATTEMPTS: u32 = 3;
+
+/// Wait before the second attempt to discover steam, doubled for every further attempt
+pub(crate) const DISCOVERY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Default for `HTTP_MAX_REDIRECTS`
+pub(crate) const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Default for `SESSION_COOKIE_NAME`
+pub(crate) const DEFAULT_SESSION_COOKIE_NAME: &str = "session-id";
+
+/// How the client for requests to OpenID providers is set up
+#[derive(Debug, Clone)]
+pub(crate) struct ClientConfig {
+    /// Configured through `HTTP_MIN_TLS_VERSION` as `1.2` (default) or `1.3`,
+    /// the latter is rejected when building the client if the tls backend can't enforce it
+    pub(crate) min_tls_version: reqwest::tls::Version,
+    /// Configured through `HTTP_MAX_REDIRECTS` (default `5`), `0` doesn't follow any
+    pub(crate) max_redirects: usize,
+    /// Configured through `HTTP_USER_AGENT`, none is sent if it is unset
+    pub(crate) user_agent: Option<String>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            min_tls_version: reqwest::tls::Version::TLS_1_2,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            user_agent: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub(crate) fn from_env() -> anyhow::Result<ClientConfig> {
+        let min_tls_version = match dotenv::var("HTTP_MIN_TLS_VERSION") {
+            Ok(version) => parse_min_tls_version(&version)?,
+            Err(_) => reqwest::tls::Version::TLS_1_2,
+        };
+        let max_redirects = match dotenv::var("HTTP_MAX_REDIRECTS") {
+            Ok(max) => max
+                .parse()
+                .context("couldn't parse HTTP_MAX_REDIRECTS as an integer")?,
+            Err(_) => DEFAULT_MAX_REDIRECTS,
+        };
+        let user_agent = dotenv::var("HTTP_USER_AGENT")
+            .ok()
+            .filter(|user_agent| !user_agent.is_empty());
+        Ok(ClientConfig {
+            min_tls_version,
+            max_redirects,
+            user_agent,
+        })
+    }
+}
+
+/// Parse `HTTP_MIN_TLS_VERSION`, anything older than TLS 1.2 isn't allowed
+pub(crate) fn parse_min_tls_version(value: &str) -> anyhow::Result<reqwest::tls::Version> {
+    match value {
+        "1.2" => Ok(reqwest::tls::Version::TLS_1_2),
+        "1.3" => Ok(reqwest::tls::Version::TLS_1_3),
+        _ => anyhow::bail!(
+            "HTTP_MIN_TLS_VERSION must be `1.2` or `1.3`, got `{}`",
+            value
+        ),
+    }
+}
+
+/// Client for discovery and verification requests to OpenID providers, it only talks https
+pub(crate) fn create_client(config: &ClientConfig) -> anyhow::Result<reqwest::Client> {
+    client_builder(config)
+        .https_only(true)
+        .build()
+        .context("couldn't build reqwest client")
+}
+
+/// Everything of [`create_client`] but the https restriction
+fn client_builder(config: &ClientConfig) -> reqwest::ClientBuilder {
+    let redirect = match config.max_redirects {
+        0 => reqwest::redirect::Policy::none(),
+        max => reqwest::redirect::Policy::limited(max),
+    };
+    let builder = reqwest::Client::builder()
+        .min_tls_version(config.min_tls_version)
+        .redirect(redirect);
+    match &config.user_agent {
+        Some(user_agent) => builder.user_agent(user_agent),
+        None => builder,
+    }
+}
+
+/// What a successful steam callback responds with
+///
+/// Configured through `CALLBACK_RESPONSE` as `redirect` (default), `json` or `minimal`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CallbackResponseMode {
+    /// Redirect to `OPENID_SUCCESS_REDIRECT`
+    #[default]
+    Redirect,
+    /// The verification result and the assertion as json, without its signature and association
+    Json,
+    /// Only the steam id as json
+    Minimal,
+}
+
+impl FromStr for CallbackResponseMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<CallbackResponseMode> {
+        match s {
+            "redirect" => Ok(CallbackResponseMode::Redirect),
+            "json" => Ok(CallbackResponseMode::Json),
+            "minimal" => Ok(CallbackResponseMode::Minimal),
+            _ => anyhow::bail!("CALLBACK_RESPONSE must be one of redirect, json or minimal"),
+        }
+    }
+}
+
+/// How discovery of steam is retried at startup if it fails for transient reasons
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DiscoveryRetry {
+    /// Configured through `DISCOVERY_ATTEMPTS` (default `3`), at least one attempt is made
+    pub(crate) attempts: u32,
+    pub(crate) backoff: Duration,
+}
+
+impl DiscoveryRetry {
+    pub(crate) fn from_env() -> anyhow::Result<DiscoveryRetry> {
+        let attempts = match dotenv::var("DISCOVERY_ATTEMPTS") {
+            Ok(attempts) => attempts
+                .parse()
+                .context("couldn't parse DISCOVERY_ATTEMPTS as an integer")?,
+            Err(_) => DEFAULT_DISCOVERY_ATTEMPTS,
+        };
+        Ok(DiscoveryRetry {
+            attempts,
+            backoff: DISCOVERY_BACKOFF,
+        })
+    }
+}
+
+/// Parse `REDIRECT_STATUS`, only temporary redirects that make the browser
+/// issue a `GET` (302, 303) or keep the method (307) make sense here
+pub(crate) fn parse_redirect_status(value: &str) -> anyhow::Result<StatusCode> {
+    let status: u16 = value
+        .parse()
+        .context("couldn't parse REDIRECT_STATUS as an integer")?;
+    match StatusCode::from_u16(status) {
+        Ok(
+            status @ (StatusCode::FOUND | StatusCode::SEE_OTHER | StatusCode::TEMPORARY_REDIRECT),
+        ) => Ok(status),
+        _ => anyhow::bail!("REDIRECT_STATUS must be one of 302, 303 or 307"),
+    }
+}
+
+/// Number of bytes [`cookie::Key`] expects to be derived from
+pub(crate) const COOKIE_KEY_LEN: usize = 64;
+
+/// Decode the base64 encoded cookie key, which must be exactly [`COOKIE_KEY_LEN`] bytes
+///
+/// Keys are accepted with the standard alphabet (what `openssl rand -base64` emits)
+/// and, as a fallback, with the url-safe alphabet without padding.
+pub(crate) fn decode_cookie_key(key_b64: &str) -> anyhow::Result<cookie::Key> {
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use base64::Engine;
+
+    let key_data = STANDARD
+        .decode(key_b64)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(key_b64))
+        .context("couldn't decode COOKIE_KEY_BASE64 as standard or url-safe base64")?;
+
+    if key_data.len() < COOKIE_KEY_LEN {
+        anyhow::bail!(
+            "key in COOKIE_KEY_BASE64 is too short ({} < {} bytes)",
+            key_data.len(),
+            COOKIE_KEY_LEN
+        );
+    }
+    if key_data.len() > COOKIE_KEY_LEN {
+        anyhow::bail!(
+            "key in COOKIE_KEY_BASE64 is too long ({} > {} bytes)",
+            key_data.len(),
+            COOKIE_KEY_LEN
+        );
+    }
+
+    cookie::Key::try_from(key_data.as_slice())
+        .context("couldn't construct cookie key from COOKIE_KEY_BASE64 data")
+}
+
+pub(crate) fn load_cookie_key() -> anyhow::Result<cookie::Key> {
+    let key_b64 =
+        dotenv::var("COOKIE_KEY_BASE64").context("missing COOKIE_KEY_BASE64 env variable")?;
+    decode_cookie_key(&key_b64)
+}
+
+/// Parse `SESSION_SAME_SITE` as `lax` (default), `strict` or `none`
+///
+/// Steam returns the user with a cross-site top-level `GET` to the callback, browsers
+/// only send a `Lax` (or `None`) cookie along with it. With `Strict` the callback
+/// sees no session and sends the user back to the login, so it only works if
+/// the callback is reached through a same-site redirect, e.g. from the frontend.
+pub(crate) fn parse_same_site(value: &str) -> anyhow::Result<SameSite> {
+    match value.to_ascii_lowercase().as_str() {
+        "lax" => Ok(SameSite::Lax),
+        "strict" => Ok(SameSite::Strict),
+        "none" => Ok(SameSite::None),
+        _ => anyhow::bail!("SESSION_SAME_SITE must be one of lax, strict or none"),
+    }
+}
+
+/// How the session cookie is set
+#[derive(Debug, Clone)]
+pub(crate) struct SessionCookieConfig {
+    /// Configured through `SESSION_COOKIE_NAME` (default `session-id`),
+    /// apps sharing a domain need different names
+    pub(crate) name: String,
+    /// Configured through `SESSION_COOKIE_DOMAIN`, e.g. to share the session with subdomains.
+    /// If it is unset, the cookie is only sent to the host that set it
+    pub(crate) domain: Option<String>,
+    /// See [`parse_same_site`]
+    pub(crate) same_site: SameSite,
+    /// Configured through `SESSION_COOKIE_HTTP_ONLY` (default `true`)
+    ///
+    /// The cookie authenticates the user, with `false` any script injected into the
+    /// frontend could read it and take over the session. The frontend has no use for it,
+    /// it asks `/api/auth/steam/status` whether the user is logged in.
+    pub(crate) http_only: bool,
+}
+
+impl Default for SessionCookieConfig {
+    fn default() -> SessionCookieConfig {
+        SessionCookieConfig {
+            name: DEFAULT_SESSION_COOKIE_NAME.to_string(),
+            domain: None,
+            same_site: SameSite::Lax,
+            http_only: true,
+        }
+    }
+}
+
+impl SessionCookieConfig {
+    pub(crate) fn from_env() -> anyhow::Result<SessionCookieConfig> {
+        let name = match dotenv::var("SESSION_COOKIE_NAME") {
+            Ok(name) if name.is_empty() => anyhow::bail!("SESSION_COOKIE_NAME must not be empty"),
+            Ok(name) => name,
+            Err(_) => DEFAULT_SESSION_COOKIE_NAME.to_string(),
+        };
+        let domain = dotenv::var("SESSION_COOKIE_DOMAIN")
+            .ok()
+            .filter(|domain| !domain.is_empty());
+        let same_site = match dotenv::var("SESSION_SAME_SITE") {
+            Ok(same_site) => parse_same_site(&same_site)?,
+            Err(_) => SameSite::Lax,
+        };
+        if same_site == SameSite::Strict {
+            log::warn!(
+                "SESSION_SAME_SITE is strict, the cookie isn't sent along with the redirect from steam"
+            );
+        }
+        let http_only = match dotenv::var("SESSION_COOKIE_HTTP_ONLY") {
+            Ok(http_only) => http_only
+                .parse()
+                .context("couldn't parse SESSION_COOKIE_HTTP_ONLY as a boolean")?,
+            Err(_) => true,
+        };
+        if !http_only {
+            log::warn!("SESSION_COOKIE_HTTP_ONLY is false, scripts can read the session cookie");
+        }
+        Ok(SessionCookieConfig {
+            name,
+            domain,
+            same_site,
+            http_only,
+        })
+    }
+}
+#[cfg(test)]
+mod test {
+    use base64::engine::general_purpose::STANDARD as Base64;
+    use base64::Engine;
+
+    use super::*;
+
+    fn encoded_key(len: usize) -> String {
+        let key_data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        Base64.encode(key_data)
+    }
+
+    #[test]
+    fn redirect_status() -> anyhow::Result<()> {
+        assert_eq!(parse_redirect_status("302")?, StatusCode::FOUND);
+        assert_eq!(parse_redirect_status("303")?, StatusCode::SEE_OTHER);
+        assert_eq!(
+            parse_redirect_status("307")?,
+            StatusCode::TEMPORARY_REDIRECT
+        );
+        for invalid in ["301", "308", "200", "found", ""] {
+            assert!(parse_redirect_status(invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cookie_key_exact_length() -> anyhow::Result<()> {
+        decode_cookie_key(&encoded_key(COOKIE_KEY_LEN)).context("exact length was rejected")?;
+        Ok(())
+    }
+
+    #[test]
+    fn cookie_key_url_safe() -> anyhow::Result<()> {
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+        // make sure both alphabets actually differ for this key
+        let key_data: Vec<u8> = (0..COOKIE_KEY_LEN).map(|i| (i as u8) * 4 + 3).collect();
+        let standard = Base64.encode(&key_data);
+        let url_safe = URL_SAFE_NO_PAD.encode(&key_data);
+        assert_ne!(standard, url_safe);
+
+        let from_standard = decode_cookie_key(&standard).context("standard was rejected")?;
+        let from_url_safe = decode_cookie_key(&url_safe).context("url-safe was rejected")?;
+        assert!(from_standard == from_url_safe);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cookie_key_invalid_base64() {
+        assert!(decode_cookie_key("not base64 at all!").is_err());
+    }
+
+    #[test]
+    fn cookie_key_too_short() -> anyhow::Result<()> {
+        let err = decode_cookie_key(&encoded_key(COOKIE_KEY_LEN - 1))
+            .err()
+            .context("invalid length was accepted")?;
+        assert_eq!(
+            err.to_string(),
+            "key in COOKIE_KEY_BASE64 is too short (63 < 64 bytes)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cookie_key_too_long() -> anyhow::Result<()> {
+        let err = decode_cookie_key(&encoded_key(COOKIE_KEY_LEN + 1))
+            .err()
+            .context("invalid length was accepted")?;
+        assert_eq!(
+            err.to_string(),
+            "key in COOKIE_KEY_BASE64 is too long (65 > 64 bytes)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cookie_key_longer_than_old_buffer() -> anyhow::Result<()> {
+        let err = decode_cookie_key(&encoded_key(200))
+            .err()
+            .context("invalid length was accepted")?;
+        assert_eq!(
+            err.to_string(),
+            "key in COOKIE_KEY_BASE64 is too long (200 > 64 bytes)"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_from_config() -> anyhow::Result<()> {
+        use wiremock::matchers::{header, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        assert!(matches!(
+            parse_min_tls_version("1.3")?,
+            reqwest::tls::Version::TLS_1_3
+        ));
+        assert!(parse_min_tls_version("1.1").is_err());
+
+        let config = ClientConfig {
+            min_tls_version: parse_min_tls_version("1.2")?,
+            max_redirects: 0,
+            user_agent: Some("complainer/1.0".to_string()),
+        };
+        let client = create_client(&config)?;
+
+        // the client only talks https, so the plain http mock server is never reached
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        assert!(client.get(server.uri()).send().await.is_err());
+
+        // sends the user agent and hands redirects back instead of following them,
+        // built without the https restriction to reach the mock server
+        let client = client_builder(&config).build()?;
+        let server = MockServer::start().await;
+        Mock::given(path("/start"))
+            .and(header("user-agent", "complainer/1.0"))
+            .respond_with(ResponseTemplate::new(302).insert_header("location", "/target"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/target"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let res = client.get(format!("{}/start", server.uri())).send().await?;
+        assert_eq!(res.status(), reqwest::StatusCode::FOUND);
+
+        Ok(())
+    }
+
+    #[test]
+    fn same_site_values() -> anyhow::Result<()> {
+        assert_eq!(parse_same_site("lax")?, SameSite::Lax);
+        assert_eq!(parse_same_site("Strict")?, SameSite::Strict);
+        assert_eq!(parse_same_site("none")?, SameSite::None);
+        assert!(parse_same_site("relaxed").is_err());
+        Ok(())
+    }
+}
@@ -7,6 +7,19 @@ use crate::error::error_json::ErrorJson;
 pub(crate) struct AppError {
     pub(super) status_code: StatusCode,
     pub(super) inner: anyhow::Error,
+    /// Stable, machine readable reason, see [`AppError::with_code`]
+    pub(super) code: Option<&'static str>,
+}
+
+impl AppError {
+    /// Attach a code the frontend can match on instead of the error messages
+    pub(crate) const fn with_code(mut self, code: &'static str) -> AppError {
+        self.code = Some(code);
+        self
+    }
+    pub(crate) const fn code(&self) -> Option<&'static str> {
+        self.code
+    }
 }
 
 /// Error type returned from endpoints
@@ -33,6 +46,13 @@ pub(crate) trait IntoAppError: Sized {
     impl_into_app_error!(into_app_error_im_a_teapot, StatusCode::IM_A_TEAPOT);
     impl_into_app_error!(into_app_error_bad_request, StatusCode::BAD_REQUEST);
     impl_into_app_error!(into_app_error_unauthorized, StatusCode::UNAUTHORIZED);
+    impl_into_app_error!(into_app_error_forbidden, StatusCode::FORBIDDEN);
+    impl_into_app_error!(into_app_error_conflict, StatusCode::CONFLICT);
+    impl_into_app_error!(into_app_error_bad_gateway, StatusCode::BAD_GATEWAY);
+    impl_into_app_error!(
+        into_app_error_too_many_requests,
+        StatusCode::TOO_MANY_REQUESTS
+    );
     impl_into_app_error!(
         into_app_error_temorary_redirect,
         StatusCode::TEMPORARY_REDIRECT
@@ -45,6 +65,7 @@ impl IntoAppError for anyhow::Error {
         AppError {
             status_code,
             inner: self,
+            code: None,
         }
     }
 }
@@ -56,6 +77,7 @@ impl From<anyhow::Error> for AppError {
         AppError {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
             inner: err,
+            code: None,
         }
     }
 }
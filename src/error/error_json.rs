@@ -1,13 +1,55 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use actix_web::{HttpResponse, ResponseError};
+use anyhow::Context;
 use reqwest::StatusCode;
 use serde::Serialize;
 
 use crate::error::AppError;
 
+/// Errors beyond this depth of the chain are summarized, see [`collect_chain`]
+const DEFAULT_MAX_ERROR_CHAIN_DEPTH: usize = 16;
+
+/// Errors are turned into responses without access to the app state, so the
+/// configured depth is kept here, see [`max_error_chain_depth_from_env`]
+static MAX_ERROR_CHAIN_DEPTH: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_ERROR_CHAIN_DEPTH);
+
+/// Configured through `ERROR_CHAIN_MAX_DEPTH`, call it once before serving requests
+pub(crate) fn max_error_chain_depth_from_env() -> anyhow::Result<()> {
+    let max_depth = match dotenv::var("ERROR_CHAIN_MAX_DEPTH") {
+        Ok(max_depth) => max_depth
+            .parse()
+            .context("couldn't parse ERROR_CHAIN_MAX_DEPTH as an integer")?,
+        Err(_) => DEFAULT_MAX_ERROR_CHAIN_DEPTH,
+    };
+    if max_depth == 0 {
+        anyhow::bail!("ERROR_CHAIN_MAX_DEPTH must be at least 1");
+    }
+    MAX_ERROR_CHAIN_DEPTH.store(max_depth, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Messages of the first `max_depth` errors of the chain and a
+/// final `... N more` entry if the chain is longer than that
+fn collect_chain(err: &anyhow::Error, max_depth: usize) -> Vec<String> {
+    let mut chain: Vec<String> = err
+        .chain()
+        .take(max_depth)
+        .map(|err| err.to_string())
+        .collect();
+    let more = err.chain().len().saturating_sub(max_depth);
+    if more > 0 {
+        chain.push(format!("... {} more", more));
+    }
+    chain
+}
+
 /// Json struct returned from the API on error
 #[derive(Debug, Serialize)]
 pub(super) struct ErrorJson {
     error_chain: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
     status_cat: String,
     #[serde(skip)]
     status_code: StatusCode,
@@ -22,9 +64,14 @@ impl ErrorJson {
     }
 
     /// This is not implemented as a trait because it should not be exposed.
-    fn from_anyhow(err: &anyhow::Error, status_code: StatusCode) -> ErrorJson {
+    fn from_anyhow(
+        err: &anyhow::Error,
+        status_code: StatusCode,
+        code: Option<&'static str>,
+    ) -> ErrorJson {
         ErrorJson {
-            error_chain: err.chain().map(|err| err.to_string()).collect(),
+            error_chain: collect_chain(err, MAX_ERROR_CHAIN_DEPTH.load(Ordering::Relaxed)),
+            code,
             status_cat: ErrorJson::status_to_cat(status_code),
             status_code,
         }
@@ -44,6 +91,7 @@ impl ErrorJson {
         let status_code = err.as_response_error().status_code();
         ErrorJson {
             error_chain: vec![err.to_string()],
+            code: None,
             status_cat: ErrorJson::status_to_cat(status_code),
             status_code,
         }
@@ -54,6 +102,7 @@ impl ErrorJson {
         err_trace!("Convert StatusCode -> ErrorJson");
         ErrorJson {
             error_chain: vec![],
+            code: None,
             status_cat: ErrorJson::status_to_cat(status_code),
             status_code,
         }
@@ -62,7 +111,7 @@ impl ErrorJson {
     /// This is not implemented as a trait because it should not be exposed.
     pub(super) fn from_app_error(err: &AppError) -> ErrorJson {
         err_trace!("Convert AppError -> ErrorJson");
-        ErrorJson::from_anyhow(&err.inner, err.status_code)
+        ErrorJson::from_anyhow(&err.inner, err.status_code, err.code)
     }
 }
 
@@ -83,3 +132,48 @@ impl ResponseError for ErrorJson {
         HttpResponse::build(self.status_code).json(self)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use anyhow::Context;
+
+    use super::*;
+
+    fn chain_of(len: usize) -> anyhow::Error {
+        (1..len).fold(anyhow::anyhow!("root cause"), |err, depth| {
+            err.context(format!("context {}", depth))
+        })
+    }
+
+    #[test]
+    fn short_chain_is_complete() {
+        let err = anyhow::Result::<()>::Err(anyhow::anyhow!("root cause"))
+            .context("outer")
+            .unwrap_err();
+        assert_eq!(
+            collect_chain(&err, DEFAULT_MAX_ERROR_CHAIN_DEPTH),
+            ["outer", "root cause"]
+        );
+    }
+
+    #[test]
+    fn long_chain_is_truncated() {
+        let chain = collect_chain(
+            &chain_of(DEFAULT_MAX_ERROR_CHAIN_DEPTH + 4),
+            DEFAULT_MAX_ERROR_CHAIN_DEPTH,
+        );
+        assert_eq!(chain.len(), DEFAULT_MAX_ERROR_CHAIN_DEPTH + 1);
+        assert_eq!(
+            chain[0],
+            format!("context {}", DEFAULT_MAX_ERROR_CHAIN_DEPTH + 3)
+        );
+        assert_eq!(chain[DEFAULT_MAX_ERROR_CHAIN_DEPTH], "... 4 more");
+
+        let exact = collect_chain(
+            &chain_of(DEFAULT_MAX_ERROR_CHAIN_DEPTH),
+            DEFAULT_MAX_ERROR_CHAIN_DEPTH,
+        );
+        assert_eq!(exact.len(), DEFAULT_MAX_ERROR_CHAIN_DEPTH);
+        assert_eq!(exact[DEFAULT_MAX_ERROR_CHAIN_DEPTH - 1], "root cause");
+    }
+}
@@ -59,3 +59,4 @@ mod error_json;
 
 pub(crate) use app_error::{AppError, AppResponse, AppResult, IntoAppError};
 pub(crate) use error_handler::error_handler;
+pub(crate) use error_json::max_error_chain_depth_from_env;
@@ -67,205 +67,62 @@
     clippy::useless_let_if_seq
 )]
 
-mod api;
-mod error;
-mod openid;
-mod openid_next;
-mod util;
-
-use actix_session::config::CookieContentSecurity;
-use actix_session::storage::{CookieSessionStore, RedisActorSessionStore};
-use actix_session::SessionMiddleware;
-use actix_web::cookie::{self, Key, SameSite};
-use actix_web::{middleware, web, App, HttpServer};
 use anyhow::Context;
-use openid::{make_auth_req_url, Provider};
-use util::nonce::NonceSet;
-
-use crate::error::error_handler;
-
-const SOCKET: &str = "0.0.0.0:8080";
-
-const STEAM_OPENID_LOGIN: &str = "https://steamcommunity.com/openid";
-
-pub(crate) struct OpenIdState {
-    pub(crate) realm: String,
-    pub(crate) return_to: String,
-    pub(crate) success_redirect: String,
-    pub(crate) logout_redirect: String,
+use complainer_api::server::{self, init_logger};
+
+/// What the binary was asked to do on the command line
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    /// No arguments, run the server
+    Serve,
+    /// `print-auth-url`, print the steam auth url a visitor would be sent to
+    PrintAuthUrl,
 }
-impl OpenIdState {
-    pub(crate) fn new() -> anyhow::Result<OpenIdState> {
-        Ok(OpenIdState {
-            realm: dotenv::var("OPENID_REALM")?,
-            return_to: dotenv::var("OPENID_RETURN_TO")?,
-            success_redirect: dotenv::var("OPENID_SUCCESS_REDIRECT")?,
-            logout_redirect: dotenv::var("OPENID_LOGOUT_REDIRECT")?,
-        })
-    }
-    pub(crate) fn return_to_abs(&self) -> anyhow::Result<String> {
-        Ok(format!("{}{}", self.realm, self.return_to))
-    }
-}
-
-struct SteamState {
-    provider: Provider,
-    nonces: NonceSet,
-    api: steam_api_concurrent::Client,
-    open_id: OpenIdState,
-}
-impl SteamState {
-    pub(crate) async fn new(client: &reqwest::Client) -> anyhow::Result<SteamState> {
-        let api_key = dotenv::var("STEAM_API_KEY").unwrap();
-        let api = steam_api_concurrent::ClientOptions::new()
-            .api_key(api_key)
-            .build()
-            .await
-            .context("couldn't prepare steam api client")?;
-
-        let resp = client.get(STEAM_OPENID_LOGIN).send().await;
-        let resp = resp.context("couldn't fetch steam openid service")?;
 
-        let xml = resp
-            .text()
-            .await
-            .context("couldn't read response body as text")?;
-
-        let provider =
-            Provider::from_xml(&xml).context("couldn't parse response xml as service")?;
-
-        let nonces = NonceSet::new();
-        let open_id = OpenIdState::new()?;
-
-        Ok(SteamState {
-            provider,
-            nonces,
-            api,
-            open_id,
-        })
-    }
-    pub(crate) fn auth_url_with_nonce(&self, nonce: &str) -> anyhow::Result<String> {
-        let return_to = self.open_id.return_to_abs()?;
-        let return_to = reqwest::Url::parse_with_params(&return_to, [("custom_nonce", nonce)])
-            .context("couldn't parse return_to url with custom nonce")?;
-        let auth_url = make_auth_req_url(&self.provider, &self.open_id.realm, return_to.as_str())
-            .context("couldn't create auth request url with custom nonce")?;
-        Ok(auth_url)
+impl Command {
+    fn from_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+        let command = match args.next().as_deref() {
+            None => Command::Serve,
+            Some("print-auth-url" | "--print-auth-url") => Command::PrintAuthUrl,
+            Some(other) => anyhow::bail!(
+                "unknown argument `{}`, the only subcommand is `print-auth-url`",
+                other
+            ),
+        };
+        if let Some(extra) = args.next() {
+            anyhow::bail!("unexpected argument `{}`", extra);
+        }
+        Ok(command)
     }
 }
 
-fn load_cookie_key() -> anyhow::Result<cookie::Key> {
-    use base64::engine::general_purpose::STANDARD as Base64;
-    use base64::Engine;
-
-    let key_b64 =
-        dotenv::var("COOKIE_KEY_BASE64").context("missing COOKIE_KEY_BASE64 env variable")?;
-
-    let mut key_data = vec![0u8; 128];
-    let len = Base64
-        .decode_slice(key_b64, &mut key_data)
-        .context("couldn't decode COOKIE_KEY_BASE64")?;
-
-    if len != 64 {
-        anyhow::bail!("key in COOKIE_KEY_BASE64 is too small ({} < {})", 64, len);
-    }
-
-    cookie::Key::try_from(&key_data[..64])
-        .context("couldn't construct cookie key from COOKIE_KEY_BASE64 data")
-}
-
-struct State {
-    client: reqwest::Client,
-    steam: SteamState,
-}
-impl State {
-    pub async fn new() -> anyhow::Result<State> {
-        let client = reqwest::Client::builder()
-            .https_only(true)
-            .min_tls_version(reqwest::tls::Version::TLS_1_2)
-            .redirect(reqwest::redirect::Policy::limited(5))
-            .build()
-            .context("couldn't build reqwest client")?;
-        let steam = SteamState::new(&client)
-            .await
-            .context("couldn't create steam state")?;
-
-        Ok(State { client, steam })
-    }
-}
-
-fn create_redis_session_mw(url: &str, key: Key) -> SessionMiddleware<RedisActorSessionStore> {
-    SessionMiddleware::builder(RedisActorSessionStore::new(url), key)
-        .cookie_http_only(false)
-        .cookie_same_site(SameSite::Lax)
-        .cookie_name("session-id".to_string())
-        .cookie_content_security(CookieContentSecurity::Private)
-        .build()
-}
-
-fn _create_cookie_session_mw(key: Key) -> SessionMiddleware<CookieSessionStore> {
-    SessionMiddleware::builder(CookieSessionStore::default(), key)
-        .cookie_http_only(false)
-        .cookie_same_site(SameSite::Lax)
-        .cookie_name("session-data".to_string())
-        .cookie_content_security(CookieContentSecurity::Private)
-        .build()
-}
-
-fn create_logger_mw() -> middleware::Logger {
-    middleware::Logger::new(r#"%Ts %bB %{r}a [%r -> %s] "%{Referer}i" "%{User-Agent}i""#)
-}
-
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     if dotenv::dotenv().is_err() {
         log::warn!("no .env file found");
     }
 
-    util::log::init_logger().context("couldn't initialize logger")?;
+    init_logger().context("couldn't initialize logger")?;
     log::info!("initialized logger");
 
-    let cookie_key = load_cookie_key().context("couldn't load cookie key")?;
-    let state = State::new().await.context("couldn't create app state")?;
-    let data = web::Data::new(state);
-    log::info!("created app state");
-
-    let redis_url = dotenv::var("REDIS_URL").context("load REDIS_URL env variable")?;
-
-    let mut server = HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::clone(&data))
-            .wrap(create_logger_mw())
-            .wrap(error_handler())
-            .wrap(create_redis_session_mw(&redis_url, cookie_key.clone()))
-            .service(web::scope("/api").configure(api::configure))
-    });
-
-    server = server
-        .bind(SOCKET)
-        .with_context(|| format!("couldn't bind to socket `{}`", SOCKET))?;
-
-    log::info!("server is listening on {}", SOCKET);
-
-    log::info!("here is a list of endpoints:");
-    for (endpoint, description) in [
-        ("/api/auth/steam/login", "initiate login to steam"),
-        ("/api/auth/steam/callback", "verify assertion from steam"),
-        ("/api/auth/steam/logout", "logout from steam"),
-        ("/api/auth/never/login", "initiate login to never"),
-        ("/api/health/live", "health check"),
-        ("/api/health/ready", "health check"),
-        ("/api/health/error", "error example"),
-        ("/api/health/cookies", "view cookies decrypted"),
-    ] {
-        log::info!("- http://{}{}: {}", SOCKET, endpoint, description);
+    match Command::from_args(std::env::args().skip(1))? {
+        Command::Serve => server::serve().await,
+        Command::PrintAuthUrl => server::print_auth_url().await,
     }
+}
 
-    server
-        .workers(1)
-        .run()
-        .await
-        .context("error while running server")?;
-
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_from_args() -> anyhow::Result<()> {
+        let parse = |args: &[&str]| Command::from_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(parse(&[])?, Command::Serve);
+        assert_eq!(parse(&["print-auth-url"])?, Command::PrintAuthUrl);
+        assert_eq!(parse(&["--print-auth-url"])?, Command::PrintAuthUrl);
+        assert!(parse(&["serve"]).is_err());
+        assert!(parse(&["print-auth-url", "now"]).is_err());
+        Ok(())
+    }
 }
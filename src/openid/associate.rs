@@ -0,0 +1,297 @@
+//! Establish a shared secret with an OP, so its assertions can be verified locally
+//!
+//! Only the `no-encryption` session type is implemented, the spec allows it over https
+//! and the client should refuse anything else.
+//!
+//! <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8>
+
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD as Base64;
+use base64::Engine;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::key_values;
+use crate::openid::constants::*;
+use crate::openid::validate::{body_snippet, is_key_value_content_type, BODY_SNIPPET_LEN};
+use crate::openid::{AssocType, Association, Provider, UnexpectedProviderResponse};
+use crate::openid_next::OpenIdMode;
+
+/// `error_code` of an `associate` response if the OP doesn't support the requested types
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.2.4>
+const ERROR_CODE_UNSUPPORTED_TYPE: &str = "unsupported-type";
+
+/// Body of the `associate` request
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.1>
+#[derive(Serialize)]
+struct AssociateForm {
+    #[serde(rename = "openid.ns")]
+    namespace: &'static str,
+    #[serde(rename = "openid.mode")]
+    mode: &'static str,
+    #[serde(rename = "openid.assoc_type")]
+    assoc_type: AssocType,
+    #[serde(rename = "openid.session_type")]
+    session_type: &'static str,
+}
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.2.1>
+#[derive(Debug, Deserialize)]
+struct AssociateResponse {
+    assoc_handle: String,
+    session_type: String,
+    /// See [`AssocType`], the key-values deserializer doesn't do enums
+    assoc_type: String,
+    /// Lifetime of the association in seconds
+    expires_in: u32,
+    /// Base 64 encoded, only sent in plain text with `no-encryption`
+    mac_key: String,
+}
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.2.4>
+#[derive(Debug, Deserialize)]
+struct AssociateError {
+    error: String,
+    #[serde(default)]
+    error_code: Option<String>,
+    /// Type the OP supports instead
+    #[serde(default)]
+    assoc_type: Option<String>,
+    #[serde(default)]
+    session_type: Option<String>,
+}
+
+impl AssociateResponse {
+    fn into_association(self, requested: AssocType) -> anyhow::Result<Association> {
+        if self.session_type != OPENID_SESSION_TYPE_NO_ENCRYPTION {
+            anyhow::bail!(
+                "provider answered with session type `{}`",
+                self.session_type
+            );
+        }
+        let assoc_type: AssocType = self.assoc_type.parse()?;
+        if assoc_type != requested {
+            anyhow::bail!(
+                "provider answered with association type `{}` instead of `{}`",
+                assoc_type.as_str(),
+                requested.as_str()
+            );
+        }
+        let key_len = Base64
+            .decode(&self.mac_key)
+            .context("couldn't decode mac key")?
+            .len();
+        let expected_len = match assoc_type {
+            AssocType::HmacSha1 => 20,
+            AssocType::HmacSha256 => 32,
+        };
+        if key_len != expected_len {
+            anyhow::bail!(
+                "mac key has {} bytes, {} needs {}",
+                key_len,
+                assoc_type.as_str(),
+                expected_len
+            );
+        }
+        Ok(Association {
+            handle: self.assoc_handle,
+            assoc_type,
+            mac_key: self.mac_key,
+            expires_at: Utc::now() + chrono::Duration::seconds(self.expires_in.into()),
+        })
+    }
+}
+
+/// What came back for a single `associate` request
+enum Outcome {
+    Associated(Association),
+    /// The OP doesn't support the requested type, it may have suggested another one
+    Unsupported(Option<AssocType>),
+}
+
+async fn request_association(
+    client: &reqwest::Client,
+    provider: &Provider,
+    assoc_type: AssocType,
+) -> anyhow::Result<Outcome> {
+    let form = AssociateForm {
+        namespace: OPENID_AUTH_NAMESPACE,
+        mode: OpenIdMode::Associate.as_str(),
+        assoc_type,
+        session_type: OPENID_SESSION_TYPE_NO_ENCRYPTION,
+    };
+    let res = client
+        .post(provider.endpoint())
+        .form(&form)
+        .send()
+        .await
+        .context("couldn't send associate request")?;
+
+    let status = res.status();
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let text = res
+        .text()
+        .await
+        .context("provider returned an invalid response")?;
+
+    // errors of direct requests come with a 400
+    // https://openid.net/specs/openid-authentication-2_0.html#rfc.section.5.1.2.2
+    let expected_status = status.is_success() || status == reqwest::StatusCode::BAD_REQUEST;
+    let expected_content_type = content_type
+        .as_deref()
+        .is_none_or(is_key_value_content_type);
+    if !expected_status || !expected_content_type {
+        return Err(UnexpectedProviderResponse {
+            status,
+            content_type,
+            snippet: body_snippet(&text, BODY_SNIPPET_LEN).to_string(),
+        }
+        .into());
+    }
+
+    let fields: key_values::KeyValues =
+        key_values::from_str(&text).context("couldn't parse associate response as key-values")?;
+    if fields.get("error").is_none() {
+        let response: AssociateResponse = fields
+            .deserialize_into()
+            .context("couldn't parse associate response")?;
+        return response
+            .into_association(assoc_type)
+            .map(Outcome::Associated);
+    }
+
+    let error: AssociateError = fields
+        .deserialize_into()
+        .context("couldn't parse associate error")?;
+    if error.error_code.as_deref() != Some(ERROR_CODE_UNSUPPORTED_TYPE) {
+        anyhow::bail!("provider refused to associate: {}", error.error);
+    }
+    // a suggestion of another session type is of no use, only `no-encryption` is implemented
+    let suggested = match error.session_type.as_deref() {
+        None | Some(OPENID_SESSION_TYPE_NO_ENCRYPTION) => error
+            .assoc_type
+            .and_then(|suggested| suggested.parse().ok()),
+        Some(_) => None,
+    };
+    Ok(Outcome::Unsupported(suggested))
+}
+
+/// Establish an association with `provider`
+///
+/// The [default](AssocType::default) type is requested first, if the OP doesn't
+/// support it the type it suggests or the [fallback](AssocType::fallback) is tried once.
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8>
+pub async fn associate(
+    client: &reqwest::Client,
+    provider: &Provider,
+) -> anyhow::Result<Association> {
+    let requested = AssocType::default();
+    let retry = match request_association(client, provider, requested).await? {
+        Outcome::Associated(association) => return Ok(association),
+        Outcome::Unsupported(suggested) => suggested
+            .filter(|&suggested| suggested != requested)
+            .or_else(|| requested.fallback())
+            .context("provider doesn't support any association type")?,
+    };
+    match request_association(client, provider, retry).await? {
+        Outcome::Associated(association) => Ok(association),
+        Outcome::Unsupported(_) => anyhow::bail!("provider doesn't support any association type"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::openid::Service;
+
+    const MAC_KEY_SHA1: &str = "AAECAwQFBgcICQoLDA0ODxAREhM=";
+    const MAC_KEY_SHA256: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    fn provider(server: &MockServer) -> Provider {
+        Provider {
+            service: Service {
+                endpoint: server.uri(),
+                ..Service::default()
+            },
+        }
+    }
+
+    fn associated(assoc_type: AssocType, mac_key: &str) -> ResponseTemplate {
+        let body = format!(
+            "ns:{}\nassoc_handle:handle\nsession_type:no-encryption\nassoc_type:{}\nexpires_in:3600\nmac_key:{}\n",
+            OPENID_AUTH_NAMESPACE,
+            assoc_type.as_str(),
+            mac_key
+        );
+        ResponseTemplate::new(200).set_body_raw(body, "text/plain")
+    }
+
+    #[tokio::test]
+    async fn associates_without_encryption() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("openid.mode=associate"))
+            .and(body_string_contains("openid.session_type=no-encryption"))
+            .and(body_string_contains("openid.assoc_type=HMAC-SHA256"))
+            .respond_with(associated(AssocType::HmacSha256, MAC_KEY_SHA256))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let association = associate(&reqwest::Client::new(), &provider(&server)).await?;
+        assert_eq!(association.handle, "handle");
+        assert_eq!(association.assoc_type, AssocType::HmacSha256);
+        assert_eq!(association.mac_key, MAC_KEY_SHA256);
+        assert!(!association.is_expired());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unsupported_type_falls_back() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        let unsupported = format!(
+            "ns:{}\nerror:unsupported\nerror_code:unsupported-type\n",
+            OPENID_AUTH_NAMESPACE
+        );
+        Mock::given(method("POST"))
+            .and(body_string_contains("openid.assoc_type=HMAC-SHA256"))
+            .respond_with(ResponseTemplate::new(400).set_body_raw(unsupported, "text/plain"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("openid.assoc_type=HMAC-SHA1"))
+            .respond_with(associated(AssocType::HmacSha1, MAC_KEY_SHA1))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let association = associate(&reqwest::Client::new(), &provider(&server)).await?;
+        assert_eq!(association.assoc_type, AssocType::HmacSha1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn short_mac_key_is_rejected() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(associated(AssocType::HmacSha256, MAC_KEY_SHA1))
+            .mount(&server)
+            .await;
+
+        assert!(associate(&reqwest::Client::new(), &provider(&server))
+            .await
+            .is_err());
+        Ok(())
+    }
+}
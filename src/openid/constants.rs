@@ -6,13 +6,13 @@
 /// Future versions of the specification may define different values in order to allow message recipients to properly interpret the request.
 ///
 /// Value: `http://specs.openid.net/auth/2.0`
-pub(crate) const OPENID_NAMESPACE: &str = "openid.ns";
+pub const OPENID_NAMESPACE: &str = "openid.ns";
 
 /// See [`OPENID_NAMESPACE`]
-pub(crate) const OPENID_AUTH_NAMESPACE: &str = "http://specs.openid.net/auth/2.0";
+pub const OPENID_AUTH_NAMESPACE: &str = "http://specs.openid.net/auth/2.0";
 
 /// See [`OPENID_IDENTITY`]
-pub(crate) const OPENID_CLAIMED_ID: &str = "openid.claimed_id";
+pub const OPENID_CLAIMED_ID: &str = "openid.claimed_id";
 
 /// `openid.identity` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.1>
 ///
@@ -20,11 +20,10 @@ pub(crate) const OPENID_CLAIMED_ID: &str = "openid.claimed_id";
 ///
 /// If this is set to the special value `http://specs.openid.net/auth/2.0/identifier_select`
 /// then the OP SHOULD choose an Identifier that belongs to the end user.
-pub(crate) const OPENID_IDENTITY: &str = "openid.identity";
+pub const OPENID_IDENTITY: &str = "openid.identity";
 
 /// See [`OPENID_IDENTITY`]
-pub(crate) const OPENID_IDENTIFIER_SELECT: &str =
-    "http://specs.openid.net/auth/2.0/identifier_select";
+pub const OPENID_IDENTIFIER_SELECT: &str = "http://specs.openid.net/auth/2.0/identifier_select";
 
 /// `openid.mode`
 /// - <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.5.2.3>
@@ -39,45 +38,53 @@ pub(crate) const OPENID_IDENTIFIER_SELECT: &str =
 /// If the Relying Party wishes the end user to be able to interact with the OP, `checkid_setup` should be used.
 ///
 /// Value: `checkid_immediate`, `checkid_setup` or `id_res`
-pub(crate) const OPENID_MODE: &str = "openid.mode";
+pub const OPENID_MODE: &str = "openid.mode";
 
 /// See [`OPENID_MODE`]
-pub(crate) const OPENID_MODE_CHECKID_IMMEDIATE: &str = "checkid_immediate";
+pub const OPENID_MODE_CHECKID_IMMEDIATE: &str = "checkid_immediate";
 
 /// See [`OPENID_MODE`]
-pub(crate) const OPENID_MODE_CHECKID_SETUP: &str = "checkid_setup";
+pub const OPENID_MODE_CHECKID_SETUP: &str = "checkid_setup";
 
 /// See [`OPENID_MODE`]
-pub(crate) const OPENID_MODE_IDENTIFIER_RESPONSE: &str = "id_res";
+pub const OPENID_MODE_IDENTIFIER_RESPONSE: &str = "id_res";
 
 /// See [`OPENID_MODE`]
-pub(crate) const OPENID_MODE_CHECK_AUTHENTICATION: &str = "check_authentication";
+pub const OPENID_MODE_CHECK_AUTHENTICATION: &str = "check_authentication";
 
 /// See [`OPENID_MODE`]
-pub(crate) const OPENID_MODE_ERROR: &str = "error";
+pub const OPENID_MODE_ERROR: &str = "error";
 
 /// `openid.return_to` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.1> and
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 ///
 /// URL to which the OP SHOULD return the User-Agent with the response indicating the status of the request.
-pub(crate) const OPENID_RETURN_TO: &str = "openid.return_to";
+pub const OPENID_RETURN_TO: &str = "openid.return_to";
 
 /// `openid.realm` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.1>
 ///
 /// URL pattern the OP SHOULD ask the end user to trust.
-pub(crate) const OPENID_REALM: &str = "openid.realm";
+pub const OPENID_REALM: &str = "openid.realm";
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2.1.1>
 ///
 /// An OP Identifier Element is an <xrd:Service> element with the following information:
 /// - An `<xrd:Type>` tag whose text content is `http://specs.openid.net/auth/2.0/server`.
 /// - An `<xrd:URI>` tag whose text content is the OP Endpoint URL
-pub(crate) const OPENID_PROVIDER_IDENTIFIER: &str = "http://specs.openid.net/auth/2.0/server";
+pub const OPENID_PROVIDER_IDENTIFIER: &str = "http://specs.openid.net/auth/2.0/server";
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2.1.2>
+///
+/// A Claimed Identifier Element is an <xrd:Service> element with the following information:
+/// - An `<xrd:Type>` tag whose text content is `http://specs.openid.net/auth/2.0/signon`.
+/// - An `<xrd:URI>` tag whose text content is the OP Endpoint URL.
+/// - An `<xrd:LocalID>` tag (optional) whose text content is the OP-Local Identifier.
+pub const OPENID_SIGNON_IDENTIFIER: &str = "http://specs.openid.net/auth/2.0/signon";
 
 /// `openid.op_endpoint` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 ///
 /// The OP Endpoint URL.
-pub(crate) const OPENID_OP_ENDPOINT: &str = "openid.op_endpoint";
+pub const OPENID_OP_ENDPOINT: &str = "openid.op_endpoint";
 
 /// `openid.response_nonce` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 ///
@@ -91,15 +98,15 @@ pub(crate) const OPENID_OP_ENDPOINT: &str = "openid.op_endpoint";
 /// - No fractional seconds are allowed
 ///
 /// Example: `2005-05-15T17:11:51ZUNIQUE`
-pub(crate) const OPENID_RESPONSE_NONCE: &str = "openid.response_nonce";
+pub const OPENID_RESPONSE_NONCE: &str = "openid.response_nonce";
 
 /// `openid.invalidate_handle` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
-pub(crate) const OPENID_INVALIDATE_HANDLE: &str = "openid.invalidate_handle";
+pub const OPENID_INVALIDATE_HANDLE: &str = "openid.invalidate_handle";
 
 /// `openid.assoc_handle` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 ///
 /// The handle for the association that was used to sign this assertion.
-pub(crate) const OPENID_ASSOCIATION_HANDLE: &str = "openid.assoc_handle";
+pub const OPENID_ASSOCIATION_HANDLE: &str = "openid.assoc_handle";
 
 /// `openid.signed` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 ///
@@ -116,17 +123,32 @@ pub(crate) const OPENID_ASSOCIATION_HANDLE: &str = "openid.assoc_handle";
 /// and if present in the response
 /// - `claimed_id`
 /// - `identity`
-pub(crate) const OPENID_SIGNED_FIELDS: &str = "openid.signed";
+pub const OPENID_SIGNED_FIELDS: &str = "openid.signed";
 
 /// `openid.sig` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 ///
 /// Base 64 encoded signature.
-pub(crate) const OPENID_SIGNATURE: &str = "openid.sig";
+pub const OPENID_SIGNATURE: &str = "openid.sig";
+
+/// `openid.assoc_type` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.1.1>
+///
+/// See [`crate::openid::AssocType`]
+pub const OPENID_ASSOCIATION_TYPE: &str = "openid.assoc_type";
+
+/// `openid.session_type` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.1.1>
+///
+/// How the MAC key is protected in the `associate` response.
+pub const OPENID_SESSION_TYPE: &str = "openid.session_type";
+
+/// See [`OPENID_SESSION_TYPE`]
+///
+/// The MAC key is sent in plain text, only allowed over https.
+pub const OPENID_SESSION_TYPE_NO_ENCRYPTION: &str = "no-encryption";
 
 /// See [`OPENID_RESPONSE_NONCE`]
-pub(crate) const OPENID_RESPONSE_NONCE_MAX_LEN: usize = 255;
+pub const OPENID_RESPONSE_NONCE_MAX_LEN: usize = 255;
 
-pub(crate) const OPENID_FIELD_PREFIX: &str = "openid.";
+pub const OPENID_FIELD_PREFIX: &str = "openid.";
 
 /// <http://docs.oasis-open.org/xri/2.0/specs/cd02/xri-resolution-V2.0-cd-02.html#_Ref124065812>
-pub(crate) const OPENID_PRIORITY_ATTRIBUTE: &str = "priority";
+pub const OPENID_PRIORITY_ATTRIBUTE: &str = "priority";
@@ -0,0 +1,629 @@
+//! <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7>
+
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::openid::constants::OPENID_IDENTIFIER_SELECT;
+use crate::openid::validate::media_type;
+use crate::openid::{Provider, ProviderMeta, Service, ServiceType};
+
+/// Leading characters of identifiers that are XRIs rather than URLs
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.2>
+const XRI_GLOBAL_CONTEXT_SYMBOLS: [char; 6] = ['=', '@', '+', '$', '!', '('];
+const XRI_PREFIX: &str = "xri://";
+
+/// <https://openid.net/specs/yadis-v1.0.pdf> section 6.2.4, plain xml is accepted
+/// as well because some OPs don't bother with the dedicated media type
+const XRDS_CONTENT_TYPES: [&str; 3] = ["application/xrds+xml", "application/xml", "text/xml"];
+
+/// Outcome of the discovery request, logged to diagnose issues on the side of the OP
+struct DiscoveryStats<'a> {
+    url: &'a str,
+    status: reqwest::StatusCode,
+    bytes: usize,
+    elapsed: Duration,
+}
+impl Display for DiscoveryStats<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "discovery of `{}` returned {} with {} bytes in {}ms",
+            self.url,
+            self.status,
+            self.bytes,
+            self.elapsed.as_millis()
+        )
+    }
+}
+
+/// `ETag` and `Last-Modified` of a discovery response, used to revalidate it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &reqwest::header::HeaderMap) -> Validators {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToString::to_string)
+        };
+        Validators {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        }
+    }
+    /// Turn the request into a conditional one
+    fn apply(&self, mut req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(etag) = &self.etag {
+            req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            req = req.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        req
+    }
+}
+
+/// Why discovering the OP failed, tells apart outages of the OP that are
+/// worth retrying from responses that won't get any better
+#[derive(Debug, Error)]
+pub enum DiscoveryError {
+    /// The request couldn't be sent or the response body couldn't be read
+    #[error("couldn't fetch openid service `{url}`")]
+    Unreachable {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("openid service responded with status {0}")]
+    BadStatus(reqwest::StatusCode),
+    /// The response isn't an XRDS document describing an OpenID service
+    #[error("couldn't parse response of openid service: {0:#}")]
+    ParseFailed(anyhow::Error),
+}
+
+impl DiscoveryError {
+    /// Whether the OP might respond differently to the same request later on
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DiscoveryError::Unreachable { .. } => true,
+            DiscoveryError::BadStatus(status) => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            DiscoveryError::ParseFailed(_) => false,
+        }
+    }
+}
+
+/// Result of a (conditional) discovery request
+pub enum Discovery {
+    /// The OP responded with `304 Not Modified`
+    NotModified,
+    Modified {
+        provider: Provider,
+        validators: Validators,
+    },
+}
+
+/// Fetch and parse the XRDS document of the OP, if `validators` are given the
+/// request is conditional and the OP may respond with [`Discovery::NotModified`]
+pub async fn discover_conditional(
+    client: &reqwest::Client,
+    url: &str,
+    validators: Option<&Validators>,
+) -> Result<Discovery, DiscoveryError> {
+    let unreachable = |source| DiscoveryError::Unreachable {
+        url: url.to_string(),
+        source,
+    };
+
+    let start = Instant::now();
+    let mut req = client.get(url);
+    if let Some(validators) = validators {
+        req = validators.apply(req);
+    }
+    let resp = req.send().await.map_err(unreachable)?;
+
+    let status = resp.status();
+    let validators = Validators::from_headers(resp.headers());
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let xml = resp.text().await.map_err(unreachable)?;
+
+    let stats = DiscoveryStats {
+        url,
+        status,
+        bytes: xml.len(),
+        elapsed: start.elapsed(),
+    };
+    log::info!("{}", stats);
+
+    if status == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(Discovery::NotModified);
+    }
+    if !status.is_success() {
+        return Err(DiscoveryError::BadStatus(status));
+    }
+    if let Some(content_type) = content_type {
+        if !is_xrds_content_type(&content_type) {
+            return Err(DiscoveryError::ParseFailed(anyhow::anyhow!(
+                "unexpected content type `{}`",
+                content_type
+            )));
+        }
+    }
+
+    let (provider, meta) = match Provider::from_xml_with_raw(&xml) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            if let Ok(meta) = ProviderMeta::from_xml(&xml) {
+                log::warn!(
+                    "openid service `{}` returned unexpected xrds: {:?}",
+                    url,
+                    meta
+                );
+            }
+            return Err(DiscoveryError::ParseFailed(
+                err.context("couldn't parse response xml as service"),
+            ));
+        }
+    };
+    log::debug!("openid service `{}` returned {:?}", url, meta);
+
+    Ok(Discovery::Modified {
+        provider,
+        validators,
+    })
+}
+
+fn is_xrds_content_type(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    XRDS_CONTENT_TYPES
+        .iter()
+        .any(|expected| media_type.eq_ignore_ascii_case(expected))
+}
+
+impl Provider {
+    /// Fetch and parse the XRDS document of the OP
+    ///
+    /// Fails if the OP doesn't respond with a successful status and an xml content type.
+    pub async fn from_url(client: &reqwest::Client, url: &str) -> Result<Provider, DiscoveryError> {
+        match discover_conditional(client, url, None).await? {
+            Discovery::Modified { provider, .. } => Ok(provider),
+            // not modified since nothing, that's no answer to an unconditional request
+            Discovery::NotModified => {
+                Err(DiscoveryError::BadStatus(reqwest::StatusCode::NOT_MODIFIED))
+            }
+        }
+    }
+}
+
+/// Fetch and parse the XRDS document of the OP, see [`Provider::from_url`]
+pub async fn discover(client: &reqwest::Client, url: &str) -> Result<Provider, DiscoveryError> {
+    Provider::from_url(client, url).await
+}
+
+struct CachedProvider {
+    provider: Arc<Provider>,
+    validators: Validators,
+    /// When the OP last confirmed the provider, with a `200` or a `304`
+    discovered_at: DateTime<Utc>,
+}
+
+/// The provider discovered at `url`, revalidated with conditional requests on refresh
+pub struct ProviderCache {
+    url: String,
+    inner: Mutex<Option<CachedProvider>>,
+}
+
+impl ProviderCache {
+    pub fn new(url: impl Into<String>) -> ProviderCache {
+        ProviderCache {
+            url: url.into(),
+            inner: Mutex::new(None),
+        }
+    }
+    /// Cache that starts out with `provider`, e.g. a known fallback if discovery failed
+    ///
+    /// There are no validators for it, so the next refresh is unconditional.
+    pub fn with_provider(url: impl Into<String>, provider: Provider) -> ProviderCache {
+        ProviderCache {
+            url: url.into(),
+            inner: Mutex::new(Some(CachedProvider {
+                provider: Arc::new(provider),
+                validators: Validators::default(),
+                discovered_at: Utc::now(),
+            })),
+        }
+    }
+    /// The url the provider is discovered at
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// The cached provider, if it has been discovered
+    pub fn provider(&self) -> Option<Arc<Provider>> {
+        self.inner
+            .lock()
+            .as_ref()
+            .map(|cached| Arc::clone(&cached.provider))
+    }
+    /// When the cached provider was last confirmed by the OP
+    pub fn discovered_at(&self) -> Option<DateTime<Utc>> {
+        self.inner
+            .lock()
+            .as_ref()
+            .map(|cached| cached.discovered_at)
+    }
+    /// Discover the provider, reusing the cached one if the OP responds with 304
+    ///
+    /// The cached provider is kept if the refresh fails.
+    pub async fn refresh(&self, client: &reqwest::Client) -> Result<Arc<Provider>, DiscoveryError> {
+        let validators = self
+            .inner
+            .lock()
+            .as_ref()
+            .map(|cached| cached.validators.clone());
+
+        match discover_conditional(client, &self.url, validators.as_ref()).await? {
+            Discovery::NotModified => self
+                .inner
+                .lock()
+                .as_mut()
+                .map(|cached| {
+                    cached.discovered_at = Utc::now();
+                    Arc::clone(&cached.provider)
+                })
+                // not modified since nothing, that's no answer to an unconditional request
+                .ok_or(DiscoveryError::BadStatus(reqwest::StatusCode::NOT_MODIFIED)),
+            Discovery::Modified {
+                provider,
+                validators,
+            } => {
+                let provider = Arc::new(provider);
+                *self.inner.lock() = Some(CachedProvider {
+                    provider: Arc::clone(&provider),
+                    validators,
+                    discovered_at: Utc::now(),
+                });
+                Ok(provider)
+            }
+        }
+    }
+}
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.2>
+///
+/// XRIs are not supported, URLs get a `http://` scheme if they don't have
+/// one and the fragment is removed.
+pub fn normalize_identifier(identifier: &str) -> anyhow::Result<String> {
+    let identifier = identifier.trim();
+    let identifier = identifier.strip_prefix(XRI_PREFIX).unwrap_or(identifier);
+
+    if identifier.starts_with(XRI_GLOBAL_CONTEXT_SYMBOLS) {
+        anyhow::bail!("xri identifiers are not supported");
+    }
+
+    let mut url = if identifier.starts_with("http://") || identifier.starts_with("https://") {
+        reqwest::Url::parse(identifier)
+    } else {
+        reqwest::Url::parse(&format!("http://{}", identifier))
+    }
+    .context("identifier is not a valid url")?;
+    url.set_fragment(None);
+
+    Ok(url.into())
+}
+
+/// Identifier to request authentication for and to expect in the positive assertion
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.1>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClaimedId {
+    /// The OP lets the user select an identifier, see [`OPENID_IDENTIFIER_SELECT`]
+    Select,
+    /// The user supplied an identifier that is delegated to the OP-Local Identifier `local_id`,
+    /// which is the claimed identifier itself if the service doesn't specify one
+    Delegated {
+        claimed_id: String,
+        local_id: String,
+    },
+}
+
+impl ClaimedId {
+    /// Value for `openid.claimed_id`
+    pub fn claimed_id(&self) -> &str {
+        match self {
+            ClaimedId::Select => OPENID_IDENTIFIER_SELECT,
+            ClaimedId::Delegated { claimed_id, .. } => claimed_id,
+        }
+    }
+    /// Value for `openid.identity`
+    pub fn identity(&self) -> &str {
+        match self {
+            ClaimedId::Select => OPENID_IDENTIFIER_SELECT,
+            ClaimedId::Delegated { local_id, .. } => local_id,
+        }
+    }
+}
+
+/// For an OP Identifier Element the user selects an identifier at the OP, for a
+/// Claimed Identifier Element the identifier the user supplied is requested and
+/// delegated to the OP-Local Identifier if there is one.
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.1>
+fn claimed_id_for(service: &Service, normalized_identifier: &str) -> ClaimedId {
+    match service.service_type {
+        ServiceType::Server => ClaimedId::Select,
+        ServiceType::SignOn => ClaimedId::Delegated {
+            claimed_id: normalized_identifier.to_string(),
+            local_id: service
+                .local_id
+                .clone()
+                .unwrap_or_else(|| normalized_identifier.to_string()),
+        },
+    }
+}
+
+/// Discover the provider for an identifier that went through [`normalize_identifier`]
+///
+/// Returns the provider to authenticate against and the claimed identifier to expect.
+pub async fn resolve_provider(
+    client: &reqwest::Client,
+    normalized_identifier: &str,
+) -> anyhow::Result<(Provider, ClaimedId)> {
+    let provider = discover(client, normalized_identifier)
+        .await
+        .with_context(|| format!("couldn't discover provider for `{}`", normalized_identifier))?;
+    let claimed_id = claimed_id_for(&provider.service, normalized_identifier);
+    Ok((provider, claimed_id))
+}
+
+#[cfg(test)]
+mod test {
+    use wiremock::matchers::{header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::openid::test_xrds::op_xrds;
+
+    #[test]
+    fn discovery_stats_format() {
+        let stats = DiscoveryStats {
+            url: "https://steamcommunity.com/openid",
+            status: reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            bytes: 42,
+            elapsed: Duration::from_millis(1337),
+        };
+        assert_eq!(
+            stats.to_string(),
+            "discovery of `https://steamcommunity.com/openid` returned 503 Service Unavailable with 42 bytes in 1337ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn discovery_works() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(op_xrds(Provider::steam().endpoint()), XRDS_CONTENT_TYPES[0]),
+            )
+            .mount(&server)
+            .await;
+
+        let provider = Provider::from_url(&reqwest::Client::new(), &server.uri()).await?;
+        assert_eq!(provider, Provider::steam());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discovery_rejects_unexpected_content_type() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(op_xrds(Provider::steam().endpoint()), "text/html"),
+            )
+            .mount(&server)
+            .await;
+
+        let err = Provider::from_url(&reqwest::Client::new(), &server.uri())
+            .await
+            .expect_err("html must be rejected");
+        assert!(err.to_string().contains("text/html"));
+        assert!(!err.is_transient());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discovery_rejects_invalid_endpoint() -> anyhow::Result<()> {
+        let xrds = op_xrds("not a url");
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(xrds, XRDS_CONTENT_TYPES[0]))
+            .mount(&server)
+            .await;
+
+        let err = Provider::from_url(&reqwest::Client::new(), &server.uri())
+            .await
+            .expect_err("an endpoint that isn't a url must be rejected");
+        assert!(matches!(err, DiscoveryError::ParseFailed(_)));
+        assert!(err.to_string().contains("\"not a url\" is not a valid url"));
+        assert!(!err.is_transient());
+
+        Ok(())
+    }
+
+    #[test]
+    fn xrds_content_types() {
+        assert!(is_xrds_content_type("application/xrds+xml"));
+        assert!(is_xrds_content_type("text/xml; charset=UTF-8"));
+        assert!(!is_xrds_content_type("text/html"));
+    }
+
+    #[tokio::test]
+    async fn discovery_error_includes_status() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let err = discover(&reqwest::Client::new(), &server.uri())
+            .await
+            .err()
+            .context("discovery should fail")?;
+        assert!(err.to_string().contains("503"));
+        assert!(matches!(
+            err,
+            DiscoveryError::BadStatus(reqwest::StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        assert!(err.is_transient());
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_identifiers() -> anyhow::Result<()> {
+        assert_eq!(normalize_identifier("example.com")?, "http://example.com/");
+        assert_eq!(
+            normalize_identifier("https://Example.com/user#frag")?,
+            "https://example.com/user"
+        );
+        assert_eq!(
+            normalize_identifier(" xri://example.com/user ")?,
+            "http://example.com/user"
+        );
+        assert!(normalize_identifier("=example").is_err());
+        assert!(normalize_identifier("xri://@example").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn delegated_claimed_id() {
+        let service = Service {
+            service_type: ServiceType::SignOn,
+            local_id: Some("https://op.example.com/u/1".to_string()),
+            ..Service::default()
+        };
+        let claimed_id = claimed_id_for(&service, "http://example.com/");
+        assert_eq!(claimed_id.claimed_id(), "http://example.com/");
+        assert_eq!(claimed_id.identity(), "https://op.example.com/u/1");
+
+        let service = Service {
+            service_type: ServiceType::SignOn,
+            ..Service::default()
+        };
+        let claimed_id = claimed_id_for(&service, "http://example.com/");
+        assert_eq!(claimed_id.claimed_id(), "http://example.com/");
+        assert_eq!(claimed_id.identity(), "http://example.com/");
+    }
+
+    #[tokio::test]
+    async fn resolve_op_identifier() -> anyhow::Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/openid"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(op_xrds(Provider::steam().endpoint()), XRDS_CONTENT_TYPES[0]),
+            )
+            .mount(&server)
+            .await;
+
+        let identifier = normalize_identifier(&format!("{}/openid#login", server.uri()))?;
+        let (provider, claimed_id) = resolve_provider(&reqwest::Client::new(), &identifier).await?;
+
+        assert_eq!(provider, Provider::steam());
+        assert_eq!(claimed_id, ClaimedId::Select);
+        assert_eq!(claimed_id.claimed_id(), OPENID_IDENTIFIER_SELECT);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_modified_reuses_cached_provider() -> anyhow::Result<()> {
+        const ETAG: &str = "\"xrds-v1\"";
+        const LAST_MODIFIED: &str = "Fri, 15 Sep 2023 11:23:46 GMT";
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("If-None-Match", ETAG))
+            .and(header_exists("If-Modified-Since"))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", ETAG)
+                    .insert_header("Last-Modified", LAST_MODIFIED)
+                    .set_body_raw(op_xrds(Provider::steam().endpoint()), XRDS_CONTENT_TYPES[0]),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let cache = ProviderCache::new(server.uri());
+        assert!(cache.provider().is_none());
+
+        let discovered = cache.refresh(&client).await?;
+        let revalidated = cache.refresh(&client).await?;
+        assert!(Arc::ptr_eq(&discovered, &revalidated));
+        assert_eq!(*revalidated, Provider::steam());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refresh_replaces_seeded_provider() -> anyhow::Result<()> {
+        let xrds = op_xrds("https://op.example.com/openid/login");
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"xrds-v1\"")
+                    .set_body_raw(xrds, XRDS_CONTENT_TYPES[0]),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let cache = ProviderCache::with_provider(server.uri(), Provider::steam());
+        let refreshed = cache.refresh(&client).await?;
+        assert_eq!(refreshed.endpoint(), "https://op.example.com/openid/login");
+
+        // the OP is gone now, the refreshed provider stays in use
+        assert!(cache.refresh(&client).await.is_err());
+        let cached = cache.provider().context("provider was dropped")?;
+        assert!(Arc::ptr_eq(&refreshed, &cached));
+
+        Ok(())
+    }
+}
@@ -39,15 +39,24 @@
 //!
 //! An alternate Identifier for an end user that is local to a particular OP and thus not necessarily under the end user's control.
 
-pub(crate) mod constants;
+mod associate;
+pub mod constants;
+mod discovery;
 mod params;
+pub mod prelude;
 mod provider;
 mod response;
+mod signature;
+#[cfg(test)]
+pub(crate) mod test_xrds;
 mod util;
 mod validate;
 
-pub(crate) use params::*;
-pub(crate) use provider::*;
-pub(crate) use response::*;
-pub(crate) use util::*;
-pub(crate) use validate::*;
+pub use associate::*;
+pub use discovery::*;
+pub use params::*;
+pub use provider::*;
+pub use response::*;
+pub use signature::*;
+pub use util::*;
+pub use validate::*;
@@ -1,31 +1,29 @@
 use anyhow::Context;
 
 use crate::openid::constants::*;
-use crate::openid::Provider;
+use crate::openid::{ClaimedId, Provider};
 
-/// Static params, missing `return_to` and `realm`.
+/// Static params, missing `claimed_id`, `identity`, `return_to` and `realm`.
 ///
 /// See [`make_auth_req_params`].
-const OPENID_STATIC_PARAMS: [Params<'static>; 4] = [
+const OPENID_STATIC_PARAMS: [Params<'static>; 2] = [
     // Not using immediate mode
     Params::new(OPENID_MODE, "checkid_setup"),
     // Using OpenID 2.0
     Params::new(OPENID_NAMESPACE, OPENID_AUTH_NAMESPACE),
-    Params::new(OPENID_IDENTITY, OPENID_IDENTIFIER_SELECT),
-    Params::new(OPENID_CLAIMED_ID, OPENID_IDENTIFIER_SELECT),
 ];
 
 #[derive(Clone)]
-pub(crate) struct Params<'a> {
+pub struct Params<'a> {
     key: &'a str,
     value: &'a str,
 }
 
 impl<'a> Params<'a> {
-    pub(crate) const fn new(key: &'a str, value: &'a str) -> Params<'a> {
+    pub const fn new(key: &'a str, value: &'a str) -> Params<'a> {
         Params { key, value }
     }
-    pub(crate) const fn into_pair(self) -> (&'a str, &'a str) {
+    pub const fn into_pair(self) -> (&'a str, &'a str) {
         (self.key, self.value)
     }
 }
@@ -34,7 +32,8 @@ impl<'a> Params<'a> {
 ///
 /// # Example
 ///
-/// All the parameters are static except `return_to` and `realm`.
+/// All the parameters are static except `claimed_id`, `identity`, `return_to` and `realm`.
+/// With [`ClaimedId::Select`] the OP lets the user select an identifier:
 ///
 /// ```json
 /// {
@@ -46,43 +45,177 @@ impl<'a> Params<'a> {
 ///   "openid.return_to": "http://localhost:3000/auth/steam/callback",
 /// }
 /// ```
-fn make_auth_req_params<'a>(realm: &'a str, return_to: &'a str) -> Vec<Params<'a>> {
-    let mut params = Vec::with_capacity(OPENID_STATIC_PARAMS.len() + 2);
+///
+/// With an `assoc_handle` the OP is asked to sign the assertion with that association.
+fn make_auth_req_params<'a>(
+    claimed_id: &'a ClaimedId,
+    realm: &'a str,
+    return_to: &'a str,
+    assoc_handle: Option<&'a str>,
+) -> Vec<Params<'a>> {
+    let mut params = Vec::with_capacity(OPENID_STATIC_PARAMS.len() + 5);
     params.extend_from_slice(&OPENID_STATIC_PARAMS);
+    params.push(Params::new(OPENID_IDENTITY, claimed_id.identity()));
+    params.push(Params::new(OPENID_CLAIMED_ID, claimed_id.claimed_id()));
     params.push(Params::new(OPENID_REALM, realm));
     params.push(Params::new(OPENID_RETURN_TO, return_to));
+    if let Some(assoc_handle) = assoc_handle {
+        params.push(Params::new(OPENID_ASSOCIATION_HANDLE, assoc_handle));
+    }
     params
 }
 
-/// Build the url the user should be redirected to to authenticate.
+/// Prefix of a realm host that trusts every subdomain, e.g. `http://*.example.com/`
+const WILDCARD_PREFIX: &str = "*.";
+
+/// Realm the user is asked to trust, validated once when it is parsed
 ///
-/// See [`make_auth_req_params`]
-pub(crate) fn make_auth_req_url(
-    provider: &Provider,
-    realm: &str,
-    return_to: &str,
-) -> anyhow::Result<String> {
-    let return_to = reqwest::Url::parse(return_to).context("couldn't parse return_to url")?;
-    let realm = reqwest::Url::parse(realm).context("couldn't parse realm url")?;
+/// The realm may start its host with a [wildcard](WILDCARD_PREFIX) to cover every subdomain
+/// of the rest of it.
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.2>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Realm(reqwest::Url);
 
-    let return_to_host = return_to
-        .host_str()
-        .context("return_to url is missing host part")?;
-    let realm_host = return_to
-        .host_str()
-        .context("realm url is missing host part")?;
+impl Realm {
+    pub fn parse(realm: &str) -> anyhow::Result<Realm> {
+        let realm = reqwest::Url::parse(realm).context("couldn't parse realm url")?;
+        if !matches!(realm.scheme(), "http" | "https") {
+            anyhow::bail!("realm url must be http or https");
+        }
+        let host = realm.host_str().context("realm url is missing host part")?;
+        if realm.fragment().is_some() {
+            anyhow::bail!("realm url must not contain a fragment");
+        }
+        match host.strip_prefix(WILDCARD_PREFIX) {
+            Some(base) if base.contains('*') || !base.contains('.') => {
+                anyhow::bail!("wildcard realm `{}` is too broad", host);
+            }
+            None if host.contains('*') => {
+                anyhow::bail!("realm may only contain a wildcard at the start of its host");
+            }
+            _ => {}
+        }
+        Ok(Realm(realm))
+    }
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+    /// Check that `return_to` is covered by the realm
+    fn covers(&self, return_to: &reqwest::Url) -> anyhow::Result<()> {
+        let realm = &self.0;
+        let return_to_host = return_to
+            .host_str()
+            .context("return_to url is missing host part")?;
+        let realm_host = realm.host_str().context("realm url is missing host part")?;
 
-    if return_to_host != realm_host {
-        anyhow::bail!("host part of realm and return_to urls don't match");
+        if return_to.scheme() != realm.scheme() {
+            anyhow::bail!("scheme part of realm and return_to urls don't match");
+        }
+        if return_to.port_or_known_default() != realm.port_or_known_default() {
+            anyhow::bail!("port part of realm and return_to urls don't match");
+        }
+
+        match realm_host.strip_prefix(WILDCARD_PREFIX) {
+            Some(base) => {
+                let is_subdomain = return_to_host
+                    .strip_suffix(base)
+                    .and_then(|sub| sub.strip_suffix('.'))
+                    .is_some_and(|sub| !sub.is_empty());
+                if !is_subdomain {
+                    anyhow::bail!("host of return_to url isn't a subdomain of the wildcard realm");
+                }
+            }
+            None if return_to_host != realm_host => {
+                anyhow::bail!("host part of realm and return_to urls don't match");
+            }
+            None => {}
+        }
+
+        if !path_is_below(return_to.path(), realm.path()) {
+            anyhow::bail!("path of return_to url isn't below the path of the realm");
+        }
+        Ok(())
     }
-    if return_to.scheme() != realm.scheme() {
-        anyhow::bail!("scheme part of realm and return_to urls don't match");
+}
+
+/// Whether `path` is `realm_path` or below it, `/auth` covers `/auth/callback` but not `/authevil`
+fn path_is_below(path: &str, realm_path: &str) -> bool {
+    path.strip_prefix(realm_path)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || realm_path.ends_with('/'))
+}
+
+/// `return_to` url that is covered by its [`Realm`], validated once when it is parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReturnTo(reqwest::Url);
+
+impl ReturnTo {
+    pub fn parse(realm: &Realm, return_to: &str) -> anyhow::Result<ReturnTo> {
+        let return_to = reqwest::Url::parse(return_to).context("couldn't parse return_to url")?;
+        realm
+            .covers(&return_to)
+            .context("return_to url doesn't match realm")?;
+        Ok(ReturnTo(return_to))
     }
+    /// Append `params` to the query, e.g. a nonce
+    ///
+    /// The realm only covers the scheme, host, port and path, so the result is still covered.
+    pub fn with_params(&self, params: &[(&str, &str)]) -> ReturnTo {
+        let mut return_to = self.0.clone();
+        return_to.query_pairs_mut().extend_pairs(params);
+        ReturnTo(return_to)
+    }
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+    /// Check that `received`, e.g. the `openid.return_to` of an assertion, is this url
+    /// with some params appended (see [`ReturnTo::with_params`])
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.1>
+    pub fn ensure_matches(&self, received: &str) -> anyhow::Result<()> {
+        let expected = &self.0;
+        let received = reqwest::Url::parse(received).context("couldn't parse return_to url")?;
+        if received.scheme() != expected.scheme()
+            || received.host_str() != expected.host_str()
+            || received.port_or_known_default() != expected.port_or_known_default()
+            || received.path() != expected.path()
+        {
+            anyhow::bail!("return_to url `{}` isn't `{}`", received, expected);
+        }
+        for param in expected.query_pairs() {
+            if !received.query_pairs().any(|received| received == param) {
+                anyhow::bail!("return_to url is missing the param `{}`", param.0);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Build the url the user should be redirected to to authenticate as `claimed_id`.
+///
+/// See [`make_auth_req_params`]
+pub fn make_auth_req_url(
+    provider: &Provider,
+    claimed_id: &ClaimedId,
+    realm: &Realm,
+    return_to: &ReturnTo,
+) -> anyhow::Result<String> {
+    make_associated_auth_req_url(provider, claimed_id, realm, return_to, None)
+}
 
-    let params = make_auth_req_params(realm.as_str(), return_to.as_str());
+/// Same as [`make_auth_req_url`], with an `assoc_handle` established through
+/// [`associate`](crate::openid::associate) the assertion can be verified locally
+pub fn make_associated_auth_req_url(
+    provider: &Provider,
+    claimed_id: &ClaimedId,
+    realm: &Realm,
+    return_to: &ReturnTo,
+    assoc_handle: Option<&str>,
+) -> anyhow::Result<String> {
+    let params = make_auth_req_params(claimed_id, realm.as_str(), return_to.as_str(), assoc_handle);
     let params: Vec<_> = params.into_iter().map(Params::into_pair).collect();
 
-    let url = reqwest::Url::parse_with_params(&provider.service.endpoint, params)
+    let url = reqwest::Url::parse_with_params(provider.endpoint(), params)
         .context("couldn't parse provider endpoint with query params into a url")?;
 
     Ok(url.into())
@@ -110,7 +243,13 @@ mod test {
 
         let provider = Provider::steam();
 
-        let url = make_auth_req_url(&provider, REALM, RETURN_TO)?;
+        let realm = Realm::parse(REALM)?;
+        let url = make_auth_req_url(
+            &provider,
+            &ClaimedId::Select,
+            &realm,
+            &ReturnTo::parse(&realm, RETURN_TO)?,
+        )?;
 
         let (expected_url, expected_query) = sorted_query_pairs(EXPECTED_URL)?;
         let (url, query) = sorted_query_pairs(&url)?;
@@ -133,4 +272,127 @@ mod test {
         assert_eq!(url.origin(), expected_url.origin());
         Ok(())
     }
+
+    #[test]
+    fn wildcard_realm() -> anyhow::Result<()> {
+        const REALM: &str = "http://*.example.com/";
+        let provider = Provider::steam();
+        let realm = Realm::parse(REALM)?;
+
+        let return_to = ReturnTo::parse(&realm, "http://app.example.com/callback")?;
+        let url = make_auth_req_url(&provider, &ClaimedId::Select, &realm, &return_to)?;
+        let (_, query) = sorted_query_pairs(&url)?;
+        assert!(query.contains(&("openid.realm".to_string(), REALM.to_string())));
+        ReturnTo::parse(&realm, "http://a.b.example.com/callback")?;
+
+        for return_to in [
+            "http://example.com/callback",
+            "http://evil.com/callback",
+            "http://evilexample.com/callback",
+            "http://app.example.com.evil.com/callback",
+            "https://app.example.com/callback",
+            "http://app.example.com:8080/callback",
+        ] {
+            assert!(ReturnTo::parse(&realm, return_to).is_err(), "{}", return_to);
+        }
+
+        for realm in [
+            "http://*.com/",
+            "http://app.*.example.com/",
+            "http://*.*.example.com/",
+        ] {
+            assert!(Realm::parse(realm).is_err(), "{}", realm);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_realm_is_rejected() {
+        for realm in [
+            "localhost:3000",
+            "/auth/steam/callback",
+            "ftp://localhost:3000/",
+            "http://localhost:3000/#fragment",
+        ] {
+            assert!(Realm::parse(realm).is_err(), "{}", realm);
+        }
+    }
+
+    #[test]
+    fn realm_host_must_match() -> anyhow::Result<()> {
+        let realm = Realm::parse("http://localhost:3000/")?;
+        assert!(ReturnTo::parse(&realm, "http://example.com:3000/auth/steam/callback/").is_err());
+        assert!(ReturnTo::parse(&realm, "/auth/steam/callback/").is_err());
+
+        let realm = Realm::parse("http://localhost:3000/auth/")?;
+        assert!(ReturnTo::parse(&realm, "http://localhost:3000/api/callback").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn realm_path_must_end_at_a_segment() -> anyhow::Result<()> {
+        let realm = Realm::parse("http://localhost:3000/auth")?;
+        ReturnTo::parse(&realm, "http://localhost:3000/auth")?;
+        ReturnTo::parse(&realm, "http://localhost:3000/auth/steam/callback")?;
+        assert!(ReturnTo::parse(&realm, "http://localhost:3000/authevil").is_err());
+        assert!(ReturnTo::parse(&realm, "http://localhost:3000/authevil/callback").is_err());
+
+        let realm = Realm::parse("http://localhost:3000/auth/")?;
+        ReturnTo::parse(&realm, "http://localhost:3000/auth/steam/callback")?;
+        assert!(ReturnTo::parse(&realm, "http://localhost:3000/auth").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn return_to_with_params_keeps_path() -> anyhow::Result<()> {
+        let realm = Realm::parse("http://localhost:3000/")?;
+        let return_to = ReturnTo::parse(&realm, "http://localhost:3000/auth/steam/callback")?;
+        assert_eq!(
+            return_to.with_params(&[("nonce", "a b")]).as_str(),
+            "http://localhost:3000/auth/steam/callback?nonce=a+b"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn delegated_identifier_is_requested() -> anyhow::Result<()> {
+        let realm = Realm::parse("http://localhost:3000/")?;
+        let return_to = ReturnTo::parse(&realm, "http://localhost:3000/auth/generic/callback")?;
+        let claimed_id = ClaimedId::Delegated {
+            claimed_id: "http://example.com/".to_string(),
+            local_id: "https://op.example.com/u/1".to_string(),
+        };
+        let url = make_auth_req_url(&Provider::steam(), &claimed_id, &realm, &return_to)?;
+        let (_, query) = sorted_query_pairs(&url)?;
+        assert!(query.contains(&(
+            OPENID_CLAIMED_ID.to_string(),
+            "http://example.com/".to_string()
+        )));
+        assert!(query.contains(&(
+            OPENID_IDENTITY.to_string(),
+            "https://op.example.com/u/1".to_string()
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn return_to_must_match() -> anyhow::Result<()> {
+        let realm = Realm::parse("http://localhost:3000/")?;
+        let return_to = ReturnTo::parse(&realm, "http://localhost:3000/callback?app=1")?;
+
+        return_to.ensure_matches(return_to.as_str())?;
+        return_to.ensure_matches(return_to.with_params(&[("nonce", "n")]).as_str())?;
+        for received in [
+            "https://localhost:3000/callback?app=1",
+            "http://localhost:3001/callback?app=1",
+            "http://example.com:3000/callback?app=1",
+            "http://localhost:3000/callback/other?app=1",
+            "http://localhost:3000/callback",
+            "http://localhost:3000/callback?app=2",
+            "not a url",
+        ] {
+            assert!(return_to.ensure_matches(received).is_err(), "{}", received);
+        }
+        Ok(())
+    }
 }
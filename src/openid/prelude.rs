@@ -0,0 +1,27 @@
+//! Everything needed to authenticate users against an OP
+//!
+//! ```no_run
+//! use complainer_api::openid::prelude::*;
+//!
+//! # async fn example(client: &reqwest::Client, callback_query: &str) -> anyhow::Result<()> {
+//! let provider = Provider::from_url(client, "https://steamcommunity.com/openid").await?;
+//! let realm = Realm::parse("https://example.com")?;
+//! let return_to = ReturnTo::parse(&realm, "https://example.com/callback")?;
+//!
+//! // send the user here, the OP returns them to `return_to`
+//! let auth_url = make_auth_req_url(&provider, &ClaimedId::Select, &realm, &return_to)?;
+//!
+//! // back at `return_to`, check the assertion and let the OP verify its signature
+//! let assertion: PositiveAssertion = serde_urlencoded::from_str(callback_query)?;
+//! assertion.validate(&provider)?;
+//! let response = verify_against_provider(client, &provider, &assertion).await?;
+//! assert!(response.is_valid());
+//! # Ok(())
+//! # }
+//! ```
+
+pub use crate::openid::{
+    make_auth_req_url, verify_against_provider, ClaimedId, PositiveAssertion, Provider, Realm,
+    ReturnTo, VerifyResponse,
+};
+pub use crate::openid_next::OpenIdMode;
@@ -1,9 +1,12 @@
+use std::fmt::Display;
+
 use anyhow::Context;
 use roxmltree::Node;
 use serde::Serialize;
 
 use crate::openid::constants::{
     OPENID_AUTH_NAMESPACE, OPENID_PRIORITY_ATTRIBUTE, OPENID_PROVIDER_IDENTIFIER,
+    OPENID_SIGNON_IDENTIFIER,
 };
 use crate::openid::util::xml::*;
 
@@ -14,24 +17,55 @@ const TAG_NAME_XRD: &str = "XRD";
 const TAG_NAME_SERVICE: &str = "Service";
 const TAG_NAME_TYPE: &str = "Type";
 const TAG_NAME_URI: &str = "URI";
+const TAG_NAME_LOCAL_ID: &str = "LocalID";
 
 const EXPECTED_NAMESPACES: [Namespace; 2] = [
     Namespace::new(None, NAMESPACE_DEFAULT),
     Namespace::new(Some("xrds"), NAMESPACE_XRDS),
 ];
 
-/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2.1.2>
+/// Which kind of element a [`Service`] was discovered from
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2.1>
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceType {
+    /// OP Identifier Element, see [`OPENID_PROVIDER_IDENTIFIER`]
+    ///
+    /// The user selects an identifier at the OP.
+    #[default]
+    Server,
+    /// Claimed Identifier Element, see [`OPENID_SIGNON_IDENTIFIER`]
+    ///
+    /// The user supplied their own identifier.
+    SignOn,
+}
+
+impl ServiceType {
+    /// OP Identifier Elements take precedence if a service advertises both types
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2.2>
+    fn from_types(types: &[&str]) -> Option<ServiceType> {
+        if types.contains(&OPENID_PROVIDER_IDENTIFIER) {
+            Some(ServiceType::Server)
+        } else if types.contains(&OPENID_SIGNON_IDENTIFIER) {
+            Some(ServiceType::SignOn)
+        } else {
+            None
+        }
+    }
+}
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2.1>
 ///
-/// A Claimed Identifier Element is an `<xrd:Service>` element with the following information:
-/// - An `<xrd:Type>` tag whose text content is `http://specs.openid.net/auth/2.0/signon`.
-/// - An `<xrd:URI>` tag whose text content is the OP Endpoint URL.
-/// - An `<xrd:LocalID>` tag (optional) whose text content is the OP-Local Identifier.
-#[derive(Debug, Default, Clone, Serialize)]
-pub(crate) struct Service {
-    pub(crate) version: String,
-    pub(crate) endpoint: String,
-    pub(crate) local_id: Option<String>,
-    pub(crate) priority: Option<i32>,
+/// Either an OP Identifier Element or a Claimed Identifier Element, see [`ServiceType`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Service {
+    pub service_type: ServiceType,
+    pub version: String,
+    pub endpoint: String,
+    pub local_id: Option<String>,
+    pub priority: Option<i32>,
 }
 
 impl Service {
@@ -47,35 +81,162 @@ impl Service {
             .parse()
             .context("couldn't parse priority as an integer")?;
 
-        let service_children = get_child_set(service_node, &[TAG_NAME_URI, TAG_NAME_TYPE])
-            .context("get type and uri as only children of service element")?;
-
-        let service_type = get_only_text_child(*service_children.get(TAG_NAME_TYPE).unwrap())
-            .context("couldn't get text of type element in service")?
-            .to_string();
+        // a service may advertise several types and list several uris in order of preference
+        let mut types = Vec::new();
+        let mut uris = Vec::new();
+        let mut local_id = None;
+        for child in service_node.children().filter(|c| c.is_element()) {
+            match child.tag_name().name() {
+                TAG_NAME_TYPE => types.push(
+                    get_only_text_child(child)
+                        .context("couldn't get text of type element in service")?,
+                ),
+                TAG_NAME_URI => uris.push(
+                    get_only_text_child(child)
+                        .context("couldn't get text of uri element in service")?,
+                ),
+                TAG_NAME_LOCAL_ID if local_id.is_none() => {
+                    local_id = Some(
+                        get_only_text_child(child)
+                            .context("couldn't get text of local id element in service")?
+                            .to_string(),
+                    );
+                }
+                _ => anyhow::bail!("service element has a child with an unexpected tag name"),
+            }
+        }
 
         // https://github.com/havard/node-openid/blob/672ea6e1b25e96c4a8e4f9deb74d38487c85ac32/openid.js#L287-L290
-        if service_type.as_str() != OPENID_PROVIDER_IDENTIFIER {
-            anyhow::bail!("text in type tag does not match spec");
-        }
+        let Some(service_type) = ServiceType::from_types(&types) else {
+            anyhow::bail!("no type tag in service matches spec");
+        };
+        // the OP-Local Identifier only makes sense for a user supplied identifier
+        let local_id = local_id.filter(|_| service_type == ServiceType::SignOn);
 
-        let endpoint = get_only_text_child(*service_children.get(TAG_NAME_URI).unwrap())
-            .context("couldn't get text of uri element in service")?
-            .to_string();
+        let Some(endpoint) = uris.first() else {
+            anyhow::bail!("service element doesn't have an uri element");
+        };
+        let endpoint = parse_endpoint(endpoint).context("invalid uri element in service")?;
 
         Ok(Service {
+            service_type,
             endpoint,
             version: OPENID_AUTH_NAMESPACE.to_string(),
-            local_id: None,
+            local_id,
             priority: Some(priority),
         })
     }
+    /// Services without a priority come last
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.7.3.2>
+    pub fn priority_or_default(&self) -> i32 {
+        self.priority.unwrap_or(i32::MAX)
+    }
+}
+
+/// Parse the `<URI>` of a service into a normalized absolute http(s) url
+///
+/// Control characters are rejected up front, the url parser would silently
+/// drop tabs and newlines instead. Whitespace around the text is ignored.
+fn parse_endpoint(uri: &str) -> anyhow::Result<String> {
+    let uri = uri.trim();
+    if let Some(c) = uri.chars().find(|c| c.is_control()) {
+        anyhow::bail!(
+            "op endpoint {:?} contains the control character {:?}",
+            uri,
+            c
+        );
+    }
+    let endpoint = reqwest::Url::parse(uri)
+        .with_context(|| format!("op endpoint {:?} is not a valid url", uri))?;
+    if !matches!(endpoint.scheme(), "http" | "https") {
+        anyhow::bail!(
+            "op endpoint must use http or https, got `{}`",
+            endpoint.scheme()
+        );
+    }
+    Ok(endpoint.into())
+}
+
+/// A namespace declared on the root element of the XRDS document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeclaredNamespace {
+    pub name: Option<String>,
+    pub uri: String,
+}
+
+/// Everything a `<xrd:Service>` element advertises, parsed without validating it
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceMeta {
+    pub priority: Option<String>,
+    pub types: Vec<String>,
+    pub uris: Vec<String>,
 }
 
-pub(crate) struct Provider {
+impl ServiceMeta {
+    fn from_node(service_node: Node) -> ServiceMeta {
+        let texts_of = |tag_name: &str| {
+            service_node
+                .children()
+                .filter(|c| c.is_element() && c.tag_name().name() == tag_name)
+                .filter_map(|c| c.text())
+                .map(|text| text.trim().to_string())
+                .collect::<Vec<_>>()
+        };
+        ServiceMeta {
+            priority: service_node
+                .attribute(OPENID_PRIORITY_ATTRIBUTE)
+                .map(str::to_string),
+            types: texts_of(TAG_NAME_TYPE),
+            uris: texts_of(TAG_NAME_URI),
+        }
+    }
+}
+
+/// What the OP actually returned during discovery, including the services that weren't selected
+///
+/// Meant for diagnosing OPs whose XRDS structure differs from what [`Provider::from_xml`] expects.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ProviderMeta {
+    pub raw: String,
+    pub namespaces: Vec<DeclaredNamespace>,
+    pub services: Vec<ServiceMeta>,
+}
+
+impl ProviderMeta {
+    fn from_document(doc: &roxmltree::Document, raw: &str) -> ProviderMeta {
+        let root = doc.root_element();
+        let namespaces = root
+            .namespaces()
+            .map(|ns| DeclaredNamespace {
+                name: ns.name().map(str::to_string),
+                uri: ns.uri().to_string(),
+            })
+            .collect();
+        let services = root
+            .descendants()
+            .filter(|n| n.is_element() && n.tag_name().name() == TAG_NAME_SERVICE)
+            .map(ServiceMeta::from_node)
+            .collect();
+        ProviderMeta {
+            raw: raw.to_string(),
+            namespaces,
+            services,
+        }
+    }
+    /// Parse only as much as needed to describe the document, this succeeds for
+    /// any well-formed xml, even if [`Provider::from_xml`] would reject it
+    pub fn from_xml(xml: &str) -> anyhow::Result<ProviderMeta> {
+        let doc = roxmltree::Document::parse(xml).context("couldn't parse input document xml")?;
+        Ok(ProviderMeta::from_document(&doc, xml))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Provider {
     // TODO: This should be a `Vec<Service>` as a provider can expose
     //       multiple services and we should select them by their priority
-    pub(crate) service: Service,
+    pub service: Service,
 }
 
 impl Provider {
@@ -91,40 +252,107 @@ impl Provider {
             service: Service::from_node(service_node)?,
         })
     }
-    pub(crate) fn from_xml(xml: &str) -> anyhow::Result<Provider> {
+    pub fn from_xml(xml: &str) -> anyhow::Result<Provider> {
         let doc = roxmltree::Document::parse(xml).context("couldn't parse input document xml")?;
-
-        namespaces_eq(&doc, &EXPECTED_NAMESPACES).context("namespaces validation failed")?;
+        Provider::from_document(&doc)
+    }
+    /// Like [`Provider::from_xml`] but also returns everything the document contained
+    pub fn from_xml_with_raw(xml: &str) -> anyhow::Result<(Provider, ProviderMeta)> {
+        let doc = roxmltree::Document::parse(xml).context("couldn't parse input document xml")?;
+        let provider = Provider::from_document(&doc)?;
+        Ok((provider, ProviderMeta::from_document(&doc, xml)))
+    }
+    fn from_document(doc: &roxmltree::Document) -> anyhow::Result<Provider> {
+        namespaces_contain(doc, &EXPECTED_NAMESPACES).context("namespaces validation failed")?;
 
         let root_node = doc.root_element();
 
-        let xrd_node = get_only_child(root_node, TAG_NAME_XRD)
-            .context("get xrd element as only child of root element")?;
+        // only the last xrd is authoritative, the ones before it are the result of
+        // following redirects or references during resolution
+        // http://docs.oasis-open.org/xri/2.0/specs/xri-resolution-V2.0.html#_Ref129424065
+        let xrd_node = get_children_exact(root_node, TAG_NAME_XRD)
+            .context("get xrd elements as only children of root element")?
+            .pop()
+            .context("root element doesn't have any xrd element")?;
 
         Provider::from_node(xrd_node)
     }
+    /// OP Endpoint URL of the selected service
+    pub fn endpoint(&self) -> &str {
+        &self.service.endpoint
+    }
+    /// Every service of the provider, only the selected one for now
+    pub const fn services(&self) -> &[Service] {
+        std::slice::from_ref(&self.service)
+    }
+}
+
+/// Whether two OP Endpoint URLs point to the same place
+///
+/// Both are normalized by parsing them as urls, which lowercases the scheme and host and
+/// drops the default port, and a trailing slash of the path is ignored.
+pub fn same_endpoint(a: &str, b: &str) -> bool {
+    fn normalize(endpoint: &str) -> Option<(String, String, Option<u16>, String, String)> {
+        let url = reqwest::Url::parse(endpoint).ok()?;
+        Some((
+            url.scheme().to_string(),
+            url.host_str()?.to_string(),
+            url.port(),
+            url.path().trim_end_matches('/').to_string(),
+            url.query().unwrap_or_default().to_string(),
+        ))
+    }
+    a == b || normalize(a).is_some_and(|a| Some(a) == normalize(b))
+}
+
+/// Displays the endpoint of the selected service
+impl Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.endpoint())
+    }
 }
 
 impl Provider {
-    #[cfg(test)]
-    pub(crate) fn steam() -> Provider {
+    /// Known-good provider as discovered from <https://steamcommunity.com/openid>,
+    /// for when discovery isn't possible, e.g. during offline development
+    pub fn steam() -> Provider {
         let service = Service {
-            version: "http://specs.openid.net/auth/2.0/server".to_string(),
+            service_type: ServiceType::Server,
+            version: OPENID_AUTH_NAMESPACE.to_string(),
             endpoint: "https://steamcommunity.com/openid/login".to_string(),
             local_id: None,
             priority: Some(0),
         };
         Provider { service }
     }
+    /// Provider with a single OP Identifier Element for an already known OP Endpoint URL,
+    /// for OPs whose discovery is unreliable. No XRDS is fetched or parsed.
+    ///
+    /// The endpoint must be an absolute `https` url.
+    pub fn from_endpoint(url: &str) -> anyhow::Result<Provider> {
+        let endpoint = reqwest::Url::parse(url).context("op endpoint is not a valid url")?;
+        if endpoint.scheme() != "https" {
+            anyhow::bail!("op endpoint must use https, got `{}`", endpoint.scheme());
+        }
+        if endpoint.fragment().is_some() {
+            anyhow::bail!("op endpoint must not have a fragment");
+        }
+        let service = Service {
+            service_type: ServiceType::Server,
+            version: OPENID_AUTH_NAMESPACE.to_string(),
+            endpoint: url.to_string(),
+            local_id: None,
+            priority: None,
+        };
+        Ok(Provider { service })
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    #[test]
-    fn parse_steam_response() -> anyhow::Result<()> {
-        const EXAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+    const EXAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
 <xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
     <XRD>
         <Service priority="0">
@@ -134,7 +362,15 @@ mod test {
     </XRD>
 </xrds:XRDS>"#;
 
+    #[test]
+    fn parse_steam_response() -> anyhow::Result<()> {
         let provider = Provider::from_xml(EXAMPLE)?;
+        assert_eq!(provider, Provider::steam());
+        assert_eq!(
+            provider.endpoint(),
+            "https://steamcommunity.com/openid/login"
+        );
+
         let service = provider.service;
 
         assert_eq!(service.version, OPENID_AUTH_NAMESPACE);
@@ -144,4 +380,255 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn sort_services_by_priority() {
+        let service = |priority| Service {
+            priority,
+            ..Service::default()
+        };
+        let mut services = [service(None), service(Some(10)), service(Some(0))];
+        services.sort_by_key(Service::priority_or_default);
+        assert_eq!(
+            services.iter().map(|s| s.priority).collect::<Vec<_>>(),
+            [Some(0), Some(10), None]
+        );
+
+        assert_eq!(Provider::steam().services(), [Provider::steam().service]);
+    }
+
+    #[test]
+    fn endpoints_are_normalized() {
+        let steam = "https://steamcommunity.com/openid/login";
+        for same in [
+            "https://steamcommunity.com/openid/login/",
+            "https://SteamCommunity.com/openid/login",
+            "https://steamcommunity.com:443/openid/login",
+            "HTTPS://steamcommunity.com/openid/login",
+        ] {
+            assert!(same_endpoint(steam, same), "{}", same);
+        }
+        for other in [
+            "http://steamcommunity.com/openid/login",
+            "https://steamcommunity.com:8443/openid/login",
+            "https://steamcommunity.com/openid/Login",
+            "https://steamcommunity.com/openid/login?id=1",
+            "https://evil.example.com/openid/login",
+            "not a url",
+        ] {
+            assert!(!same_endpoint(steam, other), "{}", other);
+        }
+    }
+
+    #[test]
+    fn parse_with_raw() -> anyhow::Result<()> {
+        let (provider, meta) = Provider::from_xml_with_raw(EXAMPLE)?;
+        assert_eq!(provider, Provider::steam());
+
+        assert_eq!(meta.raw, EXAMPLE);
+        assert_eq!(meta.namespaces.len(), 2);
+        assert!(meta
+            .namespaces
+            .iter()
+            .any(|ns| ns.name.as_deref() == Some("xrds") && ns.uri == NAMESPACE_XRDS));
+        assert_eq!(
+            meta.services,
+            vec![ServiceMeta {
+                priority: Some("0".to_string()),
+                types: vec![OPENID_PROVIDER_IDENTIFIER.to_string()],
+                uris: vec!["https://steamcommunity.com/openid/login".to_string()],
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn meta_of_rejected_document() -> anyhow::Result<()> {
+        const TWO_SERVICES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
+    <XRD>
+        <Service priority="10">
+            <Type>http://specs.openid.net/auth/2.0/signon</Type>
+            <URI>https://example.com/a</URI>
+        </Service>
+        <Service>
+            <URI>https://example.com/b</URI>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        assert!(Provider::from_xml_with_raw(TWO_SERVICES).is_err());
+
+        let meta = ProviderMeta::from_xml(TWO_SERVICES)?;
+        assert_eq!(meta.services.len(), 2);
+        assert_eq!(meta.services[0].priority.as_deref(), Some("10"));
+        assert_eq!(meta.services[1].priority, None);
+        assert!(meta.services[1].types.is_empty());
+        assert_eq!(meta.services[1].uris, ["https://example.com/b"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn extra_namespaces_are_allowed() -> anyhow::Result<()> {
+        const EXTRA_NAMESPACE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)" xmlns:ds="http://www.w3.org/2000/09/xmldsig#">
+    <XRD>
+        <Service priority="0">
+            <Type>http://specs.openid.net/auth/2.0/server</Type>
+            <URI>https://steamcommunity.com/openid/login</URI>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        let provider = Provider::from_xml(EXTRA_NAMESPACE)?;
+        assert_eq!(provider, Provider::steam());
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_namespace_is_rejected() {
+        const MISSING_DEFAULT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns:ds="http://www.w3.org/2000/09/xmldsig#">
+    <XRD>
+        <Service priority="0">
+            <Type>http://specs.openid.net/auth/2.0/server</Type>
+            <URI>https://steamcommunity.com/openid/login</URI>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        assert!(Provider::from_xml(MISSING_DEFAULT).is_err());
+    }
+
+    #[test]
+    fn last_xrd_is_selected() -> anyhow::Result<()> {
+        const TWO_XRDS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
+    <XRD>
+        <Service priority="0">
+            <Type>http://specs.openid.net/auth/2.0/server</Type>
+            <URI>https://example.com/openid/login</URI>
+        </Service>
+    </XRD>
+    <XRD>
+        <Service priority="0">
+            <Type>http://specs.openid.net/auth/2.0/server</Type>
+            <URI>https://steamcommunity.com/openid/login</URI>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        let provider = Provider::from_xml(TWO_XRDS)?;
+        assert_eq!(provider, Provider::steam());
+
+        Ok(())
+    }
+
+    #[test]
+    fn service_with_multiple_types() -> anyhow::Result<()> {
+        const TWO_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
+    <XRD>
+        <Service priority="0">
+            <Type>http://openid.net/extensions/sreg/1.1</Type>
+            <Type>http://specs.openid.net/auth/2.0/server</Type>
+            <URI>https://steamcommunity.com/openid/login</URI>
+            <URI>https://example.com/openid/login</URI>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        let provider = Provider::from_xml(TWO_TYPES)?;
+        assert_eq!(provider, Provider::steam());
+
+        Ok(())
+    }
+
+    #[test]
+    fn service_without_provider_type_is_rejected() {
+        const OTHER_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
+    <XRD>
+        <Service priority="0">
+            <Type>http://openid.net/extensions/sreg/1.1</Type>
+            <Type>http://openid.net/srv/ax/1.0</Type>
+            <URI>https://steamcommunity.com/openid/login</URI>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        assert!(Provider::from_xml(OTHER_TYPES).is_err());
+    }
+
+    #[test]
+    fn signon_service() -> anyhow::Result<()> {
+        const SIGNON: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
+    <XRD>
+        <Service priority="10">
+            <Type>http://specs.openid.net/auth/2.0/signon</Type>
+            <URI>https://op.example.com/openid</URI>
+            <LocalID>https://op.example.com/u/1</LocalID>
+        </Service>
+    </XRD>
+</xrds:XRDS>"#;
+
+        let service = Provider::from_xml(SIGNON)?.service;
+        assert_eq!(service.service_type, ServiceType::SignOn);
+        assert_eq!(service.endpoint, "https://op.example.com/openid");
+        assert_eq!(
+            service.local_id.as_deref(),
+            Some("https://op.example.com/u/1")
+        );
+        assert_eq!(service.priority, Some(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_endpoint_is_rejected() -> anyhow::Result<()> {
+        let xrds = EXAMPLE.replace("https://steamcommunity.com/openid/login", "not a url");
+        assert!(Provider::from_xml(&xrds).is_err());
+
+        for uri in [
+            "/openid/login",
+            "ftp://steamcommunity.com/openid/login",
+            "https://steamcommunity.com/openid/\tlogin",
+            "https://steamcommunity.com/open\u{7f}id/login",
+        ] {
+            assert!(parse_endpoint(uri).is_err(), "{:?}", uri);
+        }
+        assert_eq!(
+            parse_endpoint("\n    https://op.example.com\n")?,
+            "https://op.example.com/"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn server_type_takes_precedence() {
+        let types = [OPENID_SIGNON_IDENTIFIER, OPENID_PROVIDER_IDENTIFIER];
+        assert_eq!(ServiceType::from_types(&types), Some(ServiceType::Server));
+        assert_eq!(
+            ServiceType::from_types(&types[..1]),
+            Some(ServiceType::SignOn)
+        );
+        assert_eq!(ServiceType::from_types(&[]), None);
+    }
+
+    #[test]
+    fn provider_from_endpoint() -> anyhow::Result<()> {
+        let provider = Provider::from_endpoint("https://steamcommunity.com/openid/login")?;
+        assert_eq!(provider.endpoint(), Provider::steam().endpoint());
+        assert_eq!(provider.service.service_type, ServiceType::Server);
+        assert_eq!(provider.service.version, OPENID_AUTH_NAMESPACE);
+
+        assert!(Provider::from_endpoint("http://steamcommunity.com/openid/login").is_err());
+        assert!(Provider::from_endpoint("https://steamcommunity.com/openid#login").is_err());
+        assert!(Provider::from_endpoint("/openid/login").is_err());
+        Ok(())
+    }
 }
@@ -14,19 +14,65 @@
 //! }
 //! ```
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use steam_api_concurrent::SteamId;
 
 use crate::openid::comma_separated::CommaSeparated;
 use crate::openid::constants::*;
-use crate::openid::nonce::Nonce;
-use crate::openid::Provider;
+use crate::openid::nonce::{Nonce, NonceTolerance};
+use crate::openid::{
+    make_base_string, same_endpoint, verify_signature, verify_signature_blocking, AssocType,
+    Association, Provider, VerifyOutcome,
+};
+use crate::openid_next::{self, OpenIdMode};
+
+pub const STEAM_IDENTITY_PREFIX: &str = "https://steamcommunity.com/openid/id/";
+
+/// Claimed identifier of a steam user, `https://steamcommunity.com/openid/id/<STEAMID>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SteamIdentity(pub SteamId);
+
+impl SteamIdentity {
+    pub fn from_claimed_id(claimed_id: &str) -> anyhow::Result<SteamIdentity> {
+        let steam_id = claimed_id
+            .strip_prefix(STEAM_IDENTITY_PREFIX)
+            .context("identifier is not for a steam id")?
+            .parse()
+            .context("identifier cannot represent a steam id")?;
+        Ok(SteamIdentity(steam_id))
+    }
+    pub fn to_claimed_id(&self) -> String {
+        format!("{}{}", STEAM_IDENTITY_PREFIX, self.0)
+    }
+    pub const fn steam_id(&self) -> SteamId {
+        self.0
+    }
+}
+
+/// Longest url accepted in `op_endpoint`, `claimed_id`, `identity` and `return_to`
+const MAX_URL_FIELD_LEN: usize = 2048;
+
+/// The spec bounds handles at 255 characters
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.2.1>
+const MAX_ASSOCIATION_HANDLE_LEN: usize = 255;
+
+/// Every field of an assertion signed, with room for some extensions
+const MAX_SIGNED_FIELDS_LEN: usize = 1024;
 
-pub(crate) const STEAM_IDENTITY_PREFIX: &str = "https://steamcommunity.com/openid/id/";
+/// A base64 HMAC-SHA256 is 44 characters
+const MAX_SIGNATURE_LEN: usize = 512;
+
+/// `ns` and `mode` have fixed values, anything longer is garbage
+const MAX_FIXED_FIELD_LEN: usize = 64;
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub(crate) struct PositiveAssertion {
+pub struct PositiveAssertion {
     /// `openid.ns` <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
     #[serde(rename = "openid.ns")]
     namespace: String,
@@ -42,12 +88,18 @@ pub(crate) struct PositiveAssertion {
     service_endpoint: String,
 
     /// See [`crate::openid::constants::OPENID_CLAIMED_ID`]
+    ///
+    /// Absent if the response is not about an identifier.
     #[serde(rename = "openid.claimed_id")]
-    claimed_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    claimed_id: Option<String>,
 
     /// See [`crate::openid::constants::OPENID_IDENTITY`]
+    ///
+    /// Absent if the response is not about an identifier.
     #[serde(rename = "openid.identity")]
-    identity: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    identity: Option<String>,
 
     /// See [`crate::openid::constants::OPENID_RETURN_TO`]
     ///
@@ -56,8 +108,13 @@ pub(crate) struct PositiveAssertion {
     return_to: String,
 
     /// See [`crate::openid::constants::OPENID_RESPONSE_NONCE`]
+    ///
+    /// Required, but an absent or empty one is only rejected by [`PositiveAssertion::validate`]
+    /// so it isn't reported as a malformed nonce.
     #[serde(rename = "openid.response_nonce")]
-    nonce: Nonce,
+    #[serde(default, deserialize_with = "deserialize_response_nonce")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<Nonce>,
 
     /// See [`crate::openid::constants::OPENID_ASSOCIATION_HANDLE`]
     #[serde(rename = "openid.assoc_handle")]
@@ -70,20 +127,81 @@ pub(crate) struct PositiveAssertion {
     /// See [`crate::openid::constants::OPENID_SIGNATURE`]
     #[serde(rename = "openid.sig")]
     signature: String,
+
+    /// Every other `openid.*` field exactly as it was received, e.g. extensions
+    /// or [`OPENID_INVALIDATE_HANDLE`], they have to be echoed in `check_authentication`
+    #[serde(flatten, deserialize_with = "deserialize_extensions")]
+    extensions: BTreeMap<String, String>,
 }
 
 impl PositiveAssertion {
+    /// Names of the fields that aren't [extensions](PositiveAssertion::extensions)
+    pub const FIELDS: [&'static str; 10] = [
+        OPENID_NAMESPACE,
+        OPENID_MODE,
+        OPENID_OP_ENDPOINT,
+        OPENID_CLAIMED_ID,
+        OPENID_IDENTITY,
+        OPENID_RETURN_TO,
+        OPENID_RESPONSE_NONCE,
+        OPENID_ASSOCIATION_HANDLE,
+        OPENID_SIGNED_FIELDS,
+        OPENID_SIGNATURE,
+    ];
+
+    /// Reject fields that are longer than anything an OP would send
+    ///
+    /// The response nonce is bounded by [`OPENID_RESPONSE_NONCE_MAX_LEN`] when it is parsed.
+    fn validate_field_lengths(&self) -> anyhow::Result<()> {
+        let fields = [
+            (OPENID_NAMESPACE, self.namespace.len(), MAX_FIXED_FIELD_LEN),
+            (OPENID_MODE, self.mode.len(), MAX_FIXED_FIELD_LEN),
+            (
+                OPENID_OP_ENDPOINT,
+                self.service_endpoint.len(),
+                MAX_URL_FIELD_LEN,
+            ),
+            (
+                OPENID_CLAIMED_ID,
+                self.claimed_id.as_ref().map_or(0, String::len),
+                MAX_URL_FIELD_LEN,
+            ),
+            (
+                OPENID_IDENTITY,
+                self.identity.as_ref().map_or(0, String::len),
+                MAX_URL_FIELD_LEN,
+            ),
+            (OPENID_RETURN_TO, self.return_to.len(), MAX_URL_FIELD_LEN),
+            (
+                OPENID_ASSOCIATION_HANDLE,
+                self.association_handle.len(),
+                MAX_ASSOCIATION_HANDLE_LEN,
+            ),
+            (
+                OPENID_SIGNED_FIELDS,
+                self.signed_fields.iter().map(|field| field.len() + 1).sum(),
+                MAX_SIGNED_FIELDS_LEN,
+            ),
+            (OPENID_SIGNATURE, self.signature.len(), MAX_SIGNATURE_LEN),
+        ];
+        for (field, len, max_len) in fields {
+            if len > max_len {
+                anyhow::bail!("`{}` is longer than {} bytes", field, max_len);
+            }
+        }
+        Ok(())
+    }
     /// Generic validation
-    pub(crate) fn validate(&self, provider: &Provider) -> anyhow::Result<()> {
+    pub fn validate(&self, provider: &Provider) -> anyhow::Result<()> {
         /// Fields that must be signed as per spec
-        const EXPECTED_SIGNED_FIELDS: [&str; 6] = [
+        const EXPECTED_SIGNED_FIELDS: [&str; 4] = [
             OPENID_OP_ENDPOINT,
             OPENID_RETURN_TO,
             OPENID_RESPONSE_NONCE,
             OPENID_ASSOCIATION_HANDLE,
-            OPENID_CLAIMED_ID,
-            OPENID_IDENTITY,
         ];
+        /// Fields that must be signed as per spec if they are present
+        const EXPECTED_SIGNED_IDENTITY_FIELDS: [&str; 2] = [OPENID_CLAIMED_ID, OPENID_IDENTITY];
 
         /// - `actual`: A list of present field names _without_ the [prefix]
         /// - `expected`: A list of expected field names _with_ the [prefix]
@@ -103,21 +221,34 @@ impl PositiveAssertion {
                 .all(|expected| actual.iter().any(|actual| eq(actual, expected)))
         }
 
+        self.validate_field_lengths()?;
         if self.namespace != OPENID_AUTH_NAMESPACE {
             anyhow::bail!("invalid value for openid namespace");
         }
-        if self.mode != OPENID_MODE_IDENTIFIER_RESPONSE {
-            anyhow::bail!("invalid mode");
+        if self.nonce.is_none() {
+            anyhow::bail!("missing response nonce");
         }
-        if self.service_endpoint != provider.service.endpoint {
+        match self.mode() {
+            Ok(OpenIdMode::IdentityResolution) => {}
+            _ => anyhow::bail!("invalid mode"),
+        }
+        if !same_endpoint(&self.service_endpoint, provider.endpoint()) {
             anyhow::bail!("provider endpoint doesn't match");
         }
-        if self.claimed_id != self.identity {
-            anyhow::bail!("claimed identity doesn't match identity");
+        // either both are present or neither is, they differ for a delegated identifier
+        // whose identity has to be checked against discovery by the caller
+        // https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1
+        if self.claimed_id.is_some() != self.identity.is_some() {
+            anyhow::bail!("only one of claimed identity and identity is present");
         }
         if !has_fields(&self.signed_fields, &EXPECTED_SIGNED_FIELDS) {
             anyhow::bail!("fields that should be signed aren't signed");
         }
+        if self.claimed_id.is_some()
+            && !has_fields(&self.signed_fields, &EXPECTED_SIGNED_IDENTITY_FIELDS)
+        {
+            anyhow::bail!("identity fields that should be signed aren't signed");
+        }
         if self.signature.is_empty() {
             anyhow::bail!("signature field is empty");
         }
@@ -125,38 +256,359 @@ impl PositiveAssertion {
         Ok(())
     }
     /// Steam specific validation
-    pub(crate) fn validate_steam(&self) -> anyhow::Result<()> {
-        let claimed_id_id: u64 = self
+    ///
+    /// The time checks of the response nonce are relaxed by `tolerance`.
+    pub fn validate_steam(&self, tolerance: NonceTolerance) -> anyhow::Result<()> {
+        let claimed_id = self
             .claimed_id
-            .strip_prefix(STEAM_IDENTITY_PREFIX)
-            .context("claimed identity is not for a steam id")?
-            .parse()
-            .context("claimed identity cannot represent a steam id")?;
+            .as_deref()
+            .context("claimed identity is missing")
+            .and_then(SteamIdentity::from_claimed_id)
+            .context("invalid claimed identity")?;
 
-        let identity_id: u64 = self
+        let identity = self
             .identity
-            .strip_prefix(STEAM_IDENTITY_PREFIX)
-            .context("identity is not for a steam id")?
-            .parse()
-            .context("identity cannot represent a steam id")?;
+            .as_deref()
+            .context("identity is missing")
+            .and_then(SteamIdentity::from_claimed_id)
+            .context("invalid identity")?;
 
-        if claimed_id_id != identity_id {
+        if claimed_id != identity {
             anyhow::bail!("claimed id doesn't match identity");
         }
 
-        if self.nonce.is_expired() {
+        self.validate_nonce(tolerance)
+    }
+    /// Check the time of the response nonce, relaxed by `tolerance`
+    pub fn validate_nonce(&self, tolerance: NonceTolerance) -> anyhow::Result<()> {
+        let nonce = self.nonce.as_ref().context("missing response nonce")?;
+        if nonce.is_expired(tolerance.grace_ms) {
             anyhow::bail!("too old");
         }
+        if nonce.is_from_future(tolerance.max_skew_ms) {
+            anyhow::bail!("from the future");
+        }
 
         Ok(())
     }
 
-    pub(crate) fn set_mode(&mut self, mode: &str) {
+    /// Value of a field given its name _without_ the [prefix]
+    ///
+    /// [prefix]: crate::openid::constants::OPENID_FIELD_PREFIX
+    fn field_value(&self, field: &str) -> Option<String> {
+        let value = match field {
+            "ns" => self.namespace.clone(),
+            "mode" => self.mode.clone(),
+            "op_endpoint" => self.service_endpoint.clone(),
+            "claimed_id" => self.claimed_id.clone()?,
+            "identity" => self.identity.clone()?,
+            "return_to" => self.return_to.clone(),
+            "response_nonce" => self.nonce.as_ref()?.to_string(),
+            "assoc_handle" => self.association_handle.clone(),
+            "signed" => self.signed_fields.to_string(),
+            _ => self
+                .extensions
+                .get(&format!("{}{}", OPENID_FIELD_PREFIX, field))?
+                .clone(),
+        };
+        Some(value)
+    }
+    /// See [`make_base_string`]
+    pub fn signature_base_string(&self) -> anyhow::Result<String> {
+        let values = self
+            .signed_fields
+            .iter()
+            .map(|field| {
+                self.field_value(field)
+                    .map(|value| (field.as_str(), value))
+                    .with_context(|| format!("signed field `{}` is unknown", field))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(make_base_string(
+            values.iter().map(|(key, value)| (*key, value.as_str())),
+        ))
+    }
+    /// Verify the signature locally with the MAC key and the negotiated type
+    /// of the association referenced by [`OPENID_ASSOCIATION_HANDLE`].
+    pub async fn verify_signature(
+        &self,
+        assoc_type: AssocType,
+        mac_key_b64: &str,
+    ) -> anyhow::Result<bool> {
+        let base_string = self
+            .signature_base_string()
+            .context("couldn't build signature base string")?;
+        verify_signature_blocking(
+            assoc_type,
+            mac_key_b64.to_string(),
+            base_string,
+            self.signature.clone(),
+        )
+        .await
+    }
+    /// Verify the assertion locally with an association that was established with the OP,
+    /// saves the `check_authentication` request
+    ///
+    /// Only [`VerifyOutcome::Valid`] and [`VerifyOutcome::InvalidSignature`] are final,
+    /// otherwise the assertion has to be verified by the OP after all.
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.4.1>
+    pub fn verify_with_association(&self, association: &Association) -> VerifyOutcome {
+        if self.association_handle != association.handle {
+            return VerifyOutcome::HandleMismatch;
+        }
+        if association.is_expired() {
+            return VerifyOutcome::Expired;
+        }
+        let verified = self.signature_base_string().and_then(|base_string| {
+            verify_signature(
+                association.assoc_type,
+                &association.mac_key,
+                &base_string,
+                &self.signature,
+            )
+        });
+        match verified {
+            Ok(true) => VerifyOutcome::Valid,
+            Ok(false) | Err(_) => VerifyOutcome::InvalidSignature,
+        }
+    }
+
+    /// See [`crate::openid::constants::OPENID_MODE`]
+    pub fn mode(&self) -> anyhow::Result<OpenIdMode> {
+        self.mode.parse()
+    }
+    /// Every field of the assertion, extensions included, with the mode replaced by
+    /// [`OpenIdMode::CheckAuthentication`], `self` stays untouched
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.4.2.1>
+    pub fn to_check_auth_form(&self) -> impl Serialize + '_ {
+        CheckAuthForm {
+            namespace: &self.namespace,
+            mode: OpenIdMode::CheckAuthentication.as_str(),
+            service_endpoint: &self.service_endpoint,
+            claimed_id: self.claimed_id.as_deref(),
+            identity: self.identity.as_deref(),
+            return_to: &self.return_to,
+            nonce: self.nonce.as_ref(),
+            association_handle: &self.association_handle,
+            signed_fields: &self.signed_fields,
+            signature: &self.signature,
+            extensions: &self.extensions,
+        }
+    }
+    pub fn set_mode(&mut self, mode: OpenIdMode) {
         self.mode.clear();
-        self.mode.push_str(mode);
+        self.mode.push_str(mode.as_str());
     }
-    pub(crate) fn claimed_id(&self) -> &str {
-        &self.claimed_id
+    pub fn claimed_id(&self) -> Option<&str> {
+        self.claimed_id.as_deref()
+    }
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+    pub fn return_to(&self) -> &str {
+        &self.return_to
+    }
+    pub fn op_endpoint(&self) -> &str {
+        &self.service_endpoint
+    }
+    pub fn association_handle(&self) -> &str {
+        &self.association_handle
+    }
+    pub const fn response_nonce(&self) -> Option<&Nonce> {
+        self.nonce.as_ref()
+    }
+    pub fn signed_fields(&self) -> &[String] {
+        &self.signed_fields
+    }
+    /// The `openid.*` fields that aren't in [`PositiveAssertion::FIELDS`], prefix included
+    pub const fn extensions(&self) -> &BTreeMap<String, String> {
+        &self.extensions
+    }
+    /// The `openid.*` params as they are appended to `return_to`
+    pub fn to_query_string(&self) -> anyhow::Result<String> {
+        serde_urlencoded::to_string(self).context("couldn't serialize positive assertion")
+    }
+}
+
+/// Body of the `check_authentication` request, see [`PositiveAssertion::to_check_auth_form`]
+#[derive(Serialize)]
+struct CheckAuthForm<'a> {
+    #[serde(rename = "openid.ns")]
+    namespace: &'a str,
+    #[serde(rename = "openid.mode")]
+    mode: &'static str,
+    #[serde(rename = "openid.op_endpoint")]
+    service_endpoint: &'a str,
+    #[serde(rename = "openid.claimed_id")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    claimed_id: Option<&'a str>,
+    #[serde(rename = "openid.identity")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    identity: Option<&'a str>,
+    #[serde(rename = "openid.return_to")]
+    return_to: &'a str,
+    #[serde(rename = "openid.response_nonce")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<&'a Nonce>,
+    #[serde(rename = "openid.assoc_handle")]
+    association_handle: &'a str,
+    #[serde(rename = "openid.signed")]
+    signed_fields: &'a CommaSeparated<String>,
+    #[serde(rename = "openid.sig")]
+    signature: &'a str,
+    #[serde(flatten)]
+    extensions: &'a BTreeMap<String, String>,
+}
+
+/// Build a [`PositiveAssertion`] field by field, e.g. in a mock OP or in tests
+///
+/// `openid.ns` and `openid.mode` are fixed to an OpenID 2.0 positive assertion and
+/// unless set otherwise every present field is signed.
+#[derive(Debug, Default, Clone)]
+pub struct PositiveAssertionBuilder {
+    service_endpoint: Option<String>,
+    claimed_id: Option<String>,
+    identity: Option<String>,
+    return_to: Option<String>,
+    nonce: Option<Nonce>,
+    association_handle: Option<String>,
+    signed_fields: Option<CommaSeparated<String>>,
+    signature: Option<String>,
+}
+
+impl PositiveAssertionBuilder {
+    pub fn new() -> PositiveAssertionBuilder {
+        PositiveAssertionBuilder::default()
+    }
+    pub fn op_endpoint(mut self, op_endpoint: impl Into<String>) -> PositiveAssertionBuilder {
+        self.service_endpoint = Some(op_endpoint.into());
+        self
+    }
+    pub fn claimed_id(mut self, claimed_id: impl Into<String>) -> PositiveAssertionBuilder {
+        self.claimed_id = Some(claimed_id.into());
+        self
+    }
+    pub fn identity(mut self, identity: impl Into<String>) -> PositiveAssertionBuilder {
+        self.identity = Some(identity.into());
+        self
+    }
+    pub fn return_to(mut self, return_to: impl Into<String>) -> PositiveAssertionBuilder {
+        self.return_to = Some(return_to.into());
+        self
+    }
+    pub fn response_nonce(mut self, nonce: Nonce) -> PositiveAssertionBuilder {
+        self.nonce = Some(nonce);
+        self
+    }
+    pub fn assoc_handle(mut self, assoc_handle: impl Into<String>) -> PositiveAssertionBuilder {
+        self.association_handle = Some(assoc_handle.into());
+        self
+    }
+    /// Field names without the [`OPENID_FIELD_PREFIX`]
+    pub fn signed<S: Into<String>>(
+        mut self,
+        fields: impl IntoIterator<Item = S>,
+    ) -> PositiveAssertionBuilder {
+        self.signed_fields = Some(fields.into_iter().map(Into::into).collect());
+        self
+    }
+    /// Base64 encoded signature, see [`PositiveAssertion::signature_base_string`]
+    pub fn signature(mut self, signature: impl Into<String>) -> PositiveAssertionBuilder {
+        self.signature = Some(signature.into());
+        self
+    }
+    /// Everything but the identifiers and the signature is required,
+    /// an unsigned assertion has an empty signature
+    pub fn build(self) -> anyhow::Result<PositiveAssertion> {
+        let signed_fields = match self.signed_fields {
+            Some(signed_fields) => signed_fields,
+            None => [
+                Some("op_endpoint"),
+                self.claimed_id.as_ref().map(|_| "claimed_id"),
+                self.identity.as_ref().map(|_| "identity"),
+                Some("return_to"),
+                Some("response_nonce"),
+                Some("assoc_handle"),
+            ]
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .collect(),
+        };
+        Ok(PositiveAssertion {
+            namespace: OPENID_AUTH_NAMESPACE.to_string(),
+            mode: OpenIdMode::IdentityResolution.as_str().to_string(),
+            service_endpoint: self.service_endpoint.context("op endpoint is missing")?,
+            claimed_id: self.claimed_id,
+            identity: self.identity,
+            return_to: self.return_to.context("return_to is missing")?,
+            nonce: Some(self.nonce.context("response nonce is missing")?),
+            association_handle: self
+                .association_handle
+                .context("association handle is missing")?,
+            signed_fields,
+            signature: self.signature.unwrap_or_default(),
+            extensions: BTreeMap::new(),
+        })
+    }
+}
+
+/// An empty `openid.response_nonce` counts as absent
+fn deserialize_response_nonce<'de, D>(deserializer: D) -> Result<Option<Nonce>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let nonce = Cow::<'de, str>::deserialize(deserializer)?;
+    if nonce.is_empty() {
+        return Ok(None);
+    }
+    nonce.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Keep the `openid.*` fields the named fields left over, everything else belongs to the caller
+fn deserialize_extensions<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut fields = BTreeMap::<String, String>::deserialize(deserializer)?;
+    fields.retain(|key, _| {
+        key.starts_with(OPENID_FIELD_PREFIX) && !PositiveAssertion::FIELDS.contains(&key.as_str())
+    });
+    Ok(fields)
+}
+
+/// Parses the nonce and the signed fields of the loosely typed assertion.
+///
+/// The result still has to be [validated](PositiveAssertion::validate).
+impl TryFrom<openid_next::PositiveAssertion> for PositiveAssertion {
+    type Error = anyhow::Error;
+    fn try_from(value: openid_next::PositiveAssertion) -> Result<Self, Self::Error> {
+        Ok(PositiveAssertion {
+            namespace: value.ns,
+            mode: value.mode,
+            service_endpoint: value.op_endpoint,
+            claimed_id: value.claimed_id,
+            identity: value.identity,
+            return_to: value.return_to,
+            nonce: Some(value.response_nonce.as_str())
+                .filter(|nonce| !nonce.is_empty())
+                .map(str::parse)
+                .transpose()
+                .context("couldn't parse response nonce")?,
+            association_handle: value.assoc_handle,
+            signed_fields: value
+                .signed
+                .parse()
+                .context("couldn't parse signed fields")?,
+            signature: value.sig,
+            extensions: value
+                .invalidate_handle
+                .map(|handle| (OPENID_INVALIDATE_HANDLE.to_string(), handle))
+                .into_iter()
+                .collect(),
+        })
     }
 }
 
@@ -200,10 +652,7 @@ mod test {
             .find(|(k, _)| k == OPENID_RESPONSE_NONCE)
             .context("nonce field not found")?;
 
-        let nonce = Nonce {
-            salt: TEST_PARAMS_NONCE_SALT.to_string(),
-            time: Utc::now(),
-        };
+        let nonce = Nonce::new(Utc::now(), TEST_PARAMS_NONCE_SALT);
 
         // This relies on `to_string` resulting in the same
         // representation as serialized with serde_urlencoded!
@@ -230,7 +679,7 @@ mod test {
             .validate(&provider)
             .context("couldn't validate response")?;
         parsed
-            .validate_steam()
+            .validate_steam(NonceTolerance::default())
             .context("couldn't validate steam response")?;
 
         let as_query = serde_urlencoded::to_string(&parsed)
@@ -240,6 +689,79 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn verify_signature_works() -> anyhow::Result<()> {
+        const MAC_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+        const SIGNATURE: &str = "7hk3kqMPS4BZldIBY4YzTu3Q9FzPCyeBHuh6mbugKAg=";
+
+        let parsed = reqwest::Url::parse(TEST_URL).context("couldn't parse url")?;
+        let query = parsed.query().context("url doesn't contain a query")?;
+
+        let mut parsed: PositiveAssertion = serde_urlencoded::from_str(query)
+            .context("couldn't parse positive assertion from query")?;
+        assert!(
+            !parsed
+                .verify_signature(AssocType::HmacSha256, MAC_KEY)
+                .await?
+        );
+
+        parsed.signature = SIGNATURE.to_string();
+        assert!(
+            parsed
+                .verify_signature(AssocType::HmacSha256, MAC_KEY)
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_with_association() -> anyhow::Result<()> {
+        const MAC_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+        const SIGNATURE: &str = "7hk3kqMPS4BZldIBY4YzTu3Q9FzPCyeBHuh6mbugKAg=";
+
+        let parsed = reqwest::Url::parse(TEST_URL).context("couldn't parse url")?;
+        let query = parsed.query().context("url doesn't contain a query")?;
+        let mut parsed: PositiveAssertion = serde_urlencoded::from_str(query)?;
+
+        let association = Association {
+            handle: TEST_PARAMS_ASSOC_HANDLE.to_string(),
+            assoc_type: AssocType::HmacSha256,
+            mac_key: MAC_KEY.to_string(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+        };
+        assert_eq!(
+            parsed.verify_with_association(&association),
+            VerifyOutcome::InvalidSignature
+        );
+
+        parsed.signature = SIGNATURE.to_string();
+        assert_eq!(
+            parsed.verify_with_association(&association),
+            VerifyOutcome::Valid
+        );
+
+        let other_handle = Association {
+            handle: "0987654321".to_string(),
+            ..association.clone()
+        };
+        assert_eq!(
+            parsed.verify_with_association(&other_handle),
+            VerifyOutcome::HandleMismatch
+        );
+
+        let expired = Association {
+            expires_at: Utc::now() - chrono::Duration::seconds(1),
+            ..association
+        };
+        assert_eq!(
+            parsed.verify_with_association(&expired),
+            VerifyOutcome::Expired
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn serialize_deserialize() -> anyhow::Result<()> {
         let parsed = reqwest::Url::parse(TEST_URL).context("couldn't parse url")?;
@@ -251,10 +773,13 @@ mod test {
         assert_eq!(parsed.namespace, OPENID_AUTH_NAMESPACE);
         assert_eq!(parsed.mode, OPENID_MODE_IDENTIFIER_RESPONSE);
         assert_eq!(parsed.service_endpoint, TEST_PARAMS_ENDPOINT);
-        assert_eq!(parsed.claimed_id, TEST_PARAMS_ID);
-        assert_eq!(parsed.identity, TEST_PARAMS_ID);
+        assert_eq!(parsed.claimed_id.as_deref(), Some(TEST_PARAMS_ID));
+        assert_eq!(parsed.identity.as_deref(), Some(TEST_PARAMS_ID));
         assert_eq!(parsed.return_to, TEST_PARAMS_RETURN_TO);
-        assert_eq!(parsed.nonce.to_string(), TEST_PARAMS_NONCE);
+        assert_eq!(
+            parsed.nonce.as_ref().map(Nonce::as_str),
+            Some(TEST_PARAMS_NONCE)
+        );
         assert_eq!(parsed.association_handle, TEST_PARAMS_ASSOC_HANDLE);
         assert_eq!(parsed.signed_fields.to_string(), TEST_PARAMS_SIGNED_FIELDS);
         assert_eq!(parsed.signature, TEST_PARAMS_SIGNATURE);
@@ -265,4 +790,295 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn check_auth_form_keeps_fields() -> anyhow::Result<()> {
+        let url = reqwest::Url::parse(TEST_URL).context("couldn't parse url")?;
+        let query = url.query().context("url doesn't contain a query")?;
+        let parsed: PositiveAssertion = serde_urlencoded::from_str(query)
+            .context("couldn't parse positive assertion from query")?;
+
+        let form = serde_urlencoded::to_string(parsed.to_check_auth_form())
+            .context("couldn't encode check_authentication form")?;
+        let form: Vec<(String, String)> = serde_urlencoded::from_str(&form)?;
+
+        let expected: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(key, value)| {
+                let value = if key == OPENID_MODE {
+                    OPENID_MODE_CHECK_AUTHENTICATION.into()
+                } else {
+                    value
+                };
+                (key.into_owned(), value.into_owned())
+            })
+            .collect();
+        assert_eq!(form, expected);
+        assert_eq!(parsed.mode, OPENID_MODE_IDENTIFIER_RESPONSE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_auth_form_echoes_extensions() -> anyhow::Result<()> {
+        let query = format!(
+            "{}&openid.ns.sreg=http%3A%2F%2Fopenid.net%2Fextensions%2Fsreg%2F1.1\
+             &openid.sreg.nickname=bob&openid.invalidate_handle=old&custom_nonce=x",
+            TEST_URL
+                .split_once('?')
+                .context("url doesn't contain a query")?
+                .1
+        );
+        let parsed: PositiveAssertion = serde_urlencoded::from_str(&query)
+            .context("couldn't parse positive assertion with extensions")?;
+        assert_eq!(parsed.extensions().len(), 3);
+        assert_eq!(parsed.field_value("sreg.nickname").as_deref(), Some("bob"));
+
+        let form = serde_urlencoded::to_string(parsed.to_check_auth_form())
+            .context("couldn't encode check_authentication form")?;
+        let form: BTreeMap<String, String> = serde_urlencoded::from_str(&form)?;
+
+        let expected: BTreeMap<String, String> =
+            serde_urlencoded::from_str::<Vec<(String, String)>>(&query)?
+                .into_iter()
+                .filter(|(key, _)| key.starts_with(OPENID_FIELD_PREFIX))
+                .map(|(key, value)| match key == OPENID_MODE {
+                    true => (key, OPENID_MODE_CHECK_AUTHENTICATION.to_string()),
+                    false => (key, value),
+                })
+                .collect();
+        assert_eq!(form, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn identity_less_assertion() -> anyhow::Result<()> {
+        let provider = Provider::steam();
+
+        let mut url = reqwest::Url::parse(&make_test_url()?).context("couldn't parse url")?;
+        let params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != OPENID_CLAIMED_ID && key != OPENID_IDENTITY)
+            .map(|(key, value)| {
+                let value = if key == OPENID_SIGNED_FIELDS {
+                    "signed,op_endpoint,return_to,response_nonce,assoc_handle".into()
+                } else {
+                    value
+                };
+                (key.into_owned(), value.into_owned())
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(&params);
+        let query = url.query().context("url doesn't contain a query")?;
+
+        let parsed: PositiveAssertion = serde_urlencoded::from_str(query)
+            .context("couldn't parse identity-less positive assertion from query")?;
+        assert!(parsed.claimed_id().is_none());
+
+        parsed
+            .validate(&provider)
+            .context("couldn't validate identity-less response")?;
+        assert!(parsed.validate_steam(NonceTolerance::default()).is_err());
+
+        let as_query = serde_urlencoded::to_string(&parsed)
+            .context("couldn't encode positive asstion back into a query")?;
+        assert_eq!(query, as_query);
+
+        Ok(())
+    }
+
+    #[test]
+    fn convert_without_claimed_id() -> anyhow::Result<()> {
+        let provider = Provider::steam();
+
+        let mut url = reqwest::Url::parse(&make_test_url()?).context("couldn't parse url")?;
+        let params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(key, _)| key != OPENID_CLAIMED_ID && key != OPENID_IDENTITY)
+            .map(|(key, value)| {
+                let value = if key == OPENID_SIGNED_FIELDS {
+                    "signed,op_endpoint,return_to,response_nonce,assoc_handle".into()
+                } else {
+                    value
+                };
+                (key.into_owned(), value.into_owned())
+            })
+            .chain([("openid.invalidate_handle".to_string(), "stale".to_string())])
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(&params);
+        let query = url.query().context("url doesn't contain a query")?;
+
+        let loose: openid_next::PositiveAssertion = serde_urlencoded::from_str(query)
+            .context("couldn't parse loose positive assertion from query")?;
+        assert!(loose.claimed_id.is_none());
+        assert_eq!(loose.invalidate_handle.as_deref(), Some("stale"));
+
+        let parsed = PositiveAssertion::try_from(loose)?;
+        assert!(parsed.claimed_id().is_none());
+        assert_eq!(
+            parsed.signed_fields.to_string(),
+            "signed,op_endpoint,return_to,response_nonce,assoc_handle"
+        );
+        parsed
+            .validate(&provider)
+            .context("couldn't validate converted response")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn convert_rejects_invalid_nonce() -> anyhow::Result<()> {
+        let parsed = reqwest::Url::parse(TEST_URL).context("couldn't parse url")?;
+        let query = parsed.query().context("url doesn't contain a query")?;
+
+        let mut loose: openid_next::PositiveAssertion = serde_urlencoded::from_str(query)
+            .context("couldn't parse loose positive assertion from query")?;
+        assert_eq!(loose.claimed_id.as_deref(), Some(TEST_PARAMS_ID));
+
+        loose.response_nonce = "not a nonce".to_string();
+        assert!(PositiveAssertion::try_from(loose).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn steam_identity_round_trip() -> anyhow::Result<()> {
+        let identity = SteamIdentity::from_claimed_id(TEST_PARAMS_ID)?;
+        assert_eq!(identity.steam_id(), SteamId(76561198181282063));
+        assert_eq!(identity.to_claimed_id(), TEST_PARAMS_ID);
+        Ok(())
+    }
+
+    #[test]
+    fn steam_identity_rejects_other_identifiers() {
+        for claimed_id in [
+            "https://example.com/openid/id/76561198181282063",
+            "http://steamcommunity.com/openid/id/76561198181282063",
+            "https://steamcommunity.com/openid/id/",
+            "https://steamcommunity.com/openid/id/steam",
+        ] {
+            assert!(SteamIdentity::from_claimed_id(claimed_id).is_err());
+        }
+    }
+
+    #[test]
+    fn builder_passes_validation() -> anyhow::Result<()> {
+        let nonce = Nonce::new(Utc::now(), TEST_PARAMS_NONCE_SALT);
+        let assertion = PositiveAssertionBuilder::new()
+            .op_endpoint(TEST_PARAMS_ENDPOINT)
+            .claimed_id(TEST_PARAMS_ID)
+            .identity(TEST_PARAMS_ID)
+            .return_to(TEST_PARAMS_RETURN_TO)
+            .response_nonce(nonce)
+            .assoc_handle(TEST_PARAMS_ASSOC_HANDLE)
+            .signature(TEST_PARAMS_SIGNATURE)
+            .build()?;
+        assertion.validate(&Provider::steam())?;
+        assertion.validate_steam(NonceTolerance::default())?;
+
+        let parsed: PositiveAssertion = serde_urlencoded::from_str(&assertion.to_query_string()?)?;
+        assert_eq!(
+            parsed.signature_base_string()?,
+            assertion.signature_base_string()?
+        );
+        assert_eq!(
+            parsed.signed_fields(),
+            [
+                "op_endpoint",
+                "claimed_id",
+                "identity",
+                "return_to",
+                "response_nonce",
+                "assoc_handle"
+            ]
+        );
+
+        // unsigned and without required fields
+        assert!(PositiveAssertionBuilder::new()
+            .op_endpoint(TEST_PARAMS_ENDPOINT)
+            .build()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn endpoint_with_trailing_slash_matches() -> anyhow::Result<()> {
+        let assertion = |endpoint: &str| {
+            PositiveAssertionBuilder::new()
+                .op_endpoint(endpoint)
+                .claimed_id(TEST_PARAMS_ID)
+                .identity(TEST_PARAMS_ID)
+                .return_to(TEST_PARAMS_RETURN_TO)
+                .response_nonce(Nonce::new(Utc::now(), TEST_PARAMS_NONCE_SALT))
+                .assoc_handle(TEST_PARAMS_ASSOC_HANDLE)
+                .signature(TEST_PARAMS_SIGNATURE)
+                .build()
+        };
+        assertion("https://steamcommunity.com/openid/login/")?.validate(&Provider::steam())?;
+        assertion("https://STEAMCOMMUNITY.com:443/openid/login")?.validate(&Provider::steam())?;
+        assert!(assertion("https://steamcommunity.com/openid/other")?
+            .validate(&Provider::steam())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_response_nonce() -> anyhow::Result<()> {
+        let empty =
+            reqwest::Url::parse_with_params(TEST_PARAMS_BASE_URL, TEST_PARAMS_WITHOUT_NONCE)?;
+        let empty: PositiveAssertion =
+            serde_urlencoded::from_str(empty.query().unwrap_or_default())?;
+
+        let absent = TEST_PARAMS_WITHOUT_NONCE
+            .into_iter()
+            .filter(|(key, _)| *key != OPENID_RESPONSE_NONCE);
+        let absent = reqwest::Url::parse_with_params(TEST_PARAMS_BASE_URL, absent)?;
+        let absent: PositiveAssertion =
+            serde_urlencoded::from_str(absent.query().unwrap_or_default())?;
+
+        for assertion in [empty, absent] {
+            let err = assertion
+                .validate(&Provider::steam())
+                .expect_err("assertion without nonce is valid");
+            assert_eq!(err.to_string(), "missing response nonce");
+            assert!(assertion.validate_nonce(NonceTolerance::default()).is_err());
+            assert!(assertion.signature_base_string().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_fields_are_rejected() -> anyhow::Result<()> {
+        let builder = PositiveAssertionBuilder::new()
+            .op_endpoint(TEST_PARAMS_ENDPOINT)
+            .claimed_id(TEST_PARAMS_ID)
+            .identity(TEST_PARAMS_ID)
+            .return_to(TEST_PARAMS_RETURN_TO)
+            .response_nonce(Nonce::new(Utc::now(), TEST_PARAMS_NONCE_SALT))
+            .assoc_handle(TEST_PARAMS_ASSOC_HANDLE)
+            .signature(TEST_PARAMS_SIGNATURE);
+        builder.clone().build()?.validate(&Provider::steam())?;
+
+        let long_id = format!("{}{}", TEST_PARAMS_ID, "0".repeat(MAX_URL_FIELD_LEN));
+        let long_handle = "a".repeat(MAX_ASSOCIATION_HANDLE_LEN + 1);
+        let long_signature = "A".repeat(1024 * 1024);
+        let oversized = [
+            builder.clone().claimed_id(&long_id).identity(&long_id),
+            builder
+                .clone()
+                .return_to(format!("{}?{}", TEST_PARAMS_RETURN_TO, long_id)),
+            builder.clone().assoc_handle(long_handle),
+            builder.signature(long_signature),
+        ];
+        for builder in oversized {
+            let err = builder
+                .build()?
+                .validate(&Provider::steam())
+                .expect_err("oversized assertion is valid");
+            assert!(err.to_string().contains("is longer than"), "{}", err);
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,233 @@
+//! Local verification of assertion signatures
+//!
+//! <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.6>
+//!
+//! Computing the HMAC is cheap but not free, so the async entry point
+//! offloads it to the blocking thread pool to keep the executor responsive.
+
+use std::str::FromStr;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::Sha256;
+
+/// Signature algorithm of an association
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.3>
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssocType {
+    /// Steam still uses this one
+    #[serde(rename = "HMAC-SHA1")]
+    HmacSha1,
+    #[default]
+    #[serde(rename = "HMAC-SHA256")]
+    HmacSha256,
+}
+
+impl AssocType {
+    /// Value of `openid.assoc_type`
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            AssocType::HmacSha1 => "HMAC-SHA1",
+            AssocType::HmacSha256 => "HMAC-SHA256",
+        }
+    }
+    /// Type to request next if the OP rejected `self` in the `associate` request
+    /// without suggesting one, the [default](AssocType::default) is tried first.
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8.2.4>
+    pub const fn fallback(&self) -> Option<AssocType> {
+        match self {
+            AssocType::HmacSha256 => Some(AssocType::HmacSha1),
+            AssocType::HmacSha1 => None,
+        }
+    }
+}
+
+impl FromStr for AssocType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HMAC-SHA1" => Ok(AssocType::HmacSha1),
+            "HMAC-SHA256" => Ok(AssocType::HmacSha256),
+            _ => anyhow::bail!("unknown association type {:?}", s),
+        }
+    }
+}
+
+/// Shared secret established with an OP through an `associate` request
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.8>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Association {
+    /// `assoc_handle` the OP refers to the association by
+    pub handle: String,
+    pub assoc_type: AssocType,
+    /// Base 64 encoded MAC key
+    pub mac_key: String,
+    /// Issue time plus the `expires_in` of the `associate` response
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Association {
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+}
+
+/// Result of verifying an assertion with an [`Association`] instead of asking the OP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    /// The signature was made with the MAC key of the association
+    Valid,
+    /// The assertion claims to be signed with the association but it wasn't
+    InvalidSignature,
+    /// The assertion was signed with another association, the OP has to verify it
+    HandleMismatch,
+    /// The association can't be used anymore, the OP has to verify it
+    Expired,
+}
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.6.1>
+///
+/// Key-Value Form of the signed fields in the order they are listed in `openid.signed`.
+/// The keys are expected _without_ the `openid.` prefix.
+pub fn make_base_string<'a>(fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut buffer = String::new();
+    for (key, value) in fields {
+        buffer.push_str(key);
+        buffer.push(':');
+        buffer.push_str(value);
+        buffer.push('\n');
+    }
+    buffer
+}
+
+fn verify_mac<M: Mac + KeyInit>(
+    mac_key: &[u8],
+    base_string: &str,
+    signature: &[u8],
+) -> anyhow::Result<bool> {
+    let mut mac = <M as Mac>::new_from_slice(mac_key).context("invalid mac key length")?;
+    mac.update(base_string.as_bytes());
+
+    // `verify_slice` compares in constant time
+    Ok(mac.verify_slice(signature).is_ok())
+}
+
+/// Decode the base64 encoded MAC key and signature and
+/// check the signature over the base string with the algorithm of the association.
+pub fn verify_signature(
+    assoc_type: AssocType,
+    mac_key_b64: &str,
+    base_string: &str,
+    signature_b64: &str,
+) -> anyhow::Result<bool> {
+    use base64::engine::general_purpose::STANDARD as Base64;
+    use base64::Engine;
+
+    let mac_key = Base64
+        .decode(mac_key_b64)
+        .context("couldn't decode mac key")?;
+    let signature = Base64
+        .decode(signature_b64)
+        .context("couldn't decode signature")?;
+
+    match assoc_type {
+        AssocType::HmacSha1 => verify_mac::<Hmac<Sha1>>(&mac_key, base_string, &signature),
+        AssocType::HmacSha256 => verify_mac::<Hmac<Sha256>>(&mac_key, base_string, &signature),
+    }
+}
+
+/// Same as [`verify_signature`] but runs on the blocking thread pool.
+pub async fn verify_signature_blocking(
+    assoc_type: AssocType,
+    mac_key_b64: String,
+    base_string: String,
+    signature_b64: String,
+) -> anyhow::Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        verify_signature(assoc_type, &mac_key_b64, &base_string, &signature_b64)
+    })
+    .await
+    .context("signature verification task failed")?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAC_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const SIGNATURE: &str = "+jB1vYW4lNjPvaph0LXLyIADegip8dd9yyaR4vOvHQs=";
+    const SIGNATURE_SHA1: &str = "BhBLTmFuYqh2/EqDvKrshDFunFs=";
+    const FIELDS: [(&str, &str); 2] = [
+        ("op_endpoint", "https://steamcommunity.com/openid/login"),
+        ("return_to", "http://localhost:3000/auth/steam/callback/"),
+    ];
+
+    #[test]
+    fn base_string_works() {
+        assert_eq!(
+            make_base_string(FIELDS),
+            "op_endpoint:https://steamcommunity.com/openid/login\nreturn_to:http://localhost:3000/auth/steam/callback/\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_blocking_works() -> anyhow::Result<()> {
+        let base_string = make_base_string(FIELDS);
+
+        let valid = verify_signature_blocking(
+            AssocType::HmacSha256,
+            MAC_KEY.to_string(),
+            base_string.clone(),
+            SIGNATURE.to_string(),
+        )
+        .await?;
+        assert!(valid);
+
+        let tampered = base_string.replace("3000", "3001");
+        let valid = verify_signature_blocking(
+            AssocType::HmacSha256,
+            MAC_KEY.to_string(),
+            tampered,
+            SIGNATURE.to_string(),
+        )
+        .await?;
+        assert!(!valid);
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_per_assoc_type() -> anyhow::Result<()> {
+        let base_string = make_base_string(FIELDS);
+        let verify =
+            |assoc_type, signature| verify_signature(assoc_type, MAC_KEY, &base_string, signature);
+
+        assert!(verify(AssocType::HmacSha1, SIGNATURE_SHA1)?);
+        assert!(verify(AssocType::HmacSha256, SIGNATURE)?);
+        assert!(!verify(AssocType::HmacSha1, SIGNATURE)?);
+        assert!(!verify(AssocType::HmacSha256, SIGNATURE_SHA1)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn assoc_type_negotiation() -> anyhow::Result<()> {
+        let preference: Vec<_> =
+            std::iter::successors(Some(AssocType::default()), AssocType::fallback).collect();
+        assert_eq!(preference, [AssocType::HmacSha256, AssocType::HmacSha1]);
+
+        for assoc_type in preference {
+            assert_eq!(assoc_type.as_str().parse::<AssocType>()?, assoc_type);
+        }
+        assert!("HMAC-MD5".parse::<AssocType>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! XRDS documents for the mock servers of the tests to serve
+
+use crate::openid::constants::{OPENID_PROVIDER_IDENTIFIER, OPENID_SIGNON_IDENTIFIER};
+
+fn xrds(service_type: &str, endpoint: &str, local_id: Option<&str>) -> String {
+    let local_id = local_id
+        .map(|local_id| format!("<LocalID>{}</LocalID>", local_id))
+        .unwrap_or_default();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<xrds:XRDS xmlns:xrds="xri://$xrds" xmlns="xri://$xrd*($v*2.0)">
+    <XRD>
+        <Service priority="0">
+            <Type>{}</Type>
+            <URI>{}</URI>
+            {}
+        </Service>
+    </XRD>
+</xrds:XRDS>"#,
+        service_type, endpoint, local_id
+    )
+}
+
+/// XRDS of an OP Identifier like steam's, logins at `endpoint` select the identifier there
+pub(crate) fn op_xrds(endpoint: &str) -> String {
+    xrds(OPENID_PROVIDER_IDENTIFIER, endpoint, None)
+}
+
+/// XRDS of a claimed identifier served by the OP at `endpoint`, optionally delegated to `local_id`
+pub(crate) fn signon_xrds(endpoint: &str, local_id: Option<&str>) -> String {
+    xrds(OPENID_SIGNON_IDENTIFIER, endpoint, local_id)
+}
@@ -8,15 +8,99 @@ use serde::{Deserialize, Serialize};
 
 /// - Serialize `["a", "b", "c"]` into `"a,b,c"`
 /// - Deserialize `"a,b,c"` into `["a", "b", "c"]`
+///
+/// # Empty elements
+///
+/// An empty string has no elements, any other string has one more element than commas,
+/// so `","` is two empty elements and `"a,,b"` has an empty one in the middle.
+/// Those have to parse into `T`, use [`CommaSeparated::from_str_skip_empty`] to drop them.
+/// The serde deserializer in [`comma_separated_impl`](super::comma_separated_impl) splits
+/// the same way but turns empty elements into `None` for a list of [`Option`]s.
+///
+/// # Example
+///
+/// ```
+/// use complainer_api::openid::comma_separated::CommaSeparated;
+///
+/// let ids = [76561198181282063u64, 76561197960287930u64];
+/// let ids = ids.iter().collect::<CommaSeparated<_>>();
+/// assert_eq!(ids.to_string(), "76561198181282063,76561197960287930");
+/// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) struct CommaSeparated<T>(Vec<T>);
+pub struct CommaSeparated<T>(Vec<T>);
 
 impl<T> CommaSeparated<T> {
-    pub(crate) fn into_inner(self) -> Vec<T> {
+    pub fn new(items: impl IntoIterator<Item = T>) -> CommaSeparated<T> {
+        items.into_iter().collect()
+    }
+    pub fn into_inner(self) -> Vec<T> {
         self.0
     }
+    /// For `#[serde(skip_serializing_if = "CommaSeparated::is_empty")]`,
+    /// an empty list would otherwise be serialized as an empty string
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<T> CommaSeparated<T>
+where
+    T: FromStr,
+{
+    /// Like [`CommaSeparated::from_str`] but empty elements are skipped,
+    /// `"a,,b"` is parsed as `["a", "b"]`
+    pub fn from_str_skip_empty(s: &str) -> anyhow::Result<Self> {
+        parse_elements(s, false, true).map(CommaSeparated)
+    }
+    /// Parse every element on its own instead of failing on the first invalid one
+    ///
+    /// Returns the parsed values and the index and raw text of every element that failed.
+    pub fn from_str_partial(s: &str) -> (Vec<T>, Vec<(usize, String)>) {
+        if s.is_empty() {
+            return (Vec::new(), Vec::new());
+        }
+
+        let mut parsed = Vec::new();
+        let mut failed = Vec::new();
+        for (index, part) in s.split(',').enumerate() {
+            match part.parse() {
+                Ok(value) => parsed.push(value),
+                Err(_) => failed.push((index, part.to_string())),
+            }
+        }
+        (parsed, failed)
+    }
+}
+
+impl<T> From<Vec<T>> for CommaSeparated<T> {
+    fn from(values: Vec<T>) -> Self {
+        CommaSeparated(values)
+    }
 }
 
+impl<T> FromIterator<T> for CommaSeparated<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        CommaSeparated(iter.into_iter().collect())
+    }
+}
+
+impl<T> IntoIterator for CommaSeparated<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a CommaSeparated<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Also provides indexing through [`Vec`]
 impl<T> Deref for CommaSeparated<T> {
     type Target = Vec<T>;
     fn deref(&self) -> &Self::Target {
@@ -24,29 +108,42 @@ impl<T> Deref for CommaSeparated<T> {
     }
 }
 
-impl<T> FromStr for CommaSeparated<T>
+/// Parse every element of `s`, trimming it first if `trim` is set
+/// and leaving it out if it's empty and `skip_empty` is set
+fn parse_elements<T>(s: &str, trim: bool, skip_empty: bool) -> anyhow::Result<Vec<T>>
 where
     T: FromStr,
 {
-    type Err = anyhow::Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() {
-            return Ok(CommaSeparated(Vec::new()));
-        }
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
 
-        let len = s.chars().filter(|&c| c == ',').count();
-        let mut buffer: Vec<T> = Vec::with_capacity(len + 1);
+    let len = s.chars().filter(|&c| c == ',').count();
+    let mut buffer: Vec<T> = Vec::with_capacity(len + 1);
 
-        let parts = s.split(',');
-        for part in parts {
-            // TODO: Error conversion
-            match part.parse() {
-                Err(_) => return Err(anyhow!("couldn't parse the thingy ( ˘︹˘ )")),
-                Ok(parsed) => buffer.push(parsed),
-            };
+    let parts = s.split(',');
+    for part in parts {
+        let part = if trim { part.trim() } else { part };
+        if skip_empty && part.is_empty() {
+            continue;
         }
+        // TODO: Error conversion
+        match part.parse() {
+            Err(_) => return Err(anyhow!("couldn't parse the thingy ( ˘︹˘ )")),
+            Ok(parsed) => buffer.push(parsed),
+        };
+    }
 
-        Ok(CommaSeparated(buffer))
+    Ok(buffer)
+}
+
+impl<T> FromStr for CommaSeparated<T>
+where
+    T: FromStr,
+{
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_elements(s, false, false).map(CommaSeparated)
     }
 }
 
@@ -93,6 +190,85 @@ where
     }
 }
 
+/// Like [`CommaSeparated`] but surrounding whitespace of every element is trimmed
+///
+/// For lists that are typed by hand, e.g. `"a, b, c"`. Serializes without the spaces.
+///
+/// # Example
+///
+/// ```
+/// use complainer_api::openid::comma_separated::CommaSeparatedTrimmed;
+///
+/// let ids: CommaSeparatedTrimmed<u64> = "1, 2 ,3".parse().unwrap();
+/// assert_eq!(ids.to_string(), "1,2,3");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommaSeparatedTrimmed<T>(CommaSeparated<T>);
+
+impl<T> CommaSeparatedTrimmed<T> {
+    pub fn into_inner(self) -> Vec<T> {
+        self.0.into_inner()
+    }
+}
+
+impl<T> From<CommaSeparatedTrimmed<T>> for CommaSeparated<T> {
+    fn from(trimmed: CommaSeparatedTrimmed<T>) -> Self {
+        trimmed.0
+    }
+}
+
+impl<T> Deref for CommaSeparatedTrimmed<T> {
+    type Target = CommaSeparated<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> FromStr for CommaSeparatedTrimmed<T>
+where
+    T: FromStr,
+{
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_elements(s, true, false).map(|values| CommaSeparatedTrimmed(CommaSeparated(values)))
+    }
+}
+
+impl<T> Display for CommaSeparatedTrimmed<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.to_string())
+    }
+}
+
+impl<'de, T> Deserialize<'de> for CommaSeparatedTrimmed<T>
+where
+    T: FromStr,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let str = Cow::<'de, str>::deserialize(deserializer)?;
+        let cs = CommaSeparatedTrimmed::from_str(&str).map_err(serde::de::Error::custom)?;
+        Ok(cs)
+    }
+}
+
+impl<T> Serialize for CommaSeparatedTrimmed<T>
+where
+    T: Display,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -101,7 +277,7 @@ mod test {
     use serde::{Deserialize, Serialize};
     use steam_api_concurrent::SteamId;
 
-    use super::CommaSeparated;
+    use super::{CommaSeparated, CommaSeparatedTrimmed};
 
     const SERIALIZED: &str = "a,b,c,d,e";
     const DESERIALIZED: [&str; 5] = ["a", "b", "c", "d", "e"];
@@ -130,6 +306,79 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn collect_works() {
+        let collected: CommaSeparated<&str> = DESERIALIZED.into_iter().collect();
+        assert_eq!(collected.to_string(), SERIALIZED);
+        assert_eq!(CommaSeparated::new(DESERIALIZED), collected);
+    }
+
+    #[test]
+    fn from_str_partial_keeps_valid_ids() {
+        let (parsed, failed) = CommaSeparated::<SteamId>::from_str_partial(
+            "76561198181282063,nope,76561197960287930,,-1",
+        );
+        assert_eq!(
+            parsed,
+            [SteamId(76561198181282063), SteamId(76561197960287930)]
+        );
+        assert_eq!(
+            failed,
+            [
+                (1, "nope".to_string()),
+                (3, String::new()),
+                (4, "-1".to_string())
+            ]
+        );
+
+        let (parsed, failed) = CommaSeparated::<SteamId>::from_str_partial("");
+        assert!(parsed.is_empty());
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn empty_list_can_be_skipped() -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct Test {
+            a: CommaSeparated<u64>,
+            #[serde(skip_serializing_if = "CommaSeparated::is_empty")]
+            b: CommaSeparated<u64>,
+        }
+
+        let empty = Test {
+            a: CommaSeparated::new([]),
+            b: CommaSeparated::new([]),
+        };
+        assert_eq!(serde_urlencoded::to_string(&empty)?, "a=");
+
+        let filled = Test {
+            a: CommaSeparated::new([1]),
+            b: CommaSeparated::new([2, 3]),
+        };
+        assert_eq!(serde_urlencoded::to_string(&filled)?, "a=1&b=2%2C3");
+
+        Ok(())
+    }
+
+    #[test]
+    fn iterate_directly() -> anyhow::Result<()> {
+        let ids = CommaSeparated::<SteamId>::from_str("76561198181282063,76561197960287930")?;
+        assert_eq!(ids[1], SteamId(76561197960287930));
+
+        let mut borrowed = Vec::new();
+        for id in &ids {
+            borrowed.push(*id);
+        }
+        let owned: Vec<SteamId> = ids.into_iter().collect();
+        assert_eq!(borrowed, owned);
+        assert_eq!(
+            owned,
+            [SteamId(76561198181282063), SteamId(76561197960287930)]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn parses_steam_id_url() -> anyhow::Result<()> {
         #[derive(Deserialize, Serialize, PartialEq, Eq, Debug)]
@@ -167,4 +416,63 @@ mod test {
         assert_eq!(Ok(result), serde_urlencoded::from_str(input));
         Ok(())
     }
+
+    #[test]
+    fn trimmed_ignores_spaces_around_elements() -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct Test {
+            steam_ids: CommaSeparatedTrimmed<SteamId>,
+        }
+
+        let parsed = CommaSeparatedTrimmed::<u64>::from_str("1, 2, 3")?;
+        assert_eq!(parsed.into_inner(), [1, 2, 3]);
+
+        let parsed =
+            CommaSeparatedTrimmed::<SteamId>::from_str(" 76561198181282063 ,\t76561197960287930")?;
+        assert_eq!(
+            *parsed,
+            CommaSeparated::new([SteamId(76561198181282063), SteamId(76561197960287930)])
+        );
+        assert_eq!(parsed.to_string(), "76561198181282063,76561197960287930");
+
+        // the default keeps whitespace significant
+        assert!(CommaSeparated::<u64>::from_str("1, 2, 3").is_err());
+        assert_eq!(
+            CommaSeparated::<String>::from_str("a, b")?.into_inner(),
+            ["a", " b"]
+        );
+
+        let query: Test =
+            serde_urlencoded::from_str("steam_ids=76561198181282063,%2076561197960287930")?;
+        assert_eq!(query.steam_ids.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_elements() -> anyhow::Result<()> {
+        assert!(CommaSeparated::<String>::from_str("")?.is_empty());
+        assert_eq!(CommaSeparated::<String>::from_str("a")?.into_inner(), ["a"]);
+        assert_eq!(
+            CommaSeparated::<String>::from_str(",")?.into_inner(),
+            ["", ""]
+        );
+        assert_eq!(
+            CommaSeparated::<String>::from_str("a,,b")?.into_inner(),
+            ["a", "", "b"]
+        );
+        assert!(CommaSeparated::<u64>::from_str("1,,2").is_err());
+
+        assert_eq!(
+            CommaSeparated::<String>::from_str_skip_empty("a,,b")?.into_inner(),
+            ["a", "b"]
+        );
+        assert_eq!(
+            CommaSeparated::<u64>::from_str_skip_empty(",1,,2,")?.into_inner(),
+            [1, 2]
+        );
+        assert!(CommaSeparated::<String>::from_str_skip_empty(",")?.is_empty());
+
+        Ok(())
+    }
 }
@@ -4,6 +4,8 @@ use serde::de::{self, SeqAccess};
 use serde::{ser, Deserialize};
 use thiserror::Error;
 
+use crate::openid::util::parse_bool;
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -40,6 +42,8 @@ pub enum Error {
     ExpectedValue,
     #[error("expected a comma")]
     ExpectedComma,
+    #[error("element contains a comma")]
+    ElementContainsComma,
 }
 
 impl ser::Error for Error {
@@ -67,17 +71,14 @@ impl<'de> Deserializer<'de> {
 
 impl<'de> Deserializer<'de> {
     /// In the case of a HashMap, a value can be used as a 'key'
-    fn consume_value(&mut self) -> Result<&'de str> {
-        // a value is supposed to be here but theres nothing...
-        if self.inner.is_empty() {
-            return Err(Error::Eof);
-        }
-
+    ///
+    /// An element after a trailing comma is empty, just like one between two commas.
+    fn consume_value(&mut self) -> &'de str {
         match self.inner.find(',') {
             None => {
                 // there is no comma after this, so the whole
                 // thing has to be the value
-                Ok(std::mem::take(&mut self.inner))
+                std::mem::take(&mut self.inner)
             }
             Some(index) => {
                 // there is a comma after this, take the part
@@ -85,18 +86,14 @@ impl<'de> Deserializer<'de> {
                 // after it
                 let (value, remainder) = self.inner.split_at(index);
                 self.inner = remainder;
-                Ok(value)
+                value
             }
         }
     }
-    fn peek_value(&self) -> Result<&'de str> {
-        if self.inner.is_empty() {
-            return Err(Error::Eof);
-        }
-
+    fn peek_value(&self) -> &'de str {
         self.inner
             .find(',')
-            .map_or(Ok(self.inner), |index| (Ok(&self.inner[..index])))
+            .map_or(self.inner, |index| &self.inner[..index])
     }
 }
 
@@ -105,6 +102,10 @@ impl<'de> Deserializer<'de> {
 /// depending on what Rust types the deserializer is able to consume as input.
 ///
 /// This basic deserializer supports only `from_str`.
+///
+/// Empty elements are kept like in [`CommaSeparated`](crate::openid::comma_separated::CommaSeparated),
+/// `""` has no elements while `","` has two empty ones. Unlike there, an empty element
+/// deserializes into `None` if the element type is an [`Option`].
 pub fn from_str<'de, T>(s: &'de str) -> Result<T>
 where
     T: Deserialize<'de>,
@@ -118,7 +119,7 @@ macro_rules! deserialize_from_str {
         where
             V: de::Visitor<'de>,
         {
-            let value: $type = self.consume_value()?.parse()?;
+            let value: $type = self.consume_value().parse()?;
             visitor.$visit_method(value)
         }
     };
@@ -152,19 +153,43 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     deserialize_from_str!(u64, deserialize_u64, visit_u64);
     deserialize_from_str!(f32, deserialize_f32, visit_f32);
     deserialize_from_str!(f64, deserialize_f64, visit_f64);
-    deserialize_from_str!(bool, deserialize_bool, visit_bool);
 
     deserialize_not_implemented!(deserialize_bytes);
     deserialize_not_implemented!(deserialize_byte_buf);
-    deserialize_not_implemented!(deserialize_char);
     deserialize_not_implemented!(deserialize_map);
     deserialize_not_implemented!(deserialize_identifier);
 
+    fn deserialize_bool<V>(self, visitor: V) -> std::result::Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        let value = parse_bool(self.consume_value())?;
+        visitor.visit_bool(value)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> std::result::Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        // same as in the key-values deserializer, the element has to be exactly one char
+        let value = self.consume_value();
+        let mut chars = value.chars();
+
+        let Some(char) = chars.next() else {
+            return Err(Error::NoValue);
+        };
+        if chars.next().is_some() {
+            return Err(Error::ParseChar);
+        }
+
+        visitor.visit_char(char)
+    }
+
     fn deserialize_str<V>(self, visitor: V) -> std::result::Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        let value = self.consume_value()?;
+        let value = self.consume_value();
         visitor.visit_borrowed_str(value)
     }
 
@@ -193,7 +218,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: de::Visitor<'de>,
     {
-        let value = self.peek_value()?;
+        let value = self.peek_value();
         if value.is_empty() {
             return visitor.visit_none();
         }
@@ -204,7 +229,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: de::Visitor<'de>,
     {
-        let value = self.consume_value()?;
+        let value = self.consume_value();
         if value.is_empty() {
             return visitor.visit_unit();
         }
@@ -306,14 +331,18 @@ impl<'de, 'a> SeqAccess<'de> for CommaSeparatedSeqAccess<'a, 'de> {
     where
         T: de::DeserializeSeed<'de>,
     {
-        if self.de.inner.is_empty() {
-            return Ok(None);
-        }
-
         if self.is_first {
             self.is_first = false;
+            // an empty input is an empty list rather than a list with one empty element
+            if self.de.inner.is_empty() {
+                return Ok(None);
+            }
         } else {
-            self.de.inner = &self.de.inner[1..];
+            // every element but the first is introduced by a comma
+            match self.de.inner.strip_prefix(',') {
+                Some(remainder) => self.de.inner = remainder,
+                None => return Ok(None),
+            }
         }
 
         seed.deserialize(&mut *self.de).map(Some)
@@ -324,7 +353,7 @@ impl<'de, 'a> SeqAccess<'de> for CommaSeparatedSeqAccess<'a, 'de> {
 mod test {
     use steam_api_concurrent::SteamId;
 
-    use super::from_str;
+    use super::{from_str, Error};
 
     #[test]
     fn it_works() -> anyhow::Result<()> {
@@ -360,4 +389,37 @@ mod test {
         assert_eq!(Ok(result), from_str(input));
         Ok(())
     }
+
+    #[test]
+    fn parses_bool_ignoring_case() -> anyhow::Result<()> {
+        let input = "true,True,FALSE,false";
+        let result = vec![true, true, false, false];
+
+        assert_eq!(Ok(result), from_str(input));
+        assert!(from_str::<Vec<bool>>("true,1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_chars() -> anyhow::Result<()> {
+        let input = "a,b,c";
+        let result = vec!['a', 'b', 'c'];
+
+        assert_eq!(Ok(result), from_str(input));
+        assert_eq!(Err(Error::ParseChar), from_str::<Vec<char>>("ab,c"));
+        assert_eq!(Err(Error::NoValue), from_str::<Vec<char>>("a,,c"));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_elements() -> anyhow::Result<()> {
+        assert_eq!(Ok(vec![String::new(); 2]), from_str::<Vec<String>>(","));
+        assert_eq!(Ok(vec!["a".to_string()]), from_str::<Vec<String>>("a"));
+        assert_eq!(
+            Ok(vec![Some(SteamId(76561198181282063)), None]),
+            from_str("76561198181282063,")
+        );
+        assert!(from_str::<Vec<u64>>("1,,2").is_err());
+        Ok(())
+    }
 }
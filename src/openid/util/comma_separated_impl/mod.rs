@@ -1 +1,4 @@
 mod de;
+mod ser;
+pub use de::{from_str, Error};
+pub use ser::to_string;
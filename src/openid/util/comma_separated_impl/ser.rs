@@ -0,0 +1,266 @@
+use serde::ser::{self, Impossible, Serialize};
+
+use super::de::Error;
+
+type Result<T> = std::result::Result<T, Error>;
+
+struct Serializer {
+    /// The output string
+    output: String,
+    /// Whether a sequence is being serialized, only one level is supported
+    in_seq: bool,
+}
+
+/// Counterpart of [`from_str`](super::from_str), a sequence is serialized
+/// into its elements joined by commas and anything else into a single element.
+///
+/// Supports the same types as the deserializer, maps, structs and enums are rejected,
+/// so are nested sequences and elements that contain a comma themselves.
+/// `None` is serialized as an empty element.
+///
+/// Like [`CommaSeparated`](crate::openid::comma_separated::CommaSeparated), a sequence
+/// with a single empty element serializes to `""`, which is parsed as no elements at all.
+pub fn to_string<T>(value: &T) -> Result<String>
+where
+    T: Serialize + ?Sized,
+{
+    let mut serializer = Serializer {
+        output: String::new(),
+        in_seq: false,
+    };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+macro_rules! serialize_display {
+    ($type:ty, $serialize_method:ident) => {
+        fn $serialize_method(self, v: $type) -> Result<()> {
+            self.output.push_str(&v.to_string());
+            Ok(())
+        }
+    };
+}
+macro_rules! serialize_not_implemented {
+    ($serialize_method:ident, $type:ty) => {
+        fn $serialize_method(self, _v: $type) -> Result<()> {
+            Err(Error::NotImplemented(stringify!($serialize_method)))
+        }
+    };
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = CommaSeparatedSeq<'a>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = Impossible<(), Error>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    serialize_display!(bool, serialize_bool);
+    serialize_display!(i8, serialize_i8);
+    serialize_display!(i16, serialize_i16);
+    serialize_display!(i32, serialize_i32);
+    serialize_display!(i64, serialize_i64);
+    serialize_display!(u8, serialize_u8);
+    serialize_display!(u16, serialize_u16);
+    serialize_display!(u32, serialize_u32);
+    serialize_display!(u64, serialize_u64);
+    serialize_display!(f32, serialize_f32);
+    serialize_display!(f64, serialize_f64);
+
+    serialize_not_implemented!(serialize_bytes, &[u8]);
+    serialize_not_implemented!(serialize_unit_struct, &'static str);
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        if v == ',' {
+            return Err(Error::ElementContainsComma);
+        }
+        self.output.push(v);
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        if v.contains(',') {
+            return Err(Error::ElementContainsComma);
+        }
+        self.output.push_str(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        Err(Error::NotImplemented("serialize_unit_variant"))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(Error::NotImplemented("serialize_newtype_variant"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        if self.in_seq {
+            return Err(Error::NotImplemented("nested sequences"));
+        }
+        self.in_seq = true;
+        Ok(CommaSeparatedSeq {
+            ser: self,
+            is_first: true,
+        })
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(Error::NotImplemented("serialize_tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(Error::NotImplemented("serialize_tuple_struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Error::NotImplemented("serialize_tuple_variant"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(Error::NotImplemented("serialize_map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Err(Error::NotImplemented("serialize_struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(Error::NotImplemented("serialize_struct_variant"))
+    }
+}
+
+struct CommaSeparatedSeq<'a> {
+    ser: &'a mut Serializer,
+    is_first: bool,
+}
+
+impl<'a> ser::SerializeSeq for CommaSeparatedSeq<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        // every element but the first is introduced by a comma
+        if self.is_first {
+            self.is_first = false;
+        } else {
+            self.ser.output.push(',');
+        }
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use steam_api_concurrent::SteamId;
+
+    use super::{to_string, Error};
+    use crate::openid::comma_separated::CommaSeparated;
+
+    #[test]
+    fn serializes_steam_ids() -> anyhow::Result<()> {
+        let ids = vec![SteamId(76561198181282063), SteamId(76561197960287930)];
+
+        assert_eq!(
+            to_string(&ids)?,
+            CommaSeparated::from(ids.clone()).to_string()
+        );
+        assert_eq!(to_string(&ids)?, "76561198181282063,76561197960287930");
+        assert_eq!(to_string(&Vec::<SteamId>::new())?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn serializes_options_as_empty_elements() -> anyhow::Result<()> {
+        let ids = vec![Some(SteamId(76561198181282063)), None, Some(SteamId(1))];
+
+        assert_eq!(to_string(&ids)?, "76561198181282063,,1");
+        Ok(())
+    }
+
+    #[test]
+    fn serializes_scalars_as_single_element() -> anyhow::Result<()> {
+        assert_eq!(to_string(&true)?, "true");
+        assert_eq!(to_string(&'a')?, "a");
+        assert_eq!(to_string("value")?, "value");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_what_cant_be_deserialized() {
+        let map: HashMap<String, u64> = HashMap::from([("a".to_string(), 1)]);
+
+        assert!(matches!(to_string(&map), Err(Error::NotImplemented(_))));
+        assert!(matches!(
+            to_string(&vec![vec![1u64]]),
+            Err(Error::NotImplemented(_))
+        ));
+        assert_eq!(to_string(&vec!["a,b"]), Err(Error::ElementContainsComma));
+    }
+}
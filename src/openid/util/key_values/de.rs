@@ -1,9 +1,12 @@
+use std::collections::HashSet;
 use std::fmt::Display;
 
 use serde::de::{self, MapAccess};
 use serde::{ser, Deserialize};
 use thiserror::Error;
 
+use crate::openid::util::parse_bool;
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, Error, PartialEq, Eq)]
@@ -38,6 +41,12 @@ pub enum Error {
     ExpectedKey,
     #[error("expected to parse a value")]
     ExpectedValue,
+    #[error("duplicate key `{0}`")]
+    DuplicateKey(String),
+    #[error("key {0:?} is empty or contains a colon or a newline")]
+    InvalidKey(String),
+    #[error("value {0:?} contains a newline")]
+    InvalidValue(String),
 }
 
 impl ser::Error for Error {
@@ -69,6 +78,8 @@ struct Deserializer<'de> {
     ///     ^
     /// ```
     consumed_key: bool,
+    /// Whether a repeated key is an error instead of being passed on to the visitor
+    strict: bool,
 }
 
 impl<'de> Deserializer<'de> {
@@ -76,6 +87,14 @@ impl<'de> Deserializer<'de> {
         Deserializer {
             inner: input,
             consumed_key: false,
+            strict: false,
+        }
+    }
+    const fn from_str_strict(input: &'de str) -> Self {
+        Deserializer {
+            inner: input,
+            consumed_key: false,
+            strict: true,
         }
     }
 }
@@ -88,6 +107,10 @@ impl<'de> Deserializer<'de> {
         let Some((key, remainder)) = self.inner.split_once(':') else {
             return Err(Error::ExpectedColon);
         };
+        // the line ended before the colon
+        if key.contains('\n') {
+            return Err(Error::ExpectedColon);
+        }
         self.inner = remainder;
         self.consumed_key = true;
         Ok(key)
@@ -99,6 +122,9 @@ impl<'de> Deserializer<'de> {
         let Some((key, _)) = self.inner.split_once(':') else {
             return Err(Error::ExpectedColon);
         };
+        if key.contains('\n') {
+            return Err(Error::ExpectedColon);
+        }
         Ok(key)
     }
 
@@ -137,6 +163,19 @@ where
     T::deserialize(&mut Deserializer::from_str(s))
 }
 
+/// Like [`from_str`] but a key occurring more than once is an error
+///
+/// Key-value form encoded messages must not contain duplicate keys, the lenient
+/// [`from_str`] lets a `HashMap` silently keep the last value.
+///
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.4.1.1>
+pub fn from_str_strict<'de, T>(s: &'de str) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(&mut Deserializer::from_str_strict(s))
+}
+
 macro_rules! deserialize_from_str {
     ($type:ty, $deserialize_method:ident, $visit_method:ident) => {
         fn $deserialize_method<V>(self, visitor: V) -> std::result::Result<V::Value, Self::Error>
@@ -178,13 +217,20 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     deserialize_from_str!(u64, deserialize_u64, visit_u64);
     deserialize_from_str!(f32, deserialize_f32, visit_f32);
     deserialize_from_str!(f64, deserialize_f64, visit_f64);
-    deserialize_from_str!(bool, deserialize_bool, visit_bool);
 
     deserialize_not_implemented!(deserialize_bytes);
     deserialize_not_implemented!(deserialize_byte_buf);
     deserialize_not_implemented!(deserialize_any);
     deserialize_not_implemented!(deserialize_seq);
 
+    fn deserialize_bool<V>(self, visitor: V) -> std::result::Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        let value = parse_bool(self.consume_value()?)?;
+        visitor.visit_bool(value)
+    }
+
     fn deserialize_str<V>(self, visitor: V) -> std::result::Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
@@ -302,7 +348,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_map(KeyValueMapAccess { de: self })
+        let seen = self.strict.then(HashSet::new);
+        visitor.visit_map(KeyValueMapAccess { de: self, seen })
     }
 
     fn deserialize_struct<V>(
@@ -340,6 +387,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
 struct KeyValueMapAccess<'a, 'de: 'a> {
     de: &'a mut Deserializer<'de>,
+    /// The keys visited so far, only tracked in strict mode
+    seen: Option<HashSet<&'de str>>,
 }
 
 impl<'de, 'a> MapAccess<'de> for KeyValueMapAccess<'a, 'de> {
@@ -359,6 +408,14 @@ impl<'de, 'a> MapAccess<'de> for KeyValueMapAccess<'a, 'de> {
             return Err(Error::ExpectedColon);
         };
 
+        // refuse keys we've already seen
+        if let Some(seen) = &mut self.seen {
+            let key = self.de.peek_key()?;
+            if !seen.insert(key) {
+                return Err(Error::DuplicateKey(key.to_string()));
+            }
+        }
+
         // deserialize the key
         seed.deserialize(&mut *self.de).map(Some)
     }
@@ -384,7 +441,7 @@ mod test {
     use anyhow::Context;
     use serde::Deserialize;
 
-    use super::from_str;
+    use super::{from_str, from_str_strict, Error};
 
     macro_rules! assert_parse_error {
         ($input:literal) => {{
@@ -498,6 +555,17 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn deserialize_line_without_colon() -> anyhow::Result<()> {
+        let parsed = from_str::<HashMap<String, String>>("novalue\nkey:val\n");
+        assert_eq!(parsed, Err(Error::ExpectedColon));
+
+        let parsed = from_str::<HashMap<String, String>>("a:a\nnovalue\nkey:val\n");
+        assert_eq!(parsed, Err(Error::ExpectedColon));
+
+        Ok(())
+    }
+
     #[test]
     fn deserialize_duplicate_identifier() -> anyhow::Result<()> {
         let input = "a:1\na:-1\n";
@@ -509,6 +577,21 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn deserialize_duplicate_identifier_strict() -> anyhow::Result<()> {
+        let input = "a:1\nb:2\na:-1\n";
+
+        let parsed = from_str_strict::<HashMap<String, i32>>(input);
+        assert_eq!(parsed, Err(Error::DuplicateKey("a".to_string())));
+
+        let parsed =
+            from_str_strict::<HashMap<String, i32>>("a:1\nb:2\n").context("parsing failed")?;
+        assert_eq!(parsed.get("a"), Some(&1));
+        assert_eq!(parsed.get("b"), Some(&2));
+
+        Ok(())
+    }
+
     #[test]
     fn deserialize_bool_struct() -> anyhow::Result<()> {
         let input = "a:true\nb:false\n";
@@ -526,6 +609,19 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn deserialize_bool_casing() -> anyhow::Result<()> {
+        let input = "a:True\nb:FALSE\n";
+
+        let parsed = from_str::<HashMap<String, bool>>(input).context("parsing failed")?;
+        assert_eq!(parsed.get("a"), Some(&true));
+        assert_eq!(parsed.get("b"), Some(&false));
+
+        assert!(from_str::<HashMap<String, bool>>("a:yes\n").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn deserialize_duplicate_identifier_sturct() -> anyhow::Result<()> {
         let input = "a:true\na:false\n";
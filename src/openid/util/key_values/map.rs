@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use super::de::{from_str, from_str_strict, Error};
+
+/// Untyped key-value form message, for responses where only some fields are of interest
+///
+/// Fields keep the order they were parsed or inserted in, as signatures depend on it.
+///
+/// Use [`super::from_str`] to deserialize into a struct if all fields are known,
+/// or [`KeyValues::deserialize_into`] if the message was already parsed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyValues(Vec<(String, String)>);
+
+impl KeyValues {
+    pub fn into_inner(self) -> Vec<(String, String)> {
+        self.0
+    }
+    /// Raw value of `key`
+    ///
+    /// Messages only have a handful of fields, so this is a linear search.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+    /// Value of `key` parsed as `T`, `None` if the key is missing
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.get(key).map(str::parse)
+    }
+    /// Serialize into `key:value\n` lines
+    ///
+    /// There is no escaping in key-value form, so a key containing a colon or a newline
+    /// and a value containing a newline are rejected instead of corrupting the output.
+    /// Empty and duplicate keys are rejected as well, [`KeyValues::from_str`] wouldn't
+    /// parse the output.
+    ///
+    /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.4.1.1>
+    pub fn try_to_string(&self) -> Result<String, Error> {
+        let mut buffer = String::new();
+        let mut seen = HashSet::with_capacity(self.0.len());
+        for (key, value) in &self.0 {
+            if key.is_empty() || key.contains([':', '\n']) {
+                return Err(Error::InvalidKey(key.clone()));
+            }
+            if !seen.insert(key) {
+                return Err(Error::DuplicateKey(key.clone()));
+            }
+            if value.contains('\n') {
+                return Err(Error::InvalidValue(value.clone()));
+            }
+            // writing to a string can't fail
+            let _ = writeln!(buffer, "{}:{}", key, value);
+        }
+        Ok(buffer)
+    }
+    /// Deserialize into `T` as if the message was parsed with [`super::from_str`]
+    ///
+    /// The fields go through the key-value text again, so `#[serde(rename)]`
+    /// and the lenient bool parsing apply just like they do there.
+    pub fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let serialized = self.try_to_string()?;
+        from_str(&serialized)
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for KeyValues
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        KeyValues(
+            iter.into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// Collects the fields in the order they are visited
+struct KeyValuesVisitor;
+
+impl<'de> Visitor<'de> for KeyValuesVisitor {
+    type Value = KeyValues;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("key-value pairs")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut fields = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(field) = map.next_entry()? {
+            fields.push(field);
+        }
+        Ok(KeyValues(fields))
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyValues {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(KeyValuesVisitor)
+    }
+}
+
+/// Duplicate keys are rejected, see [`from_str_strict`]
+impl FromStr for KeyValues {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        from_str_strict(s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Context;
+
+    use super::*;
+
+    const SERIALIZED_1: &str = "ns:http://specs.openid.net/auth/2.0\nis_valid:true\nexpires_in:3600\nopenid.sreg.nickname:crab\n";
+
+    #[test]
+    fn get_works() -> anyhow::Result<()> {
+        let parsed = KeyValues::from_str(SERIALIZED_1).context("parsing failed")?;
+
+        assert_eq!(parsed.get("ns"), Some("http://specs.openid.net/auth/2.0"));
+        assert_eq!(parsed.get("openid.sreg.nickname"), Some("crab"));
+        assert_eq!(parsed.get("missing"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_parsed_works() -> anyhow::Result<()> {
+        let parsed = KeyValues::from_str(SERIALIZED_1).context("parsing failed")?;
+
+        assert_eq!(parsed.get_parsed::<bool>("is_valid"), Some(Ok(true)));
+        assert_eq!(parsed.get_parsed::<u32>("expires_in"), Some(Ok(3600)));
+        assert!(matches!(parsed.get_parsed::<u32>("ns"), Some(Err(_))));
+        assert_eq!(parsed.get_parsed::<u32>("missing"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_string_works() -> anyhow::Result<()> {
+        let parsed = KeyValues::from_str(SERIALIZED_1).context("parsing failed")?;
+        assert_eq!(parsed.try_to_string()?, SERIALIZED_1);
+        Ok(())
+    }
+
+    #[test]
+    fn insertion_order_is_kept() -> anyhow::Result<()> {
+        let fields = [("z", "1"), ("a", "2"), ("m", "3")];
+        let key_values = KeyValues::from_iter(fields);
+        assert_eq!(key_values.try_to_string()?, "z:1\na:2\nm:3\n");
+
+        let parsed = KeyValues::from_str("z:1\na:2\nm:3\n")?;
+        assert_eq!(parsed, key_values);
+        assert_eq!(
+            parsed.into_inner(),
+            fields.map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn to_string_rejects_unrepresentable() {
+        let colon_key = KeyValues::from_iter([("a:b", "c")]);
+        assert_eq!(
+            colon_key.try_to_string(),
+            Err(Error::InvalidKey("a:b".to_string()))
+        );
+
+        let newline_key = KeyValues::from_iter([("a\nb", "c")]);
+        assert_eq!(
+            newline_key.try_to_string(),
+            Err(Error::InvalidKey("a\nb".to_string()))
+        );
+
+        let newline_value = KeyValues::from_iter([("a", "b\nc:d")]);
+        assert_eq!(
+            newline_value.try_to_string(),
+            Err(Error::InvalidValue("b\nc:d".to_string()))
+        );
+
+        // a colon in the value is fine, only the first one separates the key
+        let colon_value = KeyValues::from_iter([("a", "b:c")]);
+        assert_eq!(colon_value.try_to_string(), Ok("a:b:c\n".to_string()));
+    }
+
+    #[test]
+    fn to_string_rejects_empty_key() {
+        let empty_key = KeyValues::from_iter([("a", "1"), ("", "2")]);
+        assert_eq!(
+            empty_key.try_to_string(),
+            Err(Error::InvalidKey(String::new()))
+        );
+    }
+
+    #[test]
+    fn to_string_rejects_duplicate_keys() {
+        let duplicate = KeyValues::from_iter([("a", "1"), ("b", "2"), ("a", "3")]);
+        assert_eq!(
+            duplicate.try_to_string(),
+            Err(Error::DuplicateKey("a".to_string()))
+        );
+        assert!(duplicate.deserialize_into::<KeyValues>().is_err());
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        assert_eq!(
+            KeyValues::from_str("a:1\na:2\n"),
+            Err(Error::DuplicateKey("a".to_string()))
+        );
+    }
+}
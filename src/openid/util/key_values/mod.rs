@@ -27,4 +27,6 @@
 //! ```
 
 mod de;
-pub use de::{from_str, Error};
+mod map;
+pub use de::{from_str, from_str_strict, Error};
+pub use map::KeyValues;
@@ -1,5 +1,20 @@
-pub(crate) mod comma_separated;
-pub(crate) mod comma_separated_impl;
-pub(crate) mod key_values;
-pub(crate) mod nonce;
+pub mod comma_separated;
+pub mod comma_separated_impl;
+pub mod key_values;
+pub mod nonce;
+#[cfg(test)]
+mod round_trip;
 pub(crate) mod xml;
+
+/// Like `str::parse::<bool>` but ignores ASCII casing, some providers send `True`
+///
+/// Shared by the key-values and comma-separated deserializers.
+pub(crate) fn parse_bool(value: &str) -> Result<bool, std::str::ParseBoolError> {
+    if value.eq_ignore_ascii_case("true") {
+        Ok(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Ok(false)
+    } else {
+        value.parse()
+    }
+}
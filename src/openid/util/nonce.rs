@@ -11,10 +11,55 @@ use crate::openid::constants::OPENID_RESPONSE_NONCE_MAX_LEN;
 /// the response seems reasonable.
 const NONCE_MAX_AGE_MS: i64 = 30_000;
 
-#[derive(Debug, Clone)]
-pub(crate) struct Nonce {
-    pub(crate) time: DateTime<Utc>,
-    pub(crate) salt: String,
+/// Default for [`NonceTolerance::grace_ms`], a nonce that expires
+/// while the user is on the way back should still be accepted.
+pub const DEFAULT_NONCE_GRACE_MS: i64 = 2_000;
+
+/// Length of the time in the format `2005-05-15T17:11:51Z`
+const NONCE_TIME_LEN: usize = "YYYY-MM-DDTHH:MM:SSZ".len();
+
+/// Default for [`NonceTolerance::max_skew_ms`]
+pub const DEFAULT_NONCE_MAX_SKEW_MS: i64 = 5_000;
+
+/// How lenient the time checks of the response nonce are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceTolerance {
+    /// Still accepted for this long after it expired
+    pub grace_ms: i64,
+    /// Rejected if the timestamp is further than this in the future
+    pub max_skew_ms: i64,
+}
+
+impl NonceTolerance {
+    /// How long after it was first received a nonce may still pass the time checks,
+    /// one from up to `max_skew_ms` in the future stays valid for that much longer
+    pub const fn window_ms(&self) -> i64 {
+        NONCE_MAX_AGE_MS + self.grace_ms + self.max_skew_ms
+    }
+}
+
+impl Default for NonceTolerance {
+    fn default() -> NonceTolerance {
+        NonceTolerance {
+            grace_ms: DEFAULT_NONCE_GRACE_MS,
+            max_skew_ms: DEFAULT_NONCE_MAX_SKEW_MS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nonce {
+    time: DateTime<Utc>,
+    salt: String,
+    /// The nonce exactly as the OP sent it, it is part of the signed data
+    /// and the timestamp may be written differently from what we'd produce
+    raw: String,
+}
+
+/// The salt MAY contain ASCII characters in the range 33-126 inclusive,
+/// see [`OPENID_RESPONSE_NONCE`](crate::openid::constants::OPENID_RESPONSE_NONCE)
+const fn is_salt_char(c: u8) -> bool {
+    matches!(c, 33..=126)
 }
 
 impl FromStr for Nonce {
@@ -24,44 +69,88 @@ impl FromStr for Nonce {
             anyhow::bail!("response nonce is too long");
         }
 
-        let last_time_char = nonce.find('Z').context("nonce doesn't adhere to spec")?;
-        let (time, salt) = nonce.split_at(last_time_char + 1);
+        // split at the fixed width timestamp instead of searching
+        // for the `Z` so the salt is free to contain any character
+        let time = nonce
+            .get(..NONCE_TIME_LEN)
+            .context("response nonce is too short to contain a time")?;
+        let salt = &nonce[NONCE_TIME_LEN..];
 
+        if time.contains('.') {
+            anyhow::bail!("response nonce time must not contain fractional seconds");
+        }
+        if !time.ends_with('Z') {
+            anyhow::bail!("response nonce time must be in UTC and end with `Z`");
+        }
         if salt.is_empty() {
             anyhow::bail!("response nonce doesn't contain a salt");
         }
+        if !salt.bytes().all(is_salt_char) {
+            anyhow::bail!("response nonce salt contains characters that aren't printable ascii");
+        }
 
         let salt = salt.to_string();
         let time: DateTime<Utc> = DateTime::from(
             DateTime::parse_from_rfc3339(time).context("couldn't parse date and time of nonce")?,
         );
 
-        Ok(Nonce { time, salt })
+        Ok(Nonce {
+            time,
+            salt,
+            raw: nonce.to_string(),
+        })
     }
 }
 
 impl ToString for Nonce {
     fn to_string(&self) -> String {
-        // Make sure it matches the expected format of
-        // `2001-02-03T04:05:06Z`
-        use chrono::SecondsFormat::Secs;
-        let mut buffer = self.time.to_rfc3339_opts(Secs, true);
-        buffer.push_str(&self.salt);
-        buffer
+        self.raw.clone()
     }
 }
 
 impl Nonce {
+    /// Nonce of our own, e.g. for a mock OP
+    pub fn new(time: DateTime<Utc>, salt: impl Into<String>) -> Nonce {
+        // Make sure it matches the expected format of
+        // `2001-02-03T04:05:06Z`
+        use chrono::SecondsFormat::Secs;
+        let salt = salt.into();
+        let mut raw = time.to_rfc3339_opts(Secs, true);
+        raw.push_str(&salt);
+        Nonce { time, salt, raw }
+    }
+    pub const fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+    /// The nonce as it was received, see [`Nonce::to_string`]
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+    /// Whether the nonce is older than [`NONCE_MAX_AGE_MS`] plus `grace_ms`
+    ///
     /// # Important!
     ///
     /// Timestamp from steam doesn't contain subseconds
     /// therefore it can be in the future by up to a second.
-    pub(crate) fn is_expired(&self) -> bool {
-        let now = Utc::now().timestamp_millis();
+    pub fn is_expired(&self, grace_ms: i64) -> bool {
+        self.is_expired_at(Utc::now(), grace_ms)
+    }
+    fn is_expired_at(&self, now: DateTime<Utc>, grace_ms: i64) -> bool {
+        let now = now.timestamp_millis();
         let then = self.time.timestamp_millis();
-        now - then > NONCE_MAX_AGE_MS
+        now - then > NONCE_MAX_AGE_MS + grace_ms
+    }
+    /// Whether the timestamp is more than `max_skew_ms` in the future,
+    /// otherwise a forged nonce from the far future would never expire.
+    pub fn is_from_future(&self, max_skew_ms: i64) -> bool {
+        self.is_from_future_at(Utc::now(), max_skew_ms)
     }
-    pub(crate) fn as_salt(&self) -> &str {
+    fn is_from_future_at(&self, now: DateTime<Utc>, max_skew_ms: i64) -> bool {
+        let now = now.timestamp_millis();
+        let then = self.time.timestamp_millis();
+        then - now > max_skew_ms
+    }
+    pub fn as_salt(&self) -> &str {
         &self.salt
     }
 }
@@ -82,7 +171,7 @@ impl Serialize for Nonce {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&self.to_string())
+        serializer.serialize_str(&self.raw)
     }
 }
 
@@ -91,21 +180,21 @@ mod test {
     use std::str::FromStr;
 
     use anyhow::Context;
-    use chrono::NaiveDate;
+    use chrono::{Duration, NaiveDate};
 
-    use super::Nonce;
+    use super::{Nonce, DEFAULT_NONCE_MAX_SKEW_MS, NONCE_MAX_AGE_MS};
 
     const NONCE: &str = "2023-09-15T11:23:46Z7RPb74voq1sqY2sKMcnOe/rxwQg=";
 
     fn expected_nonce() -> anyhow::Result<Nonce> {
-        Ok(Nonce {
-            time: NaiveDate::from_ymd_opt(2023, 9, 15)
+        Ok(Nonce::new(
+            NaiveDate::from_ymd_opt(2023, 9, 15)
                 .context("invalid y-m-d")?
                 .and_hms_opt(11, 23, 46)
                 .context("invalid h-m-s")?
                 .and_utc(),
-            salt: "7RPb74voq1sqY2sKMcnOe/rxwQg=".to_string(),
-        })
+            "7RPb74voq1sqY2sKMcnOe/rxwQg=",
+        ))
     }
 
     #[test]
@@ -129,4 +218,77 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn is_expired_with_grace() -> anyhow::Result<()> {
+        const GRACE_MS: i64 = 2_000;
+        let nonce = expected_nonce().context("expected nonce invalid")?;
+
+        let within = nonce.time + Duration::milliseconds(NONCE_MAX_AGE_MS + GRACE_MS / 2);
+        assert!(nonce.is_expired_at(within, 0));
+        assert!(!nonce.is_expired_at(within, GRACE_MS));
+
+        let beyond = nonce.time + Duration::milliseconds(NONCE_MAX_AGE_MS + GRACE_MS + 1);
+        assert!(nonce.is_expired_at(beyond, GRACE_MS));
+
+        Ok(())
+    }
+
+    #[test]
+    fn is_from_future_with_skew() -> anyhow::Result<()> {
+        let nonce = expected_nonce().context("expected nonce invalid")?;
+
+        let second_ahead = nonce.time - Duration::seconds(1);
+        assert!(!nonce.is_from_future_at(second_ahead, DEFAULT_NONCE_MAX_SKEW_MS));
+
+        let hour_ahead = nonce.time - Duration::hours(1);
+        assert!(nonce.is_from_future_at(hour_ahead, DEFAULT_NONCE_MAX_SKEW_MS));
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_str_rejects_invalid_salt() {
+        assert!(Nonce::from_str("2023-09-15T11:23:46Z7RPb74voq1 sqY2sKMcnOe").is_err());
+        assert!(Nonce::from_str("2023-09-15T11:23:46Z7RPb74voq1\u{7}sqY2sKMcnOe").is_err());
+        assert!(Nonce::from_str("2023-09-15T11:23:46Z7RPb74voq1\u{e4}sqY2sKMcnOe").is_err());
+    }
+
+    #[test]
+    fn from_str_rejects_fractional_seconds() {
+        assert!(Nonce::from_str("2023-09-15T11:23:46.123Z7RPb74voq1sqY2sKMcnOe").is_err());
+    }
+
+    #[test]
+    fn from_str_salt_starting_with_z() -> anyhow::Result<()> {
+        let parsed = Nonce::from_str("2023-09-15T11:23:46ZZabc7RPb74voq1sqY2sKMcnOe")
+            .context("deserialization failed")?;
+        let expected = expected_nonce().context("expected nonce invalid")?;
+
+        assert_eq!(parsed.time, expected.time);
+        assert_eq!(parsed.salt, "Zabc7RPb74voq1sqY2sKMcnOe");
+
+        Ok(())
+    }
+
+    #[test]
+    fn non_canonical_time_is_preserved() -> anyhow::Result<()> {
+        const LOWERCASE: &str = "2023-09-15t11:23:46Z7RPb74voq1sqY2sKMcnOe/rxwQg=";
+        let parsed = Nonce::from_str(LOWERCASE).context("deserialization failed")?;
+        let expected = expected_nonce().context("expected nonce invalid")?;
+
+        assert_eq!(parsed.time, expected.time);
+        assert_eq!(parsed.salt, expected.salt);
+        assert_eq!(parsed.to_string(), LOWERCASE);
+        assert_eq!(serde_json::to_string(&parsed)?, format!("{:?}", LOWERCASE));
+        assert_ne!(parsed, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_str_rejects_offset() {
+        assert!(Nonce::from_str("2023-09-15T11:23:46+00:007RPb74voq1sqY2sKMcnOe").is_err());
+        assert!(Nonce::from_str("2023-09-15T11:23Z").is_err());
+    }
 }
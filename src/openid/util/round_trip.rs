@@ -0,0 +1,109 @@
+//! Round trip tests for the formats that can be both serialized and deserialized
+//!
+//! For every such format `from_str(to_string(x)) == x` must hold,
+//! [`KeyValues`] is serialized with [`KeyValues::try_to_string`].
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::comma_separated::CommaSeparated;
+use super::comma_separated_impl;
+use super::key_values::{self, KeyValues};
+use super::nonce::Nonce;
+
+/// Check `from_str(to_string(value)) == value`
+fn assert_round_trip<T>(value: &T) -> Result<(), TestCaseError>
+where
+    T: FromStr + ToString + PartialEq + Debug,
+    T::Err: Debug,
+{
+    let serialized = value.to_string();
+    let deserialized = T::from_str(&serialized).map_err(|err| {
+        TestCaseError::fail(format!("couldn't parse `{}`: {:?}", serialized, err))
+    })?;
+    prop_assert_eq!(&deserialized, value);
+    Ok(())
+}
+
+/// A single empty element serializes to an empty string, which is
+/// parsed as no elements at all, so elements are never empty here.
+fn comma_separated_strings() -> impl Strategy<Value = CommaSeparated<String>> {
+    prop::collection::vec("[^,]+", 0..8).prop_map(CommaSeparated::from)
+}
+
+/// Keys are unique and can't contain the separator, values can't contain a newline
+fn key_values() -> impl Strategy<Value = KeyValues> {
+    prop::collection::btree_map("[^:\n]+", "[^\n]*", 0..8).prop_map(KeyValues::from_iter)
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Query {
+    values: CommaSeparated<u64>,
+}
+
+proptest! {
+    #[test]
+    fn comma_separated_strings_round_trip(value in comma_separated_strings()) {
+        assert_round_trip(&value)?;
+    }
+
+    #[test]
+    fn comma_separated_numbers_round_trip(values in prop::collection::vec(any::<u64>(), 0..8)) {
+        assert_round_trip(&CommaSeparated::from(values))?;
+    }
+
+    #[test]
+    fn comma_separated_query_round_trip(values in prop::collection::vec(any::<u64>(), 0..8)) {
+        let query = Query { values: CommaSeparated::from(values) };
+        let serialized = serde_urlencoded::to_string(&query)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        let deserialized = serde_urlencoded::from_str::<Query>(&serialized)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        prop_assert_eq!(deserialized, query);
+    }
+
+    #[test]
+    fn comma_separated_serde_numbers_round_trip(
+        values in prop::collection::vec(any::<u64>(), 0..8),
+    ) {
+        let serialized = comma_separated_impl::to_string(&values)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        prop_assert_eq!(&serialized, &CommaSeparated::from(values.clone()).to_string());
+        let deserialized: Vec<u64> = comma_separated_impl::from_str(&serialized)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        prop_assert_eq!(deserialized, values);
+    }
+
+    #[test]
+    fn comma_separated_serde_options_round_trip(
+        values in prop::collection::vec(prop::option::of(any::<u64>()), 2..8),
+    ) {
+        // a single `None` would serialize to an empty string, which has no elements
+        let serialized = comma_separated_impl::to_string(&values)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        let deserialized: Vec<Option<u64>> = comma_separated_impl::from_str(&serialized)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        prop_assert_eq!(deserialized, values);
+    }
+
+    #[test]
+    fn key_values_round_trip(value in key_values()) {
+        let serialized = value
+            .try_to_string()
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        let deserialized: KeyValues = key_values::from_str(&serialized)
+            .map_err(|err| TestCaseError::fail(err.to_string()))?;
+        prop_assert_eq!(deserialized, value);
+    }
+
+    #[test]
+    fn nonce_round_trip(secs in 0..i64::from(i32::MAX), salt in "[!-~]{1,64}") {
+        let time = chrono::DateTime::from_timestamp(secs, 0)
+            .ok_or_else(|| TestCaseError::fail("timestamp out of range"))?;
+        let nonce = Nonce::new(time, salt);
+        assert_round_trip(&nonce)?;
+    }
+}
@@ -4,38 +4,35 @@ use anyhow::Context;
 use roxmltree::{Document, Node};
 
 #[derive(Clone)]
-pub(crate) struct Namespace<'a> {
+pub struct Namespace<'a> {
     name: Option<&'a str>,
     uri: &'a str,
 }
 
 impl<'a> Namespace<'a> {
-    pub(crate) const fn new(name: Option<&'a str>, uri: &'a str) -> Namespace<'a> {
+    pub const fn new(name: Option<&'a str>, uri: &'a str) -> Namespace<'a> {
         Namespace { name, uri }
     }
-    pub(crate) fn matches(&self, other: &roxmltree::Namespace) -> bool {
+    pub fn matches(&self, other: &roxmltree::Namespace) -> bool {
         self.name.eq(&other.name()) && self.uri.eq(other.uri())
     }
 }
 
-/// Sort namespaces by their names and then compare them
+/// Check that every required namespace is declared on the root element
 ///
-/// Allocates O(n) memory where n is the maximum number of namespaces
-pub(crate) fn namespaces_eq(doc: &Document, namespaces: &[Namespace]) -> anyhow::Result<()> {
+/// Additional namespaces, e.g. for signatures, are allowed.
+pub fn namespaces_contain(doc: &Document, required: &[Namespace]) -> anyhow::Result<()> {
     let root = doc.root_element();
-    let mut root_namespaces = root.namespaces().collect::<Vec<_>>();
-    root_namespaces.sort_unstable_by(|lhs, rhs| lhs.name().cmp(&rhs.name()));
 
-    let mut namespaces = namespaces.to_vec();
-    namespaces.sort_unstable_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
-
-    if root_namespaces.len() != namespaces.len() {
-        anyhow::bail!("root node doesn't have expected number of namespaces");
-    }
-    if !std::iter::zip(root_namespaces.iter(), namespaces.iter())
-        .all(|(&root_ns, ns)| ns.matches(root_ns))
+    if let Some(missing) = required
+        .iter()
+        .find(|ns| !root.namespaces().any(|root_ns| ns.matches(root_ns)))
     {
-        anyhow::bail!("at least one namespace differs from the expected");
+        anyhow::bail!(
+            "root node doesn't declare namespace `{}` as {}",
+            missing.uri,
+            missing.name.unwrap_or("the default namespace")
+        );
     }
 
     Ok(())
@@ -44,7 +41,7 @@ pub(crate) fn namespaces_eq(doc: &Document, namespaces: &[Namespace]) -> anyhow:
 /// Check that
 /// - the node has at most one child with the given tag name
 /// and return it
-pub(crate) fn get_child_opt<'a, 'input>(
+pub fn get_child_opt<'a, 'input>(
     node: Node<'a, 'input>,
     tag_name: &str,
 ) -> Option<Node<'a, 'input>> {
@@ -56,7 +53,7 @@ pub(crate) fn get_child_opt<'a, 'input>(
 /// - the node has exactly one child and
 /// - the child has the given tag name
 /// and return it
-pub(crate) fn get_only_child<'a, 'input>(
+pub fn get_only_child<'a, 'input>(
     node: Node<'a, 'input>,
     tag_name: &str,
 ) -> anyhow::Result<Node<'a, 'input>> {
@@ -74,7 +71,7 @@ pub(crate) fn get_only_child<'a, 'input>(
 /// Check that
 /// - all children have the given tag name
 /// and return then
-pub(crate) fn get_children_exact<'a, 'input>(
+pub fn get_children_exact<'a, 'input>(
     node: Node<'a, 'input>,
     tag_name: &str,
 ) -> anyhow::Result<Vec<Node<'a, 'input>>> {
@@ -94,7 +91,7 @@ pub(crate) fn get_children_exact<'a, 'input>(
 /// Check that
 /// - the node has exactly one text child
 /// and return that one.
-pub(crate) fn get_only_text_child<'a>(node: Node<'a, '_>) -> anyhow::Result<&'a str> {
+pub fn get_only_text_child<'a>(node: Node<'a, '_>) -> anyhow::Result<&'a str> {
     let mut children = node.children().filter(|c| c.is_text());
     let first = children.next().context("node doesn't have any children")?;
     if children.next().is_some() {
@@ -108,7 +105,7 @@ pub(crate) fn get_only_text_child<'a>(node: Node<'a, '_>) -> anyhow::Result<&'a
 }
 
 /// Check that the node has exactly the children with given tag names, not more and not less.
-pub(crate) fn get_child_set<'a, 'input, 'str>(
+pub fn get_child_set<'a, 'input, 'str>(
     node: Node<'a, 'input>,
     tag_names: &[&'str str],
 ) -> anyhow::Result<HashMap<&'str str, Node<'a, 'input>>> {
@@ -1,62 +1,208 @@
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use steam_api_concurrent::SteamId;
+use thiserror::Error;
 
 use super::key_values;
-use crate::openid::constants::OPENID_MODE_CHECK_AUTHENTICATION;
-use crate::openid::{PositiveAssertion, Provider};
+use crate::openid::constants::OPENID_AUTH_NAMESPACE;
+use crate::openid::nonce::NonceTolerance;
+use crate::openid::{PositiveAssertion, Provider, SteamIdentity};
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.4.2.2>
 #[derive(Debug, Serialize, Deserialize)]
-pub(crate) struct VerifyResponse {
+pub struct VerifyResponse {
     #[serde(rename(deserialize = "ns"))]
     namespace: String,
     is_valid: bool,
+    /// Association handle the OP wants us to forget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    invalidate_handle: Option<String>,
 }
 
 impl VerifyResponse {
+    /// Outcome of a verification that didn't ask the OP,
+    /// e.g. with [`PositiveAssertion::verify_with_association`]
+    pub fn local(is_valid: bool) -> VerifyResponse {
+        VerifyResponse {
+            namespace: OPENID_AUTH_NAMESPACE.to_string(),
+            is_valid,
+            invalidate_handle: None,
+        }
+    }
     pub const fn is_valid(&self) -> bool {
         self.is_valid
     }
+    pub fn invalidate_handle(&self) -> Option<&str> {
+        self.invalidate_handle.as_deref()
+    }
+}
+
+/// How much of an unexpected response body ends up in [`UnexpectedProviderResponse`]
+pub(crate) const BODY_SNIPPET_LEN: usize = 256;
+
+/// The OP answered the verification request with something other than key-values,
+/// e.g. an html error page
+#[derive(Debug, Error)]
+#[error(
+    "unexpected provider response: status {status}, content type {content_type:?}, body starts with {snippet:?}"
+)]
+pub struct UnexpectedProviderResponse {
+    pub status: reqwest::StatusCode,
+    pub content_type: Option<String>,
+    /// The first [`BODY_SNIPPET_LEN`] bytes of the body, cut at a char boundary
+    pub snippet: String,
+}
+
+/// Cut `body` to at most `max_len` bytes without splitting a char
+pub(crate) fn body_snippet(body: &str, max_len: usize) -> &str {
+    if body.len() <= max_len {
+        return body;
+    }
+    let end = (0..=max_len)
+        .rev()
+        .find(|&index| body.is_char_boundary(index))
+        .unwrap_or(0);
+    &body[..end]
+}
+
+/// Media type of a `Content-Type` header value without parameters like `charset`
+pub(crate) fn media_type(content_type: &str) -> &str {
+    content_type
+        .split_once(';')
+        .map_or(content_type, |(media_type, _)| media_type)
+        .trim()
+}
+
+/// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.5.1.2>
+///
+/// Responses in Key-Value Form are `text/plain`, the charset
+/// is handled by [`reqwest::Response::text`].
+pub(crate) fn is_key_value_content_type(content_type: &str) -> bool {
+    media_type(content_type).eq_ignore_ascii_case("text/plain")
 }
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.4.2>
-pub(crate) async fn verify_against_provider(
+pub async fn verify_against_provider(
     client: &reqwest::Client,
     provider: &Provider,
     assertion: &PositiveAssertion,
 ) -> anyhow::Result<VerifyResponse> {
-    let url = provider.service.endpoint.as_str();
+    let url = provider.endpoint();
 
     // https://github.com/havard/node-openid/blob/672ea6e1b25e96c4a8e4f9deb74d38487c85ac32/openid.js#L1250-L1253
-    // https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.4.2.1
-    let mut assertion = assertion.clone();
-    assertion.set_mode(OPENID_MODE_CHECK_AUTHENTICATION);
-
     let req = client
         .post(url)
-        .form(&assertion)
+        .form(&assertion.to_check_auth_form())
         .send()
         .await
         .context("couldn't send request to validate assertion")?;
 
+    let status = req.status();
+    let content_type = req
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
     let text = req
         .text()
         .await
         .context("provider returned an invalid response")?;
 
-    let verification: VerifyResponse = key_values::from_str(&text)
+    let expected_content_type = content_type
+        .as_deref()
+        .is_none_or(is_key_value_content_type);
+    if !status.is_success() || !expected_content_type {
+        return Err(UnexpectedProviderResponse {
+            status,
+            content_type,
+            snippet: body_snippet(&text, BODY_SNIPPET_LEN).to_string(),
+        }
+        .into());
+    }
+
+    let verification: VerifyResponse = key_values::from_str_strict(&text)
         .context("couldn't parse response from provider as key-values")?;
 
+    // evicting it is left to whoever keeps the associations
+    // https://openid.net/specs/openid-authentication-2_0.html#rfc.section.11.4.2.2
+    if let Some(handle) = verification.invalidate_handle() {
+        log::info!("provider `{}` invalidated association `{}`", url, handle);
+    }
+
     Ok(verification)
 }
 
+/// Check the raw query string of a steam callback and return who logged in
+///
+/// Parses the [`PositiveAssertion`], validates it with the default [`NonceTolerance`]
+/// and lets `provider` confirm it with [`verify_against_provider`].
+/// The `return_to` and replay checks are left to the caller.
+pub async fn verify_steam_query(
+    client: &reqwest::Client,
+    provider: &Provider,
+    query: &str,
+) -> anyhow::Result<SteamId> {
+    let assertion: PositiveAssertion =
+        serde_urlencoded::from_str(query).context("couldn't parse positive assertion")?;
+    assertion
+        .validate(provider)
+        .context("invalid positive assertion (generic)")?;
+    assertion
+        .validate_steam(NonceTolerance::default())
+        .context("invalid positive assertion (steam)")?;
+
+    let verification = verify_against_provider(client, provider, &assertion)
+        .await
+        .context("couldn't verify assertion against provider")?;
+    if !verification.is_valid() {
+        anyhow::bail!("provider rejected the positive assertion");
+    }
+
+    let claimed_id = assertion
+        .claimed_id()
+        .context("assertion is missing a claimed id")?;
+    Ok(SteamIdentity::from_claimed_id(claimed_id)?.steam_id())
+}
+
 #[cfg(test)]
 mod test {
 
     use anyhow::Context;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::openid::Service;
 
-    use crate::openid::constants::OPENID_AUTH_NAMESPACE;
-    use crate::openid::{key_values, VerifyResponse};
+    const ASSERTION_QUERY: &str = "openid.ns=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0&openid.mode=id_res&openid.op_endpoint=https%3A%2F%2Fsteamcommunity.com%2Fopenid%2Flogin&openid.claimed_id=https%3A%2F%2Fsteamcommunity.com%2Fopenid%2Fid%2F76561198181282063&openid.identity=https%3A%2F%2Fsteamcommunity.com%2Fopenid%2Fid%2F76561198181282063&openid.return_to=http%3A%2F%2Flocalhost%3A3000%2Fauth%2Fsteam%2Fcallback%2F&openid.response_nonce=2023-09-15T11%3A23%3A46Z7RPb74voq1sqY2sKMcnOe%2FrxwQg%3D&openid.assoc_handle=1234567890&openid.signed=signed%2Cop_endpoint%2Cclaimed_id%2Cidentity%2Creturn_to%2Cresponse_nonce%2Cassoc_handle&openid.sig=SPaIMgwuYCQ2zVlgYmbSAKfD8Ps%3D";
+
+    async fn verify_against(template: ResponseTemplate) -> anyhow::Result<VerifyResponse> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(template)
+            .mount(&server)
+            .await;
+
+        let provider = Provider {
+            service: Service {
+                endpoint: server.uri(),
+                ..Service::default()
+            },
+        };
+        let assertion: PositiveAssertion = serde_urlencoded::from_str(ASSERTION_QUERY)?;
+        verify_against_provider(&reqwest::Client::new(), &provider, &assertion).await
+    }
+
+    #[test]
+    fn content_type_with_charset() {
+        assert!(is_key_value_content_type("text/plain"));
+        assert!(is_key_value_content_type("text/plain; charset=utf-8"));
+        assert!(is_key_value_content_type("text/plain;charset=UTF-8"));
+        assert!(is_key_value_content_type("Text/Plain ; charset=utf-8"));
+        assert!(!is_key_value_content_type("text/html; charset=utf-8"));
+        assert!(!is_key_value_content_type("text/plainish"));
+    }
 
     #[test]
     fn key_value_deserialize() -> anyhow::Result<()> {
@@ -69,4 +215,86 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn key_value_deserialize_invalidate_handle() -> anyhow::Result<()> {
+        const TEXT: &str =
+            "ns:http://specs.openid.net/auth/2.0\nis_valid:false\ninvalidate_handle:1234567890\n";
+
+        let parsed: VerifyResponse = key_values::from_str(TEXT).context("invalid key values")?;
+
+        assert!(!parsed.is_valid());
+        assert_eq!(parsed.invalidate_handle(), Some("1234567890"));
+
+        let parsed: VerifyResponse =
+            key_values::from_str("ns:http://specs.openid.net/auth/2.0\nis_valid:true\n")
+                .context("invalid key values")?;
+        assert_eq!(parsed.invalidate_handle(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_value_deserialize_capitalized_bool() -> anyhow::Result<()> {
+        const TEXT: &str = "ns:http://specs.openid.net/auth/2.0\nis_valid:True\n";
+
+        let parsed: VerifyResponse = key_values::from_str(TEXT).context("invalid key values")?;
+
+        assert!(parsed.is_valid());
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_values_deserialize_into() -> anyhow::Result<()> {
+        let fields = key_values::KeyValues::from_iter([
+            ("ns", OPENID_AUTH_NAMESPACE),
+            ("is_valid", "True"),
+            ("invalidate_handle", "1234567890"),
+        ]);
+
+        let parsed: VerifyResponse = fields.deserialize_into()?;
+        assert!(parsed.is_valid());
+        assert_eq!(parsed.namespace, OPENID_AUTH_NAMESPACE);
+        assert_eq!(parsed.invalidate_handle(), Some("1234567890"));
+
+        let missing = key_values::KeyValues::from_iter([("ns", OPENID_AUTH_NAMESPACE)]);
+        assert!(missing.deserialize_into::<VerifyResponse>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_works() -> anyhow::Result<()> {
+        let body = "ns:http://specs.openid.net/auth/2.0\nis_valid:true\n";
+        let template = ResponseTemplate::new(200).set_body_raw(body, "text/plain");
+        assert!(verify_against(template).await?.is_valid());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn html_response_is_unexpected() -> anyhow::Result<()> {
+        let body = format!("<!DOCTYPE html><html>{}</html>", "a".repeat(1024));
+        let template = ResponseTemplate::new(502).set_body_raw(body, "text/html");
+
+        let err = verify_against(template)
+            .await
+            .expect_err("html must be rejected");
+        let err = err
+            .downcast_ref::<UnexpectedProviderResponse>()
+            .context("not an unexpected provider response")?;
+        assert_eq!(err.status, reqwest::StatusCode::BAD_GATEWAY);
+        assert_eq!(err.content_type.as_deref(), Some("text/html"));
+        assert!(err.snippet.starts_with("<!DOCTYPE html><html>aaa"));
+        assert_eq!(err.snippet.len(), BODY_SNIPPET_LEN);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snippet_respects_char_boundaries() {
+        assert_eq!(body_snippet("short", 16), "short");
+        assert_eq!(body_snippet("🦀🦀", 6), "🦀");
+        assert_eq!(body_snippet("🦀", 2), "");
+    }
 }
@@ -1,11 +1,16 @@
-pub(crate) enum OpenIdUrl {
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+pub enum OpenIdUrl {
     IdentifierSelect,
     ReturnTo,
     Server,
     SignOn,
 }
 impl OpenIdUrl {
-    pub(crate) const fn url(&self) -> &'static str {
+    pub const fn url(&self) -> &'static str {
         match self {
             OpenIdUrl::IdentifierSelect => "http://specs.openid.net/auth/2.0/identifier_select",
             OpenIdUrl::ReturnTo => "http://specs.openid.net/auth/2.0/return_to",
@@ -15,7 +20,9 @@ impl OpenIdUrl {
     }
 }
 
-pub(crate) enum OpenIdMode {
+/// Value of [`crate::openid::constants::OPENID_MODE`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenIdMode {
     Error,
     Associate,
     CheckIdImmediate,
@@ -26,7 +33,18 @@ pub(crate) enum OpenIdMode {
     CheckAuthentication,
 }
 impl OpenIdMode {
-    pub(crate) const fn value(&self) -> &'static str {
+    pub const ALL: [OpenIdMode; 8] = [
+        OpenIdMode::Error,
+        OpenIdMode::Associate,
+        OpenIdMode::CheckIdImmediate,
+        OpenIdMode::CheckIdSetup,
+        OpenIdMode::IdentityResolution,
+        OpenIdMode::SetupNeeded,
+        OpenIdMode::Cancel,
+        OpenIdMode::CheckAuthentication,
+    ];
+
+    pub const fn as_str(&self) -> &'static str {
         match self {
             OpenIdMode::Error => "error",
             OpenIdMode::Associate => "associate",
@@ -39,3 +57,34 @@ impl OpenIdMode {
         }
     }
 }
+
+impl FromStr for OpenIdMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OpenIdMode::ALL
+            .into_iter()
+            .find(|mode| mode.as_str() == s)
+            .with_context(|| format!("unknown openid mode {:?}", s))
+    }
+}
+
+impl fmt::Display for OpenIdMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mode_round_trip() -> anyhow::Result<()> {
+        for mode in OpenIdMode::ALL {
+            assert_eq!(mode.as_str().parse::<OpenIdMode>()?, mode);
+        }
+        assert!("ID_RES".parse::<OpenIdMode>().is_err());
+        assert!("".parse::<OpenIdMode>().is_err());
+        Ok(())
+    }
+}
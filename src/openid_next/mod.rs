@@ -2,3 +2,6 @@
 
 mod enums;
 mod structs;
+
+pub use enums::*;
+pub use structs::*;
@@ -1,68 +1,85 @@
+use serde::{Deserialize, Serialize};
+
 /// All possible keys
-pub(crate) struct OpenIdBase {
-    pub(crate) assoc_handle: Option<String>,
-    pub(crate) assoc_type: Option<String>,
-    pub(crate) claimed_id: Option<String>,
-    pub(crate) contact: Option<String>,
-    pub(crate) delegate: Option<String>,
-    pub(crate) dh_consumer_public: Option<String>,
-    pub(crate) dh_gen: Option<String>,
-    pub(crate) dh_modulus: Option<String>,
-    pub(crate) error: Option<String>,
-    pub(crate) identity: Option<String>,
-    pub(crate) invalidate_handle: Option<String>,
-    pub(crate) mode: Option<String>,
-    pub(crate) ns: Option<String>,
-    pub(crate) op_endpoint: Option<String>,
-    pub(crate) openid: Option<String>,
-    pub(crate) realm: Option<String>,
-    pub(crate) reference: Option<String>,
-    pub(crate) response_nonce: Option<String>,
-    pub(crate) return_to: Option<String>,
-    pub(crate) server: Option<String>,
-    pub(crate) session_type: Option<String>,
-    pub(crate) sig: Option<String>,
-    pub(crate) signed: Option<String>,
-    pub(crate) trust_root: Option<String>,
+pub struct OpenIdBase {
+    pub assoc_handle: Option<String>,
+    pub assoc_type: Option<String>,
+    pub claimed_id: Option<String>,
+    pub contact: Option<String>,
+    pub delegate: Option<String>,
+    pub dh_consumer_public: Option<String>,
+    pub dh_gen: Option<String>,
+    pub dh_modulus: Option<String>,
+    pub error: Option<String>,
+    pub identity: Option<String>,
+    pub invalidate_handle: Option<String>,
+    pub mode: Option<String>,
+    pub ns: Option<String>,
+    pub op_endpoint: Option<String>,
+    pub openid: Option<String>,
+    pub realm: Option<String>,
+    pub reference: Option<String>,
+    pub response_nonce: Option<String>,
+    pub return_to: Option<String>,
+    pub server: Option<String>,
+    pub session_type: Option<String>,
+    pub sig: Option<String>,
+    pub signed: Option<String>,
+    pub trust_root: Option<String>,
 }
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.5.2.3>
-pub(crate) struct IndirectErrorResponse {
-    pub(crate) ns: String,
-    pub(crate) mode: String,
-    pub(crate) error: String,
-    pub(crate) contact: Option<String>,
-    pub(crate) reference: Option<String>,
+pub struct IndirectErrorResponse {
+    pub ns: String,
+    pub mode: String,
+    pub error: String,
+    pub contact: Option<String>,
+    pub reference: Option<String>,
 }
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.9.1>
-pub(crate) struct AuthenticationRequest {
-    pub(crate) ns: String,
-    pub(crate) mode: String,
-    pub(crate) claimed_id: Option<String>,
-    pub(crate) identity: Option<String>,
-    pub(crate) assoc_handle: Option<String>,
-    pub(crate) return_to: Option<String>,
-    pub(crate) realm: Option<String>,
+pub struct AuthenticationRequest {
+    pub ns: String,
+    pub mode: String,
+    pub claimed_id: Option<String>,
+    pub identity: Option<String>,
+    pub assoc_handle: Option<String>,
+    pub return_to: Option<String>,
+    pub realm: Option<String>,
 }
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.1>
-pub(crate) struct PositiveAssertion {
-    pub(crate) ns: String,
-    pub(crate) mode: String,
-    pub(crate) op_endpoint: String,
-    pub(crate) claimed_id: Option<String>,
-    pub(crate) identity: Option<String>,
-    pub(crate) return_to: String,
-    pub(crate) response_nonce: String,
-    pub(crate) invalidate_handle: Option<String>,
-    pub(crate) assoc_handle: String,
-    pub(crate) signed: String,
-    pub(crate) sig: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PositiveAssertion {
+    #[serde(rename = "openid.ns")]
+    pub ns: String,
+    #[serde(rename = "openid.mode")]
+    pub mode: String,
+    #[serde(rename = "openid.op_endpoint")]
+    pub op_endpoint: String,
+    #[serde(rename = "openid.claimed_id")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_id: Option<String>,
+    #[serde(rename = "openid.identity")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    #[serde(rename = "openid.return_to")]
+    pub return_to: String,
+    #[serde(rename = "openid.response_nonce")]
+    pub response_nonce: String,
+    #[serde(rename = "openid.invalidate_handle")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invalidate_handle: Option<String>,
+    #[serde(rename = "openid.assoc_handle")]
+    pub assoc_handle: String,
+    #[serde(rename = "openid.signed")]
+    pub signed: String,
+    #[serde(rename = "openid.sig")]
+    pub sig: String,
 }
 
 /// <https://openid.net/specs/openid-authentication-2_0.html#rfc.section.10.2.2>
-pub(crate) struct NegativeAssertion {
-    pub(crate) ns: String,
-    pub(crate) mode: String,
+pub struct NegativeAssertion {
+    pub ns: String,
+    pub mode: String,
 }
@@ -0,0 +1,491 @@
+//! The actix server around the app state: sessions, middlewares, background tasks
+//! and a graceful shutdown
+
+use std::future::Future;
+use std::time::Duration;
+
+use actix_session::config::CookieContentSecurity;
+#[cfg(test)]
+use actix_session::storage::CookieSessionStore;
+use actix_session::storage::{RedisActorSessionStore, SessionStore};
+use actix_session::SessionMiddleware;
+use actix_web::body::MessageBody;
+use actix_web::cookie::Key;
+use actix_web::dev::{Server, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{middleware, web, App, HttpServer};
+use anyhow::Context;
+use chrono::Utc;
+
+use crate::api;
+use crate::config::{
+    create_client, load_cookie_key, ClientConfig, DiscoveryRetry, SessionCookieConfig,
+};
+use crate::error::{self, error_handler};
+use crate::openid::{associate, ClaimedId};
+use crate::state::{discover_steam, OpenIdState, State, STEAM_NONCE_NAMESPACE, STEAM_OPENID_LOGIN};
+pub use crate::util::log::init_logger;
+use crate::util::nonce::NonceSet;
+use crate::util::rate_limit::{trusted_proxies_from_env, RateLimit, RateLimiter};
+
+pub(crate) const SOCKET: &str = "0.0.0.0:8080";
+
+/// How long in-flight requests may take to finish after a shutdown signal
+pub(crate) const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+/// How often expired nonces are removed from [`NonceSet`]
+pub(crate) const NONCE_REAPER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the association with steam is checked, see [`spawn_associator`]
+pub(crate) const ASSOCIATION_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often steam is rediscovered, see [`spawn_provider_refresher`]
+pub(crate) const PROVIDER_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn create_session_mw<S: SessionStore>(
+    store: S,
+    key: Key,
+    config: &SessionCookieConfig,
+) -> SessionMiddleware<S> {
+    SessionMiddleware::builder(store, key)
+        .cookie_http_only(config.http_only)
+        .cookie_same_site(config.same_site)
+        .cookie_name(config.name.clone())
+        .cookie_domain(config.domain.clone())
+        .cookie_content_security(CookieContentSecurity::Private)
+        .build()
+}
+
+pub(crate) fn create_redis_session_mw(
+    url: &str,
+    key: Key,
+    config: &SessionCookieConfig,
+) -> SessionMiddleware<RedisActorSessionStore> {
+    create_session_mw(RedisActorSessionStore::new(url), key, config)
+}
+
+/// Sessions kept in the cookie itself, so the tests don't need redis
+#[cfg(test)]
+pub(crate) fn create_cookie_session_mw(key: Key) -> SessionMiddleware<CookieSessionStore> {
+    let config = SessionCookieConfig {
+        name: "session-data".to_string(),
+        ..SessionCookieConfig::default()
+    };
+    create_session_mw(CookieSessionStore::default(), key, &config)
+}
+
+pub(crate) fn create_logger_mw() -> middleware::Logger {
+    middleware::Logger::new(r#"%Ts %bB %{r}a [%r -> %s] "%{Referer}i" "%{User-Agent}i""#)
+}
+
+/// The whole app with every middleware, sessions are kept in `session_mw`
+pub(crate) fn create_app<S: SessionStore + 'static>(
+    data: web::Data<State>,
+    rate_limiter: web::Data<RateLimiter>,
+    session_mw: SessionMiddleware<S>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(data)
+        .app_data(rate_limiter)
+        .wrap(create_logger_mw())
+        .wrap(error_handler())
+        .wrap(session_mw)
+        .service(web::scope("/api").configure(api::configure))
+}
+
+/// Periodically remove expired nonces so abandoned logins don't pile up
+pub(crate) fn spawn_nonce_reaper(data: web::Data<State>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(NONCE_REAPER_INTERVAL);
+        loop {
+            interval.tick().await;
+            data.steam.nonces.remove_expired_nonces();
+            data.steam.replays.remove_expired();
+            data.generic.nonces.remove_expired_nonces();
+            data.generic.pending.retain_valid(&data.generic.nonces);
+            data.generic.replays.remove_expired();
+            data.profiles.remove_expired();
+        }
+    })
+}
+
+/// Periodically rediscover steam, the OP is asked with a conditional request
+/// and the provider in use is kept if it can't be reached
+pub(crate) fn spawn_provider_refresher(data: web::Data<State>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PROVIDER_REFRESH_INTERVAL);
+        // the first tick completes right away, steam has just been discovered
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(err) = data.steam.providers.refresh(&data.client).await {
+                log::warn!(
+                    "couldn't rediscover steam openid service, keeping the provider in use: {:#}",
+                    anyhow::Error::new(err)
+                );
+            }
+        }
+    })
+}
+
+/// Associate with steam unless the current association outlives the next check,
+/// logins are verified by steam in the meantime if that fails
+pub(crate) async fn renew_association(data: &State) {
+    let associations = &data.steam.associations;
+    associations.remove_expired();
+    let renew_at = Utc::now()
+        + chrono::Duration::from_std(ASSOCIATION_CHECK_INTERVAL * 2)
+            .unwrap_or_else(|_| chrono::Duration::zero());
+    if associations
+        .current()
+        .is_some_and(|association| association.expires_at > renew_at)
+    {
+        return;
+    }
+    match associate(&data.client, &data.steam.provider()).await {
+        Ok(association) => {
+            log::info!(
+                "associated with steam as `{}` until {}",
+                association.handle,
+                association.expires_at
+            );
+            associations.insert(association);
+        }
+        Err(err) => log::warn!("couldn't associate with steam: {:#}", err),
+    }
+}
+
+/// Keep an association with steam, so assertions don't need a `check_authentication` request
+pub(crate) fn spawn_associator(data: web::Data<State>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ASSOCIATION_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            renew_association(&data).await;
+        }
+    })
+}
+
+/// Resolves on ctrl-c or, on unix, on SIGTERM
+pub(crate) async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(err) => log::warn!("couldn't listen for SIGTERM: {}", err),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        log::error!("couldn't listen for ctrl-c: {}", err);
+    }
+}
+
+/// Run the server until `signal` resolves, then stop accepting
+/// new connections and let in-flight requests finish.
+pub(crate) async fn serve_until(
+    server: Server,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let handle = server.handle();
+    tokio::spawn(async move {
+        signal.await;
+        log::info!(
+            "shutting down, draining in-flight requests for up to {}s",
+            SHUTDOWN_TIMEOUT_SECS
+        );
+        handle.stop(true).await;
+    });
+    server.await
+}
+
+/// Run the server until ctrl-c or SIGTERM, everything is configured
+/// through the environment
+pub async fn serve() -> anyhow::Result<()> {
+    error::max_error_chain_depth_from_env().context("couldn't load error chain depth")?;
+    let cookie_key = load_cookie_key().context("couldn't load cookie key")?;
+    let cookie_config =
+        SessionCookieConfig::from_env().context("couldn't load session cookie config")?;
+    let state = State::new().await.context("couldn't create app state")?;
+    let redis_url = state.redis_url.clone();
+    let data = web::Data::new(state);
+    let rate_limiter = web::Data::new(
+        RateLimiter::new(RateLimit::from_env().context("couldn't load auth rate limit")?)
+            .with_trusted_proxies(trusted_proxies_from_env()?),
+    );
+    log::info!("created app state");
+
+    let reaper = spawn_nonce_reaper(web::Data::clone(&data));
+    let refresher = spawn_provider_refresher(web::Data::clone(&data));
+    let associator = spawn_associator(web::Data::clone(&data));
+    let server_data = web::Data::clone(&data);
+
+    let mut server = HttpServer::new(move || {
+        create_app(
+            web::Data::clone(&server_data),
+            web::Data::clone(&rate_limiter),
+            create_redis_session_mw(&redis_url, cookie_key.clone(), &cookie_config),
+        )
+    });
+
+    server = server
+        .bind(SOCKET)
+        .with_context(|| format!("couldn't bind to socket `{}`", SOCKET))?;
+
+    log::info!("server is listening on {}", SOCKET);
+
+    log::info!("here is a list of endpoints:");
+    for (endpoint, description) in [
+        ("/api/auth/steam/login", "initiate login to steam"),
+        ("/api/auth/steam/callback", "verify assertion from steam"),
+        ("/api/auth/steam/logout", "logout from steam"),
+        ("/api/auth/steam/status", "view login state"),
+        (
+            "/api/auth/steam/refresh-nonce",
+            "replace the nonce of a pending login",
+        ),
+        (
+            "/api/auth/generic/login",
+            "initiate login to any openid provider",
+        ),
+        (
+            "/api/auth/generic/callback",
+            "verify assertion from any openid provider",
+        ),
+        ("/api/health/live", "health check"),
+        ("/api/health/ready", "health check"),
+        ("/api/health/caches", "view cache stats"),
+        ("/api/health/metrics", "prometheus metrics"),
+        #[cfg(feature = "debug-endpoints")]
+        ("/api/health/error", "error example"),
+        #[cfg(feature = "debug-endpoints")]
+        ("/api/health/cookies", "view cookies decrypted"),
+        #[cfg(feature = "debug-endpoints")]
+        ("/api/health/provider", "view the steam provider in use"),
+    ] {
+        log::info!("- http://{}{}: {}", SOCKET, endpoint, description);
+    }
+
+    let server = server
+        .workers(1)
+        .shutdown_timeout(SHUTDOWN_TIMEOUT_SECS)
+        .disable_signals()
+        .run();
+    serve_until(server, shutdown_signal())
+        .await
+        .context("error while running server")?;
+
+    reaper.abort();
+    refresher.abort();
+    associator.abort();
+    data.steam.nonces.remove_expired_nonces();
+    log::info!("server stopped");
+
+    Ok(())
+}
+
+/// Discover steam and print the auth url for a sample nonce, without starting the server
+pub async fn print_auth_url() -> anyhow::Result<()> {
+    let client_config = ClientConfig::from_env().context("couldn't load http client config")?;
+    let client = create_client(&client_config)?;
+    let providers = discover_steam(&client, STEAM_OPENID_LOGIN, DiscoveryRetry::from_env()?).await;
+    let provider = providers
+        .provider()
+        .context("steam provider wasn't discovered")?;
+    let open_id = OpenIdState::new().context("couldn't load openid config")?;
+    let nonce = NonceSet::new(STEAM_NONCE_NAMESPACE).insert_new();
+
+    let url = open_id
+        .auth_url_with_nonce(&provider, &ClaimedId::Select, nonce.as_str())
+        .context("couldn't create auth url with nonce")?;
+    println!("{}", url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::cookie::SameSite;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, TestRequest};
+
+    use super::*;
+    use crate::config::DEFAULT_REDIRECT_STATUS;
+    use crate::openid::Provider;
+    use crate::state::Dependencies;
+    use crate::util::mock_op::MockOp;
+    use crate::util::test_app::test_app;
+
+    #[tokio::test]
+    async fn shutdown_drains_in_flight_requests() -> anyhow::Result<()> {
+        type Started = tokio::sync::mpsc::UnboundedSender<()>;
+        async fn slow(started: web::Data<Started>) -> &'static str {
+            let _ = started.send(());
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "done"
+        }
+
+        let (started, mut handler_started) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let started = web::Data::new(started);
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::clone(&started))
+                .route("/slow", web::get().to(slow))
+        })
+        .workers(1)
+        .shutdown_timeout(5)
+        .disable_signals()
+        .bind("127.0.0.1:0")?;
+        let addr = server.addrs()[0];
+        let server = server.run();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(serve_until(server, async {
+            let _ = stopped.await;
+        }));
+
+        let request = tokio::spawn(reqwest::get(format!("http://{}/slow", addr)));
+        handler_started
+            .recv()
+            .await
+            .context("handler never started")?;
+        let _ = stop.send(());
+
+        let response = request.await??;
+        assert!(response.status().is_success());
+        assert_eq!(response.text().await?, "done");
+        serving.await??;
+
+        Ok(())
+    }
+
+    /// Handler that puts something into the session so the cookie is set
+    async fn touch_session(session: actix_session::Session) -> actix_web::HttpResponse {
+        session.insert("touched", true).unwrap();
+        actix_web::HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn session_cookie_from_config() -> anyhow::Result<()> {
+        use actix_web::test::{call_service, init_service, TestRequest};
+
+        let config = SessionCookieConfig {
+            name: "complainer-session".to_string(),
+            domain: Some("example.com".to_string()),
+            same_site: SameSite::Strict,
+            http_only: true,
+        };
+        let app = init_service(
+            App::new()
+                .wrap(create_session_mw(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                    &config,
+                ))
+                .route("/", web::get().to(touch_session)),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let cookie = res
+            .response()
+            .cookies()
+            .next()
+            .context("session cookie wasn't set")?;
+        assert_eq!(cookie.name(), "complainer-session");
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.http_only(), Some(true));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn session_cookie_is_http_only_by_default() -> anyhow::Result<()> {
+        use actix_web::test::{call_service, init_service, TestRequest};
+
+        let app = init_service(
+            App::new()
+                .wrap(create_cookie_session_mw(Key::generate()))
+                .route("/", web::get().to(touch_session)),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let set_cookie = res
+            .headers()
+            .get(actix_web::http::header::SET_COOKIE)
+            .context("session cookie wasn't set")?
+            .to_str()?;
+        assert!(set_cookie.contains("HttpOnly"), "{}", set_cookie);
+
+        Ok(())
+    }
+
+    /// The app as [`serve`] builds it, around a mock OP
+    macro_rules! full_app {
+        ($op:expr) => {{
+            let provider = Provider::from_url(&reqwest::Client::new(), &$op.identifier()).await?;
+            let deps = Dependencies::for_test(provider);
+            test_app!(web::Data::new(State::for_test_with(deps)?))
+        }};
+    }
+
+    #[actix_web::test]
+    async fn app_is_live() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let app = full_app!(op);
+
+        let req = TestRequest::get().uri("/api/health/live").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn app_redirects_login_to_op() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let app = full_app!(op);
+
+        let req = TestRequest::get().uri("/api/auth/steam/login").to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), DEFAULT_REDIRECT_STATUS);
+        let location = res
+            .headers()
+            .get(actix_web::http::header::LOCATION)
+            .context("redirect without location")?
+            .to_str()?;
+        assert!(location.starts_with(&op.endpoint()), "{}", location);
+        assert!(res.response().cookies().next().is_some());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn app_requires_login_for_player_summaries() -> anyhow::Result<()> {
+        let op = MockOp::start().await;
+        let app = full_app!(op);
+
+        let req = TestRequest::get()
+            .uri("/api/steam/player-summaries?steam_ids=76561198181282063")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,600 @@
+//! Everything the handlers share: the OpenID settings, the steam provider and the caches
+//! of pending logins, built from the environment at startup
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use steam_api_concurrent::SteamId;
+
+use crate::config::{
+    create_client, parse_redirect_status, CallbackResponseMode, ClientConfig, DiscoveryRetry,
+    DEFAULT_REDIRECT_STATUS,
+};
+use crate::openid::comma_separated::CommaSeparated;
+use crate::openid::nonce::{NonceTolerance, DEFAULT_NONCE_MAX_SKEW_MS};
+use crate::openid::{
+    make_associated_auth_req_url, ClaimedId, Provider, ProviderCache, Realm, ReturnTo,
+};
+use crate::util::associations::Associations;
+use crate::util::metrics::Metrics;
+use crate::util::nonce::{NonceSet, DEFAULT_NONCE_GRACE_MS};
+use crate::util::pending_login::PendingLogins;
+use crate::util::profile_cache::{ProfileCache, DEFAULT_PROFILE_CACHE_TTL};
+use crate::util::replay_cache::ReplayCache;
+use crate::util::steam_api::SteamApi;
+use crate::util::timing::timed;
+
+pub(crate) const STEAM_OPENID_LOGIN: &str = "https://steamcommunity.com/openid";
+
+/// See [`NonceSet`]
+pub(crate) const STEAM_NONCE_NAMESPACE: &str = "steam";
+
+/// See [`NonceSet`]
+pub(crate) const GENERIC_NONCE_NAMESPACE: &str = "generic";
+
+/// Default for `OPENID_GENERIC_RETURN_TO`, relative to the realm like `OPENID_RETURN_TO`
+pub(crate) const DEFAULT_GENERIC_RETURN_TO: &str = "/api/auth/generic/callback";
+
+/// Realm and `return_to` are validated once when the state is built, a misconfigured
+/// deployment fails at startup instead of on the first login
+pub(crate) struct OpenIdState {
+    pub(crate) realm: Realm,
+    pub(crate) return_to: ReturnTo,
+    pub(crate) success_redirect: String,
+    pub(crate) logout_redirect: String,
+}
+impl OpenIdState {
+    pub(crate) fn new() -> anyhow::Result<OpenIdState> {
+        Self::with_return_to(&dotenv::var("OPENID_RETURN_TO")?)
+    }
+    /// Same as [`OpenIdState::new`] with `return_to` instead of `OPENID_RETURN_TO`
+    pub(crate) fn with_return_to(return_to: &str) -> anyhow::Result<OpenIdState> {
+        Self::from_values(
+            &dotenv::var("OPENID_REALM")?,
+            return_to,
+            dotenv::var("OPENID_SUCCESS_REDIRECT")?,
+            dotenv::var("OPENID_LOGOUT_REDIRECT")?,
+        )
+    }
+    /// `return_to` is the path below `realm` the OP sends the user back to
+    pub(crate) fn from_values(
+        realm: &str,
+        return_to: &str,
+        success_redirect: String,
+        logout_redirect: String,
+    ) -> anyhow::Result<OpenIdState> {
+        let return_to = format!("{}{}", realm, return_to);
+        let realm = Realm::parse(realm).context("invalid OPENID_REALM")?;
+        let return_to = ReturnTo::parse(&realm, &return_to).context("invalid return_to")?;
+        Ok(OpenIdState {
+            realm,
+            return_to,
+            success_redirect,
+            logout_redirect,
+        })
+    }
+    /// Auth request for `provider` that returns to `return_to` with the nonce appended
+    pub(crate) fn auth_url_with_nonce(
+        &self,
+        provider: &Provider,
+        claimed_id: &ClaimedId,
+        nonce: &str,
+    ) -> anyhow::Result<String> {
+        self.auth_url_with_params(provider, claimed_id, &[("custom_nonce", nonce)], None)
+    }
+    /// Auth request for `claimed_id` at `provider` that returns to `return_to`
+    /// with `params` appended, signed with `assoc_handle` if there is one
+    fn auth_url_with_params(
+        &self,
+        provider: &Provider,
+        claimed_id: &ClaimedId,
+        params: &[(&str, &str)],
+        assoc_handle: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let return_to = self.return_to.with_params(params);
+        let auth_url = make_associated_auth_req_url(
+            provider,
+            claimed_id,
+            &self.realm,
+            &return_to,
+            assoc_handle,
+        )
+        .context("couldn't create auth request url with custom nonce")?;
+        Ok(auth_url)
+    }
+}
+
+/// Optional list of steam ids that may log in, e.g. for a closed beta.
+///
+/// Configured through `ALLOWED_STEAM_IDS` as a comma separated list,
+/// everyone may log in if it is unset or empty.
+pub(crate) struct SteamIdAllowlist {
+    ids: Option<CommaSeparated<SteamId>>,
+}
+impl SteamIdAllowlist {
+    pub(crate) fn new() -> anyhow::Result<SteamIdAllowlist> {
+        SteamIdAllowlist::from_value(dotenv::var("ALLOWED_STEAM_IDS").ok().as_deref())
+    }
+    pub(crate) fn from_value(value: Option<&str>) -> anyhow::Result<SteamIdAllowlist> {
+        let ids = value
+            .filter(|value| !value.is_empty())
+            .map(str::parse)
+            .transpose()
+            .context("couldn't parse ALLOWED_STEAM_IDS as comma separated steam ids")?;
+        Ok(SteamIdAllowlist { ids })
+    }
+    pub(crate) fn permits(&self, id: SteamId) -> bool {
+        self.ids.as_ref().is_none_or(|ids| ids.contains(&id))
+    }
+}
+
+/// Discover the steam provider into a [`ProviderCache`], falling back to [`Provider::steam`]
+/// so the server still starts if steamcommunity.com can't be reached
+///
+/// Unreachable and overloaded OPs are retried with exponential backoff,
+/// a response that can't be parsed falls back right away.
+pub(crate) async fn discover_steam(
+    client: &reqwest::Client,
+    url: &str,
+    retry: DiscoveryRetry,
+) -> ProviderCache {
+    let providers = ProviderCache::new(url);
+    let attempts = retry.attempts.max(1);
+    let mut backoff = retry.backoff;
+    let mut attempt = 1;
+    loop {
+        let Err(err) = timed!("discovery", providers.refresh(client).await) else {
+            return providers;
+        };
+        if !err.is_transient() || attempt >= attempts {
+            log::warn!(
+                "couldn't discover steam openid service, falling back to the known provider: {:#}",
+                anyhow::Error::new(err)
+            );
+            return ProviderCache::with_provider(url, Provider::steam());
+        }
+        log::warn!(
+            "couldn't discover steam openid service (attempt {}/{}), retrying in {}ms: {:#}",
+            attempt,
+            attempts,
+            backoff.as_millis(),
+            anyhow::Error::new(err)
+        );
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+pub(crate) struct SteamState {
+    /// Steam as discovered at startup, refreshed every
+    /// [`PROVIDER_REFRESH_INTERVAL`](crate::server::PROVIDER_REFRESH_INTERVAL)
+    pub(crate) providers: ProviderCache,
+    pub(crate) nonces: NonceSet,
+    /// Assertions signed with one of these don't need a `check_authentication` request
+    pub(crate) associations: Associations,
+    /// Assertions that have already been accepted
+    pub(crate) replays: ReplayCache,
+    pub(crate) api: Box<dyn SteamApi>,
+    pub(crate) open_id: OpenIdState,
+    pub(crate) allowlist: SteamIdAllowlist,
+    /// Configured through `NONCE_GRACE_MS` (see [`NonceSet::with_grace_ms`])
+    /// and `NONCE_MAX_SKEW_MS`, applies to the response nonce of steam as well
+    pub(crate) nonce_tolerance: NonceTolerance,
+    /// Whether `STEAM_API_KEY` is set to something, reported by the readiness probe
+    pub(crate) has_api_key: bool,
+    /// Log query params of the callback that aren't part of the assertion,
+    /// configured through `STRICT_CALLBACK` (default `false`)
+    pub(crate) strict_callback: bool,
+    pub(crate) callback_response: CallbackResponseMode,
+}
+impl SteamState {
+    /// `providers` has to hold a provider already, see [`discover_steam`]
+    pub(crate) fn new(
+        api: Box<dyn SteamApi>,
+        providers: ProviderCache,
+    ) -> anyhow::Result<SteamState> {
+        let has_api_key = dotenv::var("STEAM_API_KEY").is_ok_and(|key| !key.trim().is_empty());
+
+        let nonce_grace_ms = match dotenv::var("NONCE_GRACE_MS") {
+            Ok(grace) => grace
+                .parse()
+                .context("couldn't parse NONCE_GRACE_MS as an integer")?,
+            Err(_) => DEFAULT_NONCE_GRACE_MS,
+        };
+        let nonce_max_skew_ms = match dotenv::var("NONCE_MAX_SKEW_MS") {
+            Ok(skew) => skew
+                .parse()
+                .context("couldn't parse NONCE_MAX_SKEW_MS as an integer")?,
+            Err(_) => DEFAULT_NONCE_MAX_SKEW_MS,
+        };
+        let nonce_tolerance = NonceTolerance {
+            grace_ms: nonce_grace_ms,
+            max_skew_ms: nonce_max_skew_ms,
+        };
+        let nonces = NonceSet::new(STEAM_NONCE_NAMESPACE).with_grace_ms(nonce_grace_ms);
+        let open_id = OpenIdState::new()?;
+        let allowlist = SteamIdAllowlist::new()?;
+        let callback_response = match dotenv::var("CALLBACK_RESPONSE") {
+            Ok(mode) => mode.parse()?,
+            Err(_) => CallbackResponseMode::default(),
+        };
+        let strict_callback = match dotenv::var("STRICT_CALLBACK") {
+            Ok(strict) => strict
+                .parse()
+                .context("couldn't parse STRICT_CALLBACK as a boolean")?,
+            Err(_) => false,
+        };
+
+        Ok(SteamState {
+            providers,
+            nonces,
+            associations: Associations::default(),
+            replays: ReplayCache::for_tolerance(nonce_tolerance),
+            api,
+            open_id,
+            allowlist,
+            nonce_tolerance,
+            has_api_key,
+            strict_callback,
+            callback_response,
+        })
+    }
+    /// The steam provider currently in use
+    pub(crate) fn provider(&self) -> Arc<Provider> {
+        self.providers
+            .provider()
+            .unwrap_or_else(|| Arc::new(Provider::steam()))
+    }
+    /// When [`SteamState::provider`] was last confirmed by steam
+    pub(crate) fn discovered_at(&self) -> DateTime<Utc> {
+        self.providers.discovered_at().unwrap_or_else(Utc::now)
+    }
+    /// Auth request that returns with the nonce and the session bound `csrf_state`,
+    /// steam is asked to sign with the current association if there is one
+    pub(crate) fn auth_url_with_nonce(
+        &self,
+        nonce: &str,
+        csrf_state: &str,
+    ) -> anyhow::Result<String> {
+        let association = self.associations.current();
+        self.open_id.auth_url_with_params(
+            &self.provider(),
+            &ClaimedId::Select,
+            &[("custom_nonce", nonce), ("state", csrf_state)],
+            association
+                .as_ref()
+                .map(|association| association.handle.as_str()),
+        )
+    }
+}
+
+/// Login with any OpenID 2.0 provider the user supplies an identifier for
+pub(crate) struct GenericState {
+    pub(crate) nonces: NonceSet,
+    /// Provider discovered for every login that hasn't come back yet
+    pub(crate) pending: PendingLogins,
+    /// Same as for steam except for `OPENID_GENERIC_RETURN_TO`
+    pub(crate) open_id: OpenIdState,
+    pub(crate) nonce_tolerance: NonceTolerance,
+    /// Assertions that have already been accepted
+    pub(crate) replays: ReplayCache,
+}
+impl GenericState {
+    pub(crate) fn new(nonce_tolerance: NonceTolerance) -> anyhow::Result<GenericState> {
+        let return_to = dotenv::var("OPENID_GENERIC_RETURN_TO")
+            .unwrap_or_else(|_| DEFAULT_GENERIC_RETURN_TO.to_string());
+        let open_id = OpenIdState::with_return_to(&return_to)?;
+
+        Ok(GenericState {
+            nonces: NonceSet::new(GENERIC_NONCE_NAMESPACE).with_grace_ms(nonce_tolerance.grace_ms),
+            pending: PendingLogins::default(),
+            open_id,
+            nonce_tolerance,
+            replays: ReplayCache::for_tolerance(nonce_tolerance),
+        })
+    }
+    /// Auth request for the identifier the user supplied, see [`ClaimedId`]
+    pub(crate) fn auth_url_with_nonce(
+        &self,
+        provider: &Provider,
+        claimed_id: &ClaimedId,
+        nonce: &str,
+    ) -> anyhow::Result<String> {
+        self.open_id
+            .auth_url_with_nonce(provider, claimed_id, nonce)
+    }
+}
+
+pub(crate) struct State {
+    pub(crate) client: reqwest::Client,
+    pub(crate) steam: SteamState,
+    pub(crate) generic: GenericState,
+    /// Authenticated sessions issued for another version are treated as logged out.
+    ///
+    /// Configured through `SESSION_VERSION` (default `0`), increase it
+    /// and restart to invalidate every existing session, e.g. after a security event.
+    pub(crate) session_version: u32,
+    /// Address (`host:port`) of the redis session store
+    pub(crate) redis_url: String,
+    /// Status of every redirect of the auth endpoints
+    ///
+    /// Configured through `REDIRECT_STATUS`, see [`parse_redirect_status`].
+    pub(crate) redirect_status: StatusCode,
+    pub(crate) metrics: Metrics,
+    /// Player summaries served by the steam api endpoints
+    ///
+    /// Configured through `PROFILE_CACHE_TTL_SECS` (default `60`), `0` disables the cache.
+    pub(crate) profiles: ProfileCache,
+}
+
+/// Everything [`State`] talks to over the network, injected so tests can replace it with mocks
+pub(crate) struct Dependencies {
+    pub(crate) client: reqwest::Client,
+    pub(crate) api: Box<dyn SteamApi>,
+    /// The steam OP, discovered through [`Dependencies::client`]
+    pub(crate) providers: ProviderCache,
+}
+impl Dependencies {
+    /// Configured through `STEAM_API_KEY` and the variables of [`ClientConfig`]
+    /// and [`DiscoveryRetry`], steam is discovered right away
+    pub(crate) async fn from_env() -> anyhow::Result<Dependencies> {
+        let client_config = ClientConfig::from_env().context("couldn't load http client config")?;
+        let client = create_client(&client_config)?;
+        let api = steam_api_concurrent::ClientOptions::new()
+            .api_key(dotenv::var("STEAM_API_KEY").context("load STEAM_API_KEY env variable")?)
+            .build()
+            .await
+            .context("couldn't prepare steam api client")?;
+        let discovery_retry = DiscoveryRetry::from_env()?;
+        let providers = discover_steam(&client, STEAM_OPENID_LOGIN, discovery_retry).await;
+        Ok(Dependencies {
+            client,
+            api: Box::new(api),
+            providers,
+        })
+    }
+}
+
+impl State {
+    pub async fn new() -> anyhow::Result<State> {
+        State::with_dependencies(Dependencies::from_env().await?)
+    }
+    /// Everything but `deps` is read from the environment
+    pub(crate) fn with_dependencies(deps: Dependencies) -> anyhow::Result<State> {
+        let Dependencies {
+            client,
+            api,
+            providers,
+        } = deps;
+        let steam = SteamState::new(api, providers).context("couldn't create steam state")?;
+        let generic = GenericState::new(steam.nonce_tolerance)
+            .context("couldn't create generic openid state")?;
+
+        let session_version = match dotenv::var("SESSION_VERSION") {
+            Ok(version) => version
+                .parse()
+                .context("couldn't parse SESSION_VERSION as an integer")?,
+            Err(_) => 0,
+        };
+
+        let redis_url = dotenv::var("REDIS_URL").context("load REDIS_URL env variable")?;
+
+        let redirect_status = match dotenv::var("REDIRECT_STATUS") {
+            Ok(status) => parse_redirect_status(&status)?,
+            Err(_) => DEFAULT_REDIRECT_STATUS,
+        };
+
+        let profile_cache_ttl = match dotenv::var("PROFILE_CACHE_TTL_SECS") {
+            Ok(ttl) => Duration::from_secs(
+                ttl.parse()
+                    .context("couldn't parse PROFILE_CACHE_TTL_SECS as an integer")?,
+            ),
+            Err(_) => DEFAULT_PROFILE_CACHE_TTL,
+        };
+
+        Ok(State {
+            client,
+            steam,
+            generic,
+            session_version,
+            redis_url,
+            redirect_status,
+            metrics: Metrics::default(),
+            profiles: ProfileCache::new(profile_cache_ttl),
+        })
+    }
+}
+
+#[cfg(test)]
+impl Dependencies {
+    /// A plain http client and a [`MockSteamApi`] without any players,
+    /// steam is replaced by `provider`, e.g. a mock OP
+    ///
+    /// [`MockSteamApi`]: crate::util::mock_steam_api::MockSteamApi
+    pub(crate) fn for_test(provider: Provider) -> Dependencies {
+        Dependencies {
+            client: reqwest::Client::new(),
+            api: Box::<crate::util::mock_steam_api::MockSteamApi>::default(),
+            providers: ProviderCache::with_provider(STEAM_OPENID_LOGIN, provider),
+        }
+    }
+}
+
+#[cfg(test)]
+impl State {
+    /// State for handler tests, see [`Dependencies::for_test`]
+    pub(crate) fn for_test(provider: Provider) -> anyhow::Result<State> {
+        State::for_test_with(Dependencies::for_test(provider))
+    }
+    /// State for handler tests around `deps`, nothing is read from the environment
+    pub(crate) fn for_test_with(deps: Dependencies) -> anyhow::Result<State> {
+        let Dependencies {
+            client,
+            api,
+            providers,
+        } = deps;
+        let open_id = |return_to: &str| {
+            OpenIdState::from_values(
+                "http://localhost:8080",
+                return_to,
+                "/welcome".to_string(),
+                "/goodbye".to_string(),
+            )
+        };
+        let nonce_tolerance = NonceTolerance::default();
+
+        Ok(State {
+            client,
+            steam: SteamState {
+                providers,
+                nonces: NonceSet::new(STEAM_NONCE_NAMESPACE),
+                associations: Associations::default(),
+                replays: ReplayCache::for_tolerance(nonce_tolerance),
+                api,
+                open_id: open_id("/api/auth/steam/callback")?,
+                allowlist: SteamIdAllowlist::from_value(None)?,
+                nonce_tolerance,
+                has_api_key: false,
+                strict_callback: false,
+                callback_response: CallbackResponseMode::default(),
+            },
+            generic: GenericState {
+                nonces: NonceSet::new(GENERIC_NONCE_NAMESPACE),
+                pending: PendingLogins::default(),
+                open_id: open_id(DEFAULT_GENERIC_RETURN_TO)?,
+                nonce_tolerance,
+                replays: ReplayCache::for_tolerance(nonce_tolerance),
+            },
+            session_version: 0,
+            redis_url: String::new(),
+            redirect_status: DEFAULT_REDIRECT_STATUS,
+            metrics: Metrics::default(),
+            profiles: ProfileCache::new(DEFAULT_PROFILE_CACHE_TTL),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allowlist_unset_permits_everyone() -> anyhow::Result<()> {
+        for value in [None, Some("")] {
+            let allowlist = SteamIdAllowlist::from_value(value)?;
+            assert!(allowlist.permits(SteamId(76561198181282063)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn allowlist_permits_only_listed() -> anyhow::Result<()> {
+        let allowlist = SteamIdAllowlist::from_value(Some("76561198181282063,76561197960287930"))?;
+        assert!(allowlist.permits(SteamId(76561198181282063)));
+        assert!(allowlist.permits(SteamId(76561197960287930)));
+        assert!(!allowlist.permits(SteamId(76561198000000000)));
+        Ok(())
+    }
+
+    #[test]
+    fn allowlist_invalid() {
+        assert!(SteamIdAllowlist::from_value(Some("76561198181282063,nope")).is_err());
+    }
+
+    #[test]
+    fn open_id_state_rejects_invalid_realm() {
+        let open_id = |realm: &str, return_to: &str| {
+            OpenIdState::from_values(realm, return_to, String::new(), String::new())
+        };
+        assert!(open_id("http://localhost:8080", "/api/auth/steam/callback").is_ok());
+        assert!(open_id("localhost:8080", "/api/auth/steam/callback").is_err());
+        assert!(open_id("http://*.com", "/api/auth/steam/callback").is_err());
+        assert!(open_id("http://localhost:8080", "@evil.com/callback").is_err());
+    }
+
+    #[tokio::test]
+    async fn discovery_falls_back_to_known_steam_provider() -> anyhow::Result<()> {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+
+        let retry = DiscoveryRetry {
+            attempts: 1,
+            backoff: Duration::ZERO,
+        };
+        let providers = discover_steam(&reqwest::Client::new(), &server.uri(), retry).await;
+        let provider = providers.provider().context("fallback wasn't cached")?;
+        assert_eq!(*provider, Provider::steam());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discovery_is_retried() -> anyhow::Result<()> {
+        use crate::openid::test_xrds::op_xrds;
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .with_priority(1)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                op_xrds("https://op.example.com/openid/login"),
+                "application/xrds+xml",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let retry = DiscoveryRetry {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let providers = discover_steam(&reqwest::Client::new(), &server.uri(), retry).await;
+        let provider = providers.provider().context("provider wasn't cached")?;
+        assert_eq!(provider.endpoint(), "https://op.example.com/openid/login");
+        Ok(())
+    }
+
+    /// Value of the query param `key` in `url`
+    fn query_param(url: &str, key: &str) -> anyhow::Result<String> {
+        let url = reqwest::Url::parse(url)?;
+        let (_, value) = url
+            .query_pairs()
+            .find(|(k, _)| k == key)
+            .with_context(|| format!("url is missing `{}`", key))?;
+        Ok(value.into_owned())
+    }
+
+    #[tokio::test]
+    async fn nonce_survives_return_to() -> anyhow::Result<()> {
+        let state = State::for_test(Provider::steam())?;
+
+        for _ in 0..1000 {
+            let nonce = state.steam.nonces.insert_new();
+            // the base64 alphabet is url safe, `-` and `_` need no escaping
+            assert!(!nonce.as_str().contains(['+', '/', '=']));
+
+            let auth_url = state.steam.auth_url_with_nonce(nonce.as_str(), "state")?;
+            let return_to = query_param(&auth_url, "openid.return_to")?;
+            assert_eq!(query_param(&return_to, "custom_nonce")?, nonce.as_str());
+            assert_eq!(query_param(&return_to, "state")?, "state");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! Associations established with the steam OP, keyed by their handle
+//!
+//! Auth requests ask steam to sign with the [current](Associations::current) one, so the
+//! assertion is verified locally, see [`PositiveAssertion::verify_with_association`].
+//!
+//! [`PositiveAssertion::verify_with_association`]: crate::openid::PositiveAssertion::verify_with_association
+
+use std::collections::HashMap;
+
+use crate::openid::Association;
+use parking_lot::Mutex;
+
+#[derive(Debug, Default)]
+pub(crate) struct Associations {
+    inner: Mutex<HashMap<String, Association>>,
+}
+
+impl Associations {
+    pub(crate) fn insert(&self, association: Association) {
+        let _ = self
+            .inner
+            .lock()
+            .insert(association.handle.clone(), association);
+    }
+    pub(crate) fn get(&self, handle: &str) -> Option<Association> {
+        self.inner.lock().get(handle).cloned()
+    }
+    /// Forget `handle`, e.g. because it expired or the OP invalidated it
+    pub(crate) fn remove(&self, handle: &str) {
+        let _ = self.inner.lock().remove(handle);
+    }
+    /// The association that is valid for the longest time
+    pub(crate) fn current(&self) -> Option<Association> {
+        self.inner
+            .lock()
+            .values()
+            .filter(|association| !association.is_expired())
+            .max_by_key(|association| association.expires_at)
+            .cloned()
+    }
+    pub(crate) fn remove_expired(&self) {
+        self.inner
+            .lock()
+            .retain(|_, association| !association.is_expired());
+    }
+}
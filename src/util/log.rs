@@ -3,7 +3,13 @@ use simplelog::{
     format_description, ColorChoice, ConfigBuilder, LevelFilter, TermLogger, TerminalMode,
 };
 
-pub(crate) fn init_logger() -> anyhow::Result<()> {
+/// Debug level is needed for the `[timing]` logs of [`crate::util::timing::timed`]
+#[cfg(feature = "timing")]
+const LEVEL: LevelFilter = LevelFilter::Debug;
+#[cfg(not(feature = "timing"))]
+const LEVEL: LevelFilter = LevelFilter::Info;
+
+pub fn init_logger() -> anyhow::Result<()> {
     let mut config = ConfigBuilder::default();
 
     config
@@ -18,7 +24,7 @@ pub(crate) fn init_logger() -> anyhow::Result<()> {
     config.set_time_offset_to_local().unwrap();
 
     TermLogger::init(
-        LevelFilter::Info,
+        LEVEL,
         config.build(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
@@ -0,0 +1,185 @@
+//! Counters for the login funnel, exposed in the Prometheus text format
+//!
+//! <https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format>
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::util::nonce::NonceSetStats;
+
+/// Prefix of every exposed metric name
+const METRIC_PREFIX: &str = "complainer";
+
+/// `Content-Type` of [`Metrics::to_prometheus`]
+pub(crate) const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Monotonically increasing counter
+#[derive(Debug, Default)]
+pub(crate) struct Counter(AtomicU64);
+
+impl Counter {
+    pub(crate) fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    /// Users redirected to steam
+    pub(crate) logins_started: Counter,
+    /// Users coming back from steam
+    pub(crate) callbacks_received: Counter,
+    /// Callbacks whose nonce didn't match the session or was unknown
+    pub(crate) nonce_mismatches: Counter,
+    /// Assertions steam said weren't issued by it
+    pub(crate) verify_invalid: Counter,
+    /// Assertions that couldn't be verified because steam didn't answer properly
+    pub(crate) verify_unreachable: Counter,
+}
+
+impl Metrics {
+    const fn counters(&self) -> [(&'static str, &'static str, &Counter); 5] {
+        [
+            (
+                "logins_started_total",
+                "Users redirected to the OpenID provider",
+                &self.logins_started,
+            ),
+            (
+                "callbacks_received_total",
+                "Users returning from the OpenID provider",
+                &self.callbacks_received,
+            ),
+            (
+                "nonce_mismatches_total",
+                "Callbacks with an unknown or mismatching nonce",
+                &self.nonce_mismatches,
+            ),
+            (
+                "verify_invalid_total",
+                "Assertions the OpenID provider rejected",
+                &self.verify_invalid,
+            ),
+            (
+                "verify_unreachable_total",
+                "Assertions that couldn't be verified against the OpenID provider",
+                &self.verify_unreachable,
+            ),
+        ]
+    }
+    pub(crate) fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, help, counter) in self.counters() {
+            write_header(&mut out, name, help, "counter");
+            // writing to a string can't fail
+            let _ = writeln!(out, "{}_{} {}", METRIC_PREFIX, name, counter.get());
+        }
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    // writing to a string can't fail
+    let _ = writeln!(out, "# HELP {}_{} {}", METRIC_PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", METRIC_PREFIX, name, kind);
+}
+
+/// Size and counters of the nonce sets, labelled with the name of each set
+pub(crate) fn nonce_sets_to_prometheus(sets: &[(&str, NonceSetStats)]) -> String {
+    type Metric = (
+        &'static str,
+        &'static str,
+        &'static str,
+        fn(&NonceSetStats) -> u64,
+    );
+    const METRICS: [Metric; 5] = [
+        (
+            "nonce_set_entries",
+            "Nonces currently stored, including expired ones that haven't been removed yet",
+            "gauge",
+            |stats| stats.len as u64,
+        ),
+        (
+            "nonce_set_inserts_total",
+            "Nonces handed out",
+            "counter",
+            |stats| stats.inserts,
+        ),
+        (
+            "nonce_set_hits_total",
+            "Callbacks whose nonce was valid",
+            "counter",
+            |stats| stats.hits,
+        ),
+        (
+            "nonce_set_misses_total",
+            "Callbacks whose nonce was unknown or expired, spikes suggest replay attempts",
+            "counter",
+            |stats| stats.misses,
+        ),
+        (
+            "nonce_set_expired_evictions_total",
+            "Nonces removed after they expired without being used",
+            "counter",
+            |stats| stats.expired_evictions,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, kind, value) in METRICS {
+        write_header(&mut out, name, help, kind);
+        for (set, stats) in sets {
+            // writing to a string can't fail
+            let _ = writeln!(
+                out,
+                "{}_{}{{set=\"{}\"}} {}",
+                METRIC_PREFIX,
+                name,
+                set,
+                value(stats)
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prometheus_format() {
+        let metrics = Metrics::default();
+        metrics.logins_started.inc();
+        metrics.logins_started.inc();
+        metrics.verify_unreachable.inc();
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains(
+            "# TYPE complainer_logins_started_total counter\ncomplainer_logins_started_total 2\n"
+        ));
+        assert!(text.contains("complainer_callbacks_received_total 0\n"));
+        assert!(text.contains("complainer_verify_unreachable_total 1\n"));
+        assert_eq!(text.lines().count(), 3 * 5);
+    }
+
+    #[test]
+    fn nonce_sets_format() {
+        let stats = NonceSetStats {
+            len: 2,
+            inserts: 5,
+            hits: 3,
+            misses: 1,
+            expired_evictions: 0,
+        };
+        let text = nonce_sets_to_prometheus(&[("steam", stats), ("generic", stats)]);
+        assert!(text.contains(
+            "# TYPE complainer_nonce_set_entries gauge\ncomplainer_nonce_set_entries{set=\"steam\"} 2\ncomplainer_nonce_set_entries{set=\"generic\"} 2\n"
+        ));
+        assert!(text.contains("complainer_nonce_set_misses_total{set=\"steam\"} 1\n"));
+        assert_eq!(text.lines().count(), 4 * 5);
+    }
+}
@@ -0,0 +1,178 @@
+//! In-process OpenID provider that pretends to be steam, for driving the login flow in tests
+//!
+//! It serves an XRDS document for discovery, signs the positive assertions it hands out,
+//! answers `check_authentication` by checking that signature and hands out the association
+//! it signs with to an `associate` request.
+
+use crate::openid::constants::*;
+use crate::openid::nonce::Nonce;
+use crate::openid::test_xrds::op_xrds;
+use crate::openid::{
+    make_base_string, verify_signature, AssocType, Association, PositiveAssertionBuilder,
+    SteamIdentity,
+};
+use anyhow::Context;
+use base64::engine::general_purpose::STANDARD as Base64;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use steam_api_concurrent::SteamId;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+const MAC_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+const ASSOC_HANDLE: &str = "mock";
+const IDENTIFIER_PATH: &str = "/openid";
+const ENDPOINT_PATH: &str = "/openid/login";
+
+fn sign(base_string: &str) -> anyhow::Result<String> {
+    let mac_key = Base64.decode(MAC_KEY)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key).context("invalid mac key length")?;
+    mac.update(base_string.as_bytes());
+    Ok(Base64.encode(mac.finalize().into_bytes()))
+}
+
+/// Signature check of a `check_authentication` request body
+fn check_authentication(body: &[u8]) -> anyhow::Result<bool> {
+    let fields: Vec<(String, String)> = serde_urlencoded::from_bytes(body)?;
+    let value = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k.strip_prefix(OPENID_FIELD_PREFIX) == Some(key))
+            .map(|(_, value)| value.as_str())
+            .with_context(|| format!("field `{}` is missing", key))
+    };
+    if value("mode")? != OPENID_MODE_CHECK_AUTHENTICATION {
+        anyhow::bail!("not a check_authentication request");
+    }
+    let signed = value("signed")?
+        .split(',')
+        .map(|key| Ok((key, value(key)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    verify_signature(
+        AssocType::HmacSha256,
+        MAC_KEY,
+        &make_base_string(signed),
+        value("sig")?,
+    )
+}
+
+/// Whether the body of a direct request is an `associate` request
+fn is_associate(body: &[u8]) -> bool {
+    serde_urlencoded::from_bytes::<Vec<(String, String)>>(body).is_ok_and(|fields| {
+        fields
+            .iter()
+            .any(|(key, value)| key == OPENID_MODE && value == "associate")
+    })
+}
+
+/// Answers the direct requests to the endpoint
+struct DirectRequest;
+
+impl Respond for DirectRequest {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let body = if is_associate(&request.body) {
+            format!(
+                "ns:{}\nassoc_handle:{}\nsession_type:{}\nassoc_type:{}\nexpires_in:3600\nmac_key:{}\n",
+                OPENID_AUTH_NAMESPACE,
+                ASSOC_HANDLE,
+                OPENID_SESSION_TYPE_NO_ENCRYPTION,
+                AssocType::HmacSha256.as_str(),
+                MAC_KEY
+            )
+        } else {
+            let is_valid = check_authentication(&request.body).unwrap_or(false);
+            format!("ns:{}\nis_valid:{}\n", OPENID_AUTH_NAMESPACE, is_valid)
+        };
+        ResponseTemplate::new(200).set_body_raw(body, "text/plain")
+    }
+}
+
+pub(crate) struct MockOp {
+    server: MockServer,
+}
+
+impl MockOp {
+    pub(crate) async fn start() -> MockOp {
+        let server = MockServer::start().await;
+        let xrds = op_xrds(&format!("{}{}", server.uri(), ENDPOINT_PATH));
+        Mock::given(method("GET"))
+            .and(path(IDENTIFIER_PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(xrds, "application/xrds+xml"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(ENDPOINT_PATH))
+            .respond_with(DirectRequest)
+            .mount(&server)
+            .await;
+        MockOp { server }
+    }
+    /// Answer every further direct request with `503`, as if steam was down
+    pub(crate) async fn go_down(&self) {
+        Mock::given(method("POST"))
+            .and(path(ENDPOINT_PATH))
+            .respond_with(ResponseTemplate::new(503))
+            .with_priority(1)
+            .mount(&self.server)
+            .await;
+    }
+    /// The association the assertions of [`MockOp::positive_assertion`] are signed with
+    pub(crate) fn association(&self) -> Association {
+        Association {
+            handle: ASSOC_HANDLE.to_string(),
+            assoc_type: AssocType::HmacSha256,
+            mac_key: MAC_KEY.to_string(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+        }
+    }
+    /// OP Identifier to run discovery on
+    pub(crate) fn identifier(&self) -> String {
+        format!("{}{}", self.server.uri(), IDENTIFIER_PATH)
+    }
+    pub(crate) fn endpoint(&self) -> String {
+        format!("{}{}", self.server.uri(), ENDPOINT_PATH)
+    }
+    /// Let `steam_id` log in for the authentication request `auth_url`
+    ///
+    /// Returns the `return_to` url with the signed positive assertion appended.
+    pub(crate) fn positive_assertion(
+        &self,
+        auth_url: &str,
+        steam_id: SteamId,
+    ) -> anyhow::Result<reqwest::Url> {
+        self.positive_assertion_for(auth_url, &SteamIdentity(steam_id).to_claimed_id())
+    }
+    /// Like [`MockOp::positive_assertion`] for any identifier, e.g. one delegated to this OP
+    pub(crate) fn positive_assertion_for(
+        &self,
+        auth_url: &str,
+        claimed_id: &str,
+    ) -> anyhow::Result<reqwest::Url> {
+        let auth_url = reqwest::Url::parse(auth_url).context("couldn't parse auth url")?;
+        let (_, return_to) = auth_url
+            .query_pairs()
+            .find(|(key, _)| key == OPENID_RETURN_TO)
+            .context("auth url is missing return_to")?;
+        let mut return_to = reqwest::Url::parse(&return_to).context("invalid return_to")?;
+
+        let unsigned = PositiveAssertionBuilder::new()
+            .op_endpoint(self.endpoint())
+            .claimed_id(claimed_id)
+            .identity(claimed_id)
+            .return_to(return_to.as_str())
+            .response_nonce(Nonce::new(Utc::now(), "mock"))
+            .assoc_handle(ASSOC_HANDLE);
+        let sig = sign(&unsigned.clone().build()?.signature_base_string()?)?;
+        let assertion = unsigned.signature(sig).build()?;
+
+        let query = match return_to.query() {
+            Some(query) => format!("{}&{}", query, assertion.to_query_string()?),
+            None => assertion.to_query_string()?,
+        };
+        return_to.set_query(Some(&query));
+
+        Ok(return_to)
+    }
+}
@@ -0,0 +1,72 @@
+//! Steam web api with canned responses, for testing the handlers without a key or network
+
+use std::collections::HashMap;
+
+use futures_util::future::{ready, LocalBoxFuture};
+use futures_util::FutureExt;
+use steam_api_concurrent::SteamId;
+
+use crate::util::steam_api::SteamApi;
+
+/// Knows the players added with [`MockSteamApi::with_player`], every one of them
+/// is at level 1 and has no bans
+#[derive(Debug, Default)]
+pub(crate) struct MockSteamApi {
+    summaries: HashMap<SteamId, serde_json::Value>,
+}
+
+impl MockSteamApi {
+    pub(crate) fn with_player(mut self, steam_id: SteamId, persona_name: &str) -> MockSteamApi {
+        let summary = serde_json::json!({
+            "steamid": steam_id.to_string(),
+            "personaname": persona_name,
+        });
+        let _ = self.summaries.insert(steam_id, summary);
+        self
+    }
+    fn knows(&self, steam_id: SteamId) -> anyhow::Result<()> {
+        if !self.summaries.contains_key(&steam_id) {
+            anyhow::bail!("steam id {} is unknown", steam_id);
+        }
+        Ok(())
+    }
+}
+
+impl SteamApi for MockSteamApi {
+    fn player_summaries(
+        &self,
+        steam_ids: Vec<SteamId>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Vec<(SteamId, serde_json::Value)>>> {
+        let summaries = steam_ids
+            .into_iter()
+            .filter_map(|steam_id| Some((steam_id, self.summaries.get(&steam_id)?.clone())))
+            .collect();
+        ready(Ok(summaries)).boxed_local()
+    }
+    fn player_bans(
+        &self,
+        steam_ids: Vec<SteamId>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Vec<serde_json::Value>>> {
+        let bans = steam_ids
+            .into_iter()
+            .filter(|&steam_id| self.knows(steam_id).is_ok())
+            .map(|steam_id| {
+                serde_json::json!({
+                    "SteamId": steam_id.to_string(),
+                    "VACBanned": false,
+                    "NumberOfGameBans": 0,
+                })
+            })
+            .collect();
+        ready(Ok(bans)).boxed_local()
+    }
+    fn steam_level(
+        &self,
+        steam_id: SteamId,
+    ) -> LocalBoxFuture<'_, anyhow::Result<serde_json::Value>> {
+        let level = self
+            .knows(steam_id)
+            .map(|()| serde_json::json!({ "player_level": 1 }));
+        ready(level).boxed_local()
+    }
+}
@@ -1,2 +1,18 @@
+pub(crate) mod associations;
 pub(crate) mod log;
+pub(crate) mod metrics;
+#[cfg(test)]
+pub(crate) mod mock_op;
+#[cfg(test)]
+pub(crate) mod mock_steam_api;
 pub(crate) mod nonce;
+pub(crate) mod pending_login;
+pub(crate) mod profile_cache;
+pub(crate) mod query_limit;
+pub(crate) mod rate_limit;
+pub(crate) mod redis;
+pub(crate) mod replay_cache;
+pub(crate) mod steam_api;
+#[cfg(test)]
+pub(crate) mod test_app;
+pub(crate) mod timing;
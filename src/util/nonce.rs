@@ -14,14 +14,20 @@
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use parking_lot::Mutex;
 use rand::RngCore;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::error::{AppError, IntoAppError};
+
 const NONCE_BYTES: usize = 36;
+/// Not part of the url-safe base64 alphabet so it can't be confused with the random part
+const NONCE_NAMESPACE_SEPARATOR: char = '.';
 const NONCE_BASE64_LEN: usize = (NONCE_BYTES * 4) / 3;
 
 /// 5 Minutes between us redirecting the user to steam
@@ -29,6 +35,9 @@ const NONCE_BASE64_LEN: usize = (NONCE_BYTES * 4) / 3;
 /// seems reasonable.
 const NONCE_MAX_AGE_MS: i64 = 5_000_000;
 
+/// Default for [`NonceSet::with_grace_ms`], same as for the response nonce of the OP
+pub(crate) use crate::openid::nonce::DEFAULT_NONCE_GRACE_MS;
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(transparent)]
 pub(crate) struct Nonce {
@@ -50,20 +59,23 @@ impl Metadata {
         let now = Utc::now().timestamp_millis();
         Metadata { time: now }
     }
-    const fn is_expired(&self, now: i64) -> bool {
-        now - self.time > NONCE_MAX_AGE_MS
+    const fn is_expired(&self, now: i64, grace_ms: i64) -> bool {
+        now - self.time > NONCE_MAX_AGE_MS + grace_ms
     }
 }
 
 impl Nonce {
-    fn random() -> Nonce {
+    /// Generate a random nonce of the form `<namespace>.<base64>`
+    fn random(namespace: &str) -> Nonce {
         use base64::engine::general_purpose::URL_SAFE_NO_PAD as Base64;
         use base64::Engine;
 
         let mut nonce_bytes = [0u8; NONCE_BYTES];
         rand::thread_rng().fill_bytes(&mut nonce_bytes);
 
-        let mut nonce_base64 = String::with_capacity(NONCE_BASE64_LEN);
+        let mut nonce_base64 = String::with_capacity(namespace.len() + 1 + NONCE_BASE64_LEN);
+        nonce_base64.push_str(namespace);
+        nonce_base64.push(NONCE_NAMESPACE_SEPARATOR);
         Base64.encode_string(nonce_bytes, &mut nonce_base64);
 
         Nonce {
@@ -83,23 +95,97 @@ pub(crate) enum NonceError {
     Expired,
 }
 
+impl NonceError {
+    /// An expired nonce means the login took too long and can simply be retried
+    pub(crate) const fn status_code(&self) -> StatusCode {
+        match self {
+            NonceError::Invalid => StatusCode::BAD_REQUEST,
+            NonceError::Expired => StatusCode::GONE,
+        }
+    }
+    /// See [`AppError::with_code`]
+    pub(crate) const fn code(&self) -> &'static str {
+        match self {
+            NonceError::Invalid => "nonce_invalid",
+            NonceError::Expired => "nonce_expired",
+        }
+    }
+    pub(crate) fn into_app_error(self) -> AppError {
+        let status_code = self.status_code();
+        let code = self.code();
+        anyhow::Error::new(self)
+            .into_app_error_with_status(status_code)
+            .with_code(code)
+    }
+}
+
+/// Every set has its own namespace (e.g. one per provider) so a nonce
+/// minted for one provider is never accepted on the callback of another.
 #[derive(Debug)]
 pub(crate) struct NonceSet {
+    namespace: &'static str,
     inner: Mutex<HashMap<Nonce, Metadata>>,
+    /// See [`NonceSet::with_grace_ms`]
+    grace_ms: i64,
+    /// Calls to [`NonceSet::validate_and_remove`] that succeeded
+    hits: AtomicU64,
+    /// Calls to [`NonceSet::validate_and_remove`] that failed
+    misses: AtomicU64,
+    /// Nonces handed out by [`NonceSet::insert_new`] and [`NonceSet::replace`]
+    inserts: AtomicU64,
+    /// Nonces dropped by [`NonceSet::remove_expired_nonces`]
+    expired_evictions: AtomicU64,
+}
+
+/// Counters and size of a [`NonceSet`], exposed by the metrics endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct NonceSetStats {
+    pub(crate) len: usize,
+    pub(crate) inserts: u64,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) expired_evictions: u64,
 }
 impl NonceSet {
+    /// Check that the nonce was minted by a set with the same namespace
+    fn in_namespace(&self, nonce: &str) -> bool {
+        nonce
+            .strip_prefix(self.namespace)
+            .is_some_and(|rest| rest.starts_with(NONCE_NAMESPACE_SEPARATOR))
+    }
+
     /// Remove all expired nonces
     pub(crate) fn remove_expired_nonces(&self) {
         let now = Utc::now().timestamp_millis();
-        self.inner.lock().retain(|_, meta| !meta.is_expired(now));
+        let evicted = {
+            let mut lock = self.inner.lock();
+            let before = lock.len();
+            lock.retain(|_, meta| !meta.is_expired(now, self.grace_ms));
+            before - lock.len()
+        };
+        self.expired_evictions
+            .fetch_add(evicted as u64, Ordering::Relaxed);
     }
 
     /// Validate the nonce and remove it, if it is valid
     pub(crate) fn validate_and_remove(&self, nonce: &str) -> Result<(), NonceError> {
+        let result = self.validate_and_remove_inner(nonce);
+        let counter = if result.is_ok() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+    fn validate_and_remove_inner(&self, nonce: &str) -> Result<(), NonceError> {
+        if !self.in_namespace(nonce) {
+            return Err(NonceError::Invalid);
+        }
         let Some(nonce) = self.inner.lock().remove(nonce) else {
             return Err(NonceError::Invalid);
         };
-        if nonce.is_expired(Utc::now().timestamp_millis()) {
+        if nonce.is_expired(Utc::now().timestamp_millis(), self.grace_ms) {
             return Err(NonceError::Expired);
         }
         Ok(())
@@ -107,6 +193,9 @@ impl NonceSet {
 
     /// Check if the nonce is valid (as in not expired)
     pub(crate) fn validate(&self, nonce: &str) -> Result<(), NonceError> {
+        if !self.in_namespace(nonce) {
+            return Err(NonceError::Invalid);
+        }
         if self.inner.lock().contains_key(nonce) {
             Ok(())
         } else {
@@ -116,7 +205,10 @@ impl NonceSet {
 
     /// Look for the given nonce and replace it
     pub(crate) fn replace(&self, old: &str) -> Result<Nonce, NonceError> {
-        let new_nonce = Nonce::random();
+        if !self.in_namespace(old) {
+            return Err(NonceError::Invalid);
+        }
+        let new_nonce = Nonce::random(self.namespace);
         let new_meta = Metadata::new(&new_nonce);
         let new_nonce_copy = new_nonce.clone();
 
@@ -127,25 +219,188 @@ impl NonceSet {
             }
             let _ = lock.insert(new_nonce, new_meta);
         }
+        self.inserts.fetch_add(1, Ordering::Relaxed);
 
         Ok(new_nonce_copy)
     }
 
     /// Insert a new nonce
     pub(crate) fn insert_new(&self) -> Nonce {
-        let nonce = Nonce::random();
+        let nonce = Nonce::random(self.namespace);
         let meta = Metadata::new(&nonce);
         let nonce_copy = nonce.clone();
 
         let _ = self.inner.lock().insert(nonce, meta);
+        self.inserts.fetch_add(1, Ordering::Relaxed);
 
         nonce_copy
     }
 
-    /// Create a new thingy
-    pub(crate) fn new() -> NonceSet {
+    /// Number of stored nonces, including expired ones that haven't been removed yet
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().len()
+    }
+
+    /// Age of the oldest stored nonce
+    pub(crate) fn oldest_age(&self) -> Option<Duration> {
+        let now = Utc::now().timestamp_millis();
+        let oldest = self.inner.lock().values().map(|meta| meta.time).min()?;
+        Some(Duration::milliseconds(now - oldest))
+    }
+
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stats(&self) -> NonceSetStats {
+        NonceSetStats {
+            len: self.len(),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            hits: self.hits(),
+            misses: self.misses(),
+            expired_evictions: self.expired_evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Keep accepting nonces for `grace_ms` after they expired,
+    /// defaults to [`DEFAULT_NONCE_GRACE_MS`]
+    pub(crate) const fn with_grace_ms(mut self, grace_ms: i64) -> NonceSet {
+        self.grace_ms = grace_ms;
+        self
+    }
+
+    /// Create a new thingy, `namespace` must not contain [`NONCE_NAMESPACE_SEPARATOR`]
+    pub(crate) fn new(namespace: &'static str) -> NonceSet {
+        debug_assert!(!namespace.contains(NONCE_NAMESPACE_SEPARATOR));
         NonceSet {
+            namespace,
             inner: Mutex::new(HashMap::with_capacity(128)),
+            grace_ms: DEFAULT_NONCE_GRACE_MS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+            expired_evictions: AtomicU64::new(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nonce_has_namespace() {
+        let nonces = NonceSet::new("steam");
+        let nonce = nonces.insert_new();
+        assert!(nonce.as_str().starts_with("steam."));
+    }
+
+    #[test]
+    fn nonce_is_removed_once() {
+        let nonces = NonceSet::new("steam");
+        let nonce = nonces.insert_new();
+        assert!(nonces.validate_and_remove(nonce.as_str()).is_ok());
+        assert!(matches!(
+            nonces.validate_and_remove(nonce.as_str()),
+            Err(NonceError::Invalid)
+        ));
+        assert_eq!(nonces.hits(), 1);
+        assert_eq!(nonces.misses(), 1);
+        assert_eq!(nonces.len(), 0);
+    }
+
+    #[test]
+    fn nonce_rejected_by_other_provider() {
+        let provider_a = NonceSet::new("a");
+        let provider_b = NonceSet::new("b");
+        let nonce = provider_a.insert_new();
+
+        assert!(matches!(
+            provider_b.validate_and_remove(nonce.as_str()),
+            Err(NonceError::Invalid)
+        ));
+        assert!(matches!(
+            provider_b.replace(nonce.as_str()),
+            Err(NonceError::Invalid)
+        ));
+
+        // rejecting it elsewhere must not consume it
+        assert!(provider_a.validate_and_remove(nonce.as_str()).is_ok());
+    }
+
+    #[test]
+    fn nonce_error_status() {
+        use actix_web::ResponseError;
+
+        let invalid = NonceError::Invalid.into_app_error();
+        assert_eq!(invalid.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(invalid.code(), Some("nonce_invalid"));
+
+        let expired = NonceError::Expired.into_app_error();
+        assert_eq!(expired.status_code(), StatusCode::GONE);
+        assert_eq!(expired.code(), Some("nonce_expired"));
+    }
+
+    /// Insert a nonce that was issued `age_ms` ago
+    fn insert_aged(nonces: &NonceSet, age_ms: i64) -> Nonce {
+        let nonce = nonces.insert_new();
+        if let Some(meta) = nonces.inner.lock().get_mut(nonce.as_str()) {
+            meta.time -= age_ms;
         }
+        nonce
+    }
+
+    #[test]
+    fn nonce_within_grace_is_accepted() {
+        let nonces = NonceSet::new("steam");
+        let nonce = insert_aged(&nonces, NONCE_MAX_AGE_MS + DEFAULT_NONCE_GRACE_MS / 2);
+        assert!(nonces.validate_and_remove(nonce.as_str()).is_ok());
+    }
+
+    #[test]
+    fn nonce_beyond_grace_is_rejected() {
+        let nonces = NonceSet::new("steam");
+        let nonce = insert_aged(&nonces, NONCE_MAX_AGE_MS + DEFAULT_NONCE_GRACE_MS + 1_000);
+        assert!(matches!(
+            nonces.validate_and_remove(nonce.as_str()),
+            Err(NonceError::Expired)
+        ));
+
+        let nonces = NonceSet::new("steam").with_grace_ms(0);
+        let nonce = insert_aged(&nonces, NONCE_MAX_AGE_MS + DEFAULT_NONCE_GRACE_MS / 2);
+        assert!(matches!(
+            nonces.validate_and_remove(nonce.as_str()),
+            Err(NonceError::Expired)
+        ));
+    }
+
+    #[test]
+    fn stats_count_hits_misses_and_evictions() -> anyhow::Result<()> {
+        let nonces = NonceSet::new("steam");
+        let hit = nonces.insert_new();
+        let replaced = nonces.insert_new();
+        insert_aged(&nonces, NONCE_MAX_AGE_MS + DEFAULT_NONCE_GRACE_MS + 1_000);
+
+        nonces.validate_and_remove(hit.as_str())?;
+        assert!(nonces.validate_and_remove(hit.as_str()).is_err());
+        nonces.replace(replaced.as_str())?;
+        nonces.remove_expired_nonces();
+
+        assert_eq!(
+            nonces.stats(),
+            NonceSetStats {
+                len: 1,
+                inserts: 4,
+                hits: 1,
+                misses: 1,
+                expired_evictions: 1,
+            }
+        );
+
+        Ok(())
     }
 }
@@ -0,0 +1,84 @@
+//! Providers discovered for logins that haven't come back from the OP yet
+//!
+//! The callback has to verify the assertion against the provider the user was sent to,
+//! so it is kept on the server keyed by the nonce of the login instead of trusting the query.
+
+use std::collections::HashMap;
+
+use crate::openid::{ClaimedId, Provider};
+use parking_lot::Mutex;
+
+use crate::util::nonce::{Nonce, NonceSet};
+
+#[derive(Debug)]
+pub(crate) struct PendingLogin {
+    pub(crate) provider: Provider,
+    /// Identifier the user supplied, see [`crate::openid::resolve_provider`]
+    pub(crate) claimed_id: ClaimedId,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PendingLogins {
+    inner: Mutex<HashMap<Nonce, PendingLogin>>,
+}
+
+impl PendingLogins {
+    pub(crate) fn insert(&self, nonce: Nonce, login: PendingLogin) {
+        let _ = self.inner.lock().insert(nonce, login);
+    }
+    /// Remove the login of `nonce`, every login can only be completed once
+    pub(crate) fn take(&self, nonce: &str) -> Option<PendingLogin> {
+        self.inner.lock().remove(nonce)
+    }
+    /// Remove the logins whose nonce isn't in `nonces` anymore,
+    /// call this after [`NonceSet::remove_expired_nonces`]
+    pub(crate) fn retain_valid(&self, nonces: &NonceSet) {
+        self.inner
+            .lock()
+            .retain(|nonce, _| nonces.validate(nonce.as_str()).is_ok());
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn login() -> PendingLogin {
+        PendingLogin {
+            provider: Provider::steam(),
+            claimed_id: ClaimedId::Select,
+        }
+    }
+
+    #[test]
+    fn login_is_taken_once() {
+        let nonces = NonceSet::new("test");
+        let logins = PendingLogins::default();
+        let nonce = nonces.insert_new();
+
+        logins.insert(nonce.clone(), login());
+        assert!(logins.take(nonce.as_str()).is_some());
+        assert!(logins.take(nonce.as_str()).is_none());
+    }
+
+    #[test]
+    fn logins_without_nonce_are_removed() -> anyhow::Result<()> {
+        let nonces = NonceSet::new("test");
+        let logins = PendingLogins::default();
+        let kept = nonces.insert_new();
+        let used = nonces.insert_new();
+
+        logins.insert(kept.clone(), login());
+        logins.insert(used.clone(), login());
+        nonces.validate_and_remove(used.as_str())?;
+
+        logins.retain_valid(&nonces);
+        assert_eq!(logins.len(), 1);
+        assert!(logins.take(kept.as_str()).is_some());
+
+        Ok(())
+    }
+}
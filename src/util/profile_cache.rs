@@ -0,0 +1,205 @@
+//! Player summaries fetched from the steam api, kept for a short while
+//!
+//! The steam api is rate-limited, so repeated lookups of the same profiles,
+//! e.g. a page that polls the summaries of its members, are served from here.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use steam_api_concurrent::SteamId;
+
+/// Default for `PROFILE_CACHE_TTL_SECS`
+pub(crate) const DEFAULT_PROFILE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct CachedProfile {
+    fetched_at: Instant,
+    /// `None` if steam doesn't know the id, that is cached as well
+    summary: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ProfileCache {
+    ttl: Duration,
+    inner: Mutex<HashMap<SteamId, CachedProfile>>,
+}
+
+impl ProfileCache {
+    /// Summaries are served from the cache for `ttl`, nothing is cached if it is zero
+    pub(crate) fn new(ttl: Duration) -> ProfileCache {
+        ProfileCache {
+            ttl,
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+    /// The summaries of `steam_ids` in the given order, duplicates and ids steam doesn't know
+    /// are left out
+    ///
+    /// Every id that isn't cached or expired is fetched with a single call to `fetch`.
+    pub(crate) async fn get_or_fetch<F, Fut, E>(
+        &self,
+        steam_ids: &[SteamId],
+        fetch: F,
+    ) -> Result<Vec<serde_json::Value>, E>
+    where
+        F: FnOnce(Vec<SteamId>) -> Fut,
+        Fut: Future<Output = Result<Vec<(SteamId, serde_json::Value)>, E>>,
+    {
+        let mut seen = HashSet::new();
+        let steam_ids: Vec<SteamId> = steam_ids
+            .iter()
+            .copied()
+            .filter(|&steam_id| seen.insert(steam_id))
+            .collect();
+
+        let mut found = HashMap::new();
+        let misses: Vec<SteamId> = {
+            let cached = self.inner.lock();
+            steam_ids
+                .iter()
+                .copied()
+                .filter(|steam_id| match cached.get(steam_id) {
+                    Some(cached) if cached.fetched_at.elapsed() < self.ttl => {
+                        let _ = found.insert(*steam_id, cached.summary.clone());
+                        false
+                    }
+                    _ => true,
+                })
+                .collect()
+        };
+
+        if !misses.is_empty() {
+            let mut fetched: HashMap<SteamId, serde_json::Value> =
+                fetch(misses.clone()).await?.into_iter().collect();
+            let fetched_at = Instant::now();
+            let fetched: Vec<_> = misses
+                .into_iter()
+                .map(|steam_id| (steam_id, fetched.remove(&steam_id)))
+                .collect();
+            if !self.ttl.is_zero() {
+                self.inner
+                    .lock()
+                    .extend(fetched.iter().map(|(steam_id, summary)| {
+                        let profile = CachedProfile {
+                            fetched_at,
+                            summary: summary.clone(),
+                        };
+                        (*steam_id, profile)
+                    }));
+            }
+            found.extend(fetched);
+        }
+
+        Ok(steam_ids
+            .into_iter()
+            .filter_map(|steam_id| found.remove(&steam_id).flatten())
+            .collect())
+    }
+    /// Forget the summaries that are older than the ttl
+    pub(crate) fn remove_expired(&self) {
+        let ttl = self.ttl;
+        self.inner
+            .lock()
+            .retain(|_, cached| cached.fetched_at.elapsed() < ttl);
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    const STEAM_ID: SteamId = SteamId(76561198181282063);
+    const OTHER_STEAM_ID: SteamId = SteamId(76561197960287930);
+
+    fn summary(steam_id: SteamId) -> serde_json::Value {
+        serde_json::json!({ "steamid": steam_id.to_string() })
+    }
+
+    /// What [`SteamApi::player_summaries`](crate::util::steam_api::SteamApi::player_summaries)
+    /// answers for `steam_ids`
+    fn summaries(steam_ids: Vec<SteamId>) -> Vec<(SteamId, serde_json::Value)> {
+        steam_ids
+            .into_iter()
+            .map(|steam_id| (steam_id, summary(steam_id)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetched_once_within_ttl() -> anyhow::Result<()> {
+        let calls = AtomicUsize::new(0);
+        let fetch = |steam_ids: Vec<SteamId>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move { anyhow::Ok(summaries(steam_ids)) }
+        };
+
+        let cache = ProfileCache::new(DEFAULT_PROFILE_CACHE_TTL);
+        let first = cache.get_or_fetch(&[STEAM_ID], fetch).await?;
+        let second = cache.get_or_fetch(&[STEAM_ID], fetch).await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first, second);
+        assert_eq!(cache.len(), 1);
+
+        let cache = ProfileCache::new(Duration::ZERO);
+        cache.get_or_fetch(&[STEAM_ID], fetch).await?;
+        cache.get_or_fetch(&[STEAM_ID], fetch).await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.len(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn misses_are_fetched_at_once() -> anyhow::Result<()> {
+        let cache = ProfileCache::new(DEFAULT_PROFILE_CACHE_TTL);
+        cache
+            .get_or_fetch(&[STEAM_ID], |steam_ids| async move {
+                anyhow::Ok(summaries(steam_ids))
+            })
+            .await?;
+
+        let requested = Mutex::new(Vec::new());
+        let summaries = cache
+            .get_or_fetch(
+                &[OTHER_STEAM_ID, STEAM_ID, SteamId(1), OTHER_STEAM_ID],
+                |steam_ids| {
+                    requested.lock().push(steam_ids);
+                    // steam doesn't know the last one and answers in any order
+                    async { anyhow::Ok(summaries(vec![OTHER_STEAM_ID])) }
+                },
+            )
+            .await?;
+        assert_eq!(*requested.lock(), [vec![OTHER_STEAM_ID, SteamId(1)]]);
+        assert_eq!(summaries, [summary(OTHER_STEAM_ID), summary(STEAM_ID)]);
+        assert_eq!(cache.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_fetch_is_not_cached() -> anyhow::Result<()> {
+        let cache = ProfileCache::new(DEFAULT_PROFILE_CACHE_TTL);
+        let failed = cache
+            .get_or_fetch(&[STEAM_ID], |_| async { anyhow::bail!("steam is down") })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(cache.len(), 0);
+
+        let unknown = cache
+            .get_or_fetch(&[STEAM_ID], |_| async { anyhow::Ok(Vec::new()) })
+            .await?;
+        assert!(unknown.is_empty());
+        assert_eq!(cache.len(), 1);
+
+        cache.remove_expired();
+        assert_eq!(cache.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! Reject requests with huge query strings before anything parses them
+//!
+//! The callbacks take a whole positive assertion from the query, a legit one is a few
+//! kilobytes and about a dozen params. Everything that isn't part of it is collected
+//! into a map, so the number of params is bounded as well.
+//! [`PositiveAssertion::validate`] bounds every single field.
+//!
+//! [`PositiveAssertion::validate`]: crate::openid::PositiveAssertion::validate
+
+use std::future::{ready, Future};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use futures_util::future::{Either, FutureExt};
+
+use crate::error::IntoAppError;
+
+/// Longest query string accepted by [`limit_query`]
+pub(crate) const MAX_QUERY_LEN: usize = 8 * 1024;
+
+/// Most params accepted by [`limit_query`], an assertion with some extensions fits easily
+pub(crate) const MAX_QUERY_PARAMS: usize = 64;
+
+/// Middleware for [`actix_web::Scope::wrap_fn`] responding with 400 to requests with
+/// a query string longer than [`MAX_QUERY_LEN`] or more than [`MAX_QUERY_PARAMS`] params
+pub(crate) fn limit_query<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let query = req.query_string();
+    let err = if query.len() > MAX_QUERY_LEN {
+        anyhow::anyhow!(
+            "query string is {} bytes long, at most {} are allowed",
+            query.len(),
+            MAX_QUERY_LEN
+        )
+        .into_app_error_bad_request()
+        .with_code("query_too_long")
+    } else if query.split('&').count() > MAX_QUERY_PARAMS {
+        anyhow::anyhow!("query has more than {} params", MAX_QUERY_PARAMS)
+            .into_app_error_bad_request()
+            .with_code("query_too_complex")
+    } else {
+        return Either::Left(
+            srv.call(req)
+                .map(|res| res.map(ServiceResponse::map_into_left_body)),
+        );
+    };
+
+    let res = req.error_response(err).map_into_right_body();
+    Either::Right(ready(Ok(res)))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn long_or_complex_query_is_rejected() -> anyhow::Result<()> {
+        let app = init_service(
+            App::new().service(
+                web::scope("/limited")
+                    .wrap_fn(limit_query)
+                    .route("", web::get().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let request = |query: &str| {
+            TestRequest::get()
+                .uri(&format!("/limited?{}", query))
+                .to_request()
+        };
+
+        let res = call_service(&app, request(&"a".repeat(MAX_QUERY_LEN))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = call_service(&app, request(&"a".repeat(MAX_QUERY_LEN + 1))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "query_too_long");
+
+        let res = call_service(&app, request(&"a&".repeat(MAX_QUERY_PARAMS))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = actix_web::test::read_body_json(res).await;
+        assert_eq!(body["code"], "query_too_complex");
+
+        Ok(())
+    }
+}
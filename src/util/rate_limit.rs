@@ -0,0 +1,401 @@
+//! Token bucket rate limiting keyed on the client ip
+//!
+//! Every client starts with a full bucket of `burst` tokens, each request takes one
+//! and tokens are refilled at `per_sec`. A client with an empty bucket gets a 429.
+//!
+//! The client ip is the peer address, `X-Forwarded-For` is only used for requests
+//! coming from one of the [trusted proxies](RateLimiter::with_trusted_proxies).
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::{ready, Future};
+use std::net::IpAddr;
+use std::time::Instant;
+
+use crate::openid::comma_separated::CommaSeparatedTrimmed;
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::web;
+use anyhow::Context;
+use futures_util::future::{Either, FutureExt};
+use parking_lot::Mutex;
+
+use crate::error::IntoAppError;
+
+const DEFAULT_BURST: u32 = 10;
+const DEFAULT_PER_SEC: f64 = 1.0;
+
+/// The client that was seen longest ago is forgotten once this many are tracked,
+/// it starts over with a full bucket if it comes back
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Key for requests without a known peer address, they all share one bucket
+const UNKNOWN_CLIENT: &str = "unknown";
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimit {
+    /// Requests a client may make at once
+    pub(crate) burst: u32,
+    /// Requests a client may make per second on average
+    pub(crate) per_sec: f64,
+}
+
+impl RateLimit {
+    /// Configured through `AUTH_RATE_LIMIT_BURST` and `AUTH_RATE_LIMIT_PER_SEC`
+    pub(crate) fn from_env() -> anyhow::Result<RateLimit> {
+        let burst = match dotenv::var("AUTH_RATE_LIMIT_BURST") {
+            Ok(burst) => burst
+                .parse()
+                .context("couldn't parse AUTH_RATE_LIMIT_BURST as an integer")?,
+            Err(_) => DEFAULT_BURST,
+        };
+        let per_sec = match dotenv::var("AUTH_RATE_LIMIT_PER_SEC") {
+            Ok(per_sec) => per_sec
+                .parse()
+                .context("couldn't parse AUTH_RATE_LIMIT_PER_SEC as a number")?,
+            Err(_) => DEFAULT_PER_SEC,
+        };
+        if burst == 0 {
+            anyhow::bail!("AUTH_RATE_LIMIT_BURST must be at least 1");
+        }
+        if per_sec.is_nan() || per_sec <= 0.0 {
+            anyhow::bail!("AUTH_RATE_LIMIT_PER_SEC must be positive");
+        }
+        Ok(RateLimit { burst, per_sec })
+    }
+}
+
+/// Configured through `AUTH_RATE_LIMIT_TRUSTED_PROXIES` as a comma separated list of ips,
+/// no proxy is trusted if it is unset or empty
+pub(crate) fn trusted_proxies_from_env() -> anyhow::Result<Vec<IpAddr>> {
+    match dotenv::var("AUTH_RATE_LIMIT_TRUSTED_PROXIES") {
+        Ok(proxies) if !proxies.trim().is_empty() => Ok(proxies
+            .parse::<CommaSeparatedTrimmed<IpAddr>>()
+            .context("couldn't parse AUTH_RATE_LIMIT_TRUSTED_PROXIES as comma separated ips")?
+            .into_inner()),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Orders the buckets by when they were last used, so the oldest one can be evicted
+type BucketAge = (Instant, u64);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Key of the bucket in [`Buckets::by_age`]
+    age: BucketAge,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_sec).min(f64::from(limit.burst));
+        self.updated = now;
+    }
+}
+
+/// Buckets of at most `max_clients` clients, the least recently seen one is evicted
+#[derive(Debug, Default)]
+struct Buckets {
+    by_client: HashMap<String, Bucket>,
+    by_age: BTreeMap<BucketAge, String>,
+    /// Tells apart buckets used at the same instant
+    next_seq: u64,
+}
+
+impl Buckets {
+    const fn next_age(&mut self, now: Instant) -> BucketAge {
+        self.next_seq += 1;
+        (now, self.next_seq)
+    }
+    /// Bucket of `client`, refilled up to `now`
+    fn get(
+        &mut self,
+        client: &str,
+        limit: RateLimit,
+        max_clients: usize,
+        now: Instant,
+    ) -> &mut Bucket {
+        let age = self.next_age(now);
+        if let Some(bucket) = self.by_client.get(client) {
+            let _ = self.by_age.remove(&bucket.age);
+        } else if self.by_client.len() >= max_clients {
+            if let Some((_, oldest)) = self.by_age.pop_first() {
+                let _ = self.by_client.remove(&oldest);
+            }
+        }
+        let _ = self.by_age.insert(age, client.to_string());
+
+        let bucket = self
+            .by_client
+            .entry(client.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+                age,
+            });
+        bucket.age = age;
+        bucket.refill(limit, now);
+        bucket
+    }
+    fn len(&self) -> usize {
+        self.by_client.len()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// Peers whose `X-Forwarded-For` header is used to find the client ip
+    trusted_proxies: Vec<IpAddr>,
+    max_clients: usize,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            trusted_proxies: Vec::new(),
+            max_clients: MAX_TRACKED_CLIENTS,
+            buckets: Mutex::new(Buckets::default()),
+        }
+    }
+    /// Trust `X-Forwarded-For` of requests from `proxies`, see [`RateLimiter::client_ip`]
+    pub(crate) fn with_trusted_proxies(mut self, proxies: Vec<IpAddr>) -> RateLimiter {
+        self.trusted_proxies = proxies;
+        self
+    }
+    /// Take a token from the bucket of `client`, `false` if there is none left
+    pub(crate) fn check(&self, client: &str) -> bool {
+        self.check_at(client, Instant::now())
+    }
+    fn check_at(&self, client: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.get(client, self.limit, self.max_clients, now);
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        drop(buckets);
+        allowed
+    }
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.contains(&ip)
+    }
+    /// Ip of the client that sent `req`
+    ///
+    /// That is the peer address, unless the peer is a trusted proxy. Then it is the
+    /// last address in `X-Forwarded-For` that isn't a trusted proxy, since every proxy
+    /// appends the address it received the request from and the client can put
+    /// anything in front of that.
+    fn client_ip(&self, req: &ServiceRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+        let forwarded: Vec<IpAddr> = req
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|ip| ip.trim().parse().ok())
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+        // a list that doesn't parse could have been forged, fall back to the proxy
+        let client = forwarded.into_iter().rev().find(|ip| !self.is_trusted(*ip));
+        Some(client.unwrap_or(peer))
+    }
+}
+
+/// Header every proxy appends the address it received the request from to
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Middleware for [`actix_web::Scope::wrap_fn`] responding with 429 to
+/// clients exceeding the [`RateLimiter`] registered as app data.
+///
+/// Clients are told apart by [`RateLimiter::client_ip`].
+pub(crate) fn rate_limit<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let allowed = req
+        .app_data::<web::Data<RateLimiter>>()
+        .is_none_or(|limiter| {
+            let client = limiter
+                .client_ip(&req)
+                .map_or_else(|| UNKNOWN_CLIENT.to_string(), |ip| ip.to_string());
+            limiter.check(&client)
+        });
+
+    if allowed {
+        return Either::Left(
+            srv.call(req)
+                .map(|res| res.map(ServiceResponse::map_into_left_body)),
+        );
+    }
+
+    let err = anyhow::anyhow!("too many requests, slow down").into_app_error_too_many_requests();
+    let res = req.error_response(err).map_into_right_body();
+    Either::Right(ready(Ok(res)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{App, HttpResponse};
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit {
+        burst: 3,
+        per_sec: 1.0,
+    };
+
+    #[test]
+    fn bucket_refills() {
+        let limiter = RateLimiter::new(LIMIT);
+        let start = Instant::now();
+
+        for _ in 0..LIMIT.burst {
+            assert!(limiter.check_at("a", start));
+        }
+        assert!(!limiter.check_at("a", start));
+        // other clients have their own bucket
+        assert!(limiter.check_at("b", start));
+
+        let later = start + Duration::from_millis(1_100);
+        assert!(limiter.check_at("a", later));
+        assert!(!limiter.check_at("a", later));
+    }
+
+    #[test]
+    fn oldest_client_is_evicted() {
+        let limiter = RateLimiter {
+            max_clients: 2,
+            ..RateLimiter::new(LIMIT)
+        };
+        let start = Instant::now();
+
+        for _ in 0..LIMIT.burst {
+            assert!(limiter.check_at("a", start));
+        }
+        assert!(limiter.check_at("b", start));
+        // `a` was seen more recently than `b`, so `b` makes room for `c`
+        assert!(!limiter.check_at("a", start));
+        assert!(limiter.check_at("c", start));
+        assert_eq!(limiter.buckets.lock().len(), 2);
+        assert!(!limiter.check_at("a", start));
+
+        for client in 0..100 {
+            assert!(limiter.check_at(&client.to_string(), start));
+        }
+        assert_eq!(limiter.buckets.lock().len(), 2);
+    }
+
+    /// Responds with 429 after [`LIMIT`] requests of the same client
+    macro_rules! limited_app {
+        ($limiter:expr) => {
+            init_service(
+                App::new().app_data(web::Data::new($limiter)).service(
+                    web::scope("/auth")
+                        .wrap_fn(rate_limit)
+                        .route("/login", web::get().to(HttpResponse::Ok)),
+                ),
+            )
+            .await
+        };
+    }
+
+    fn request_from(peer: &str, forwarded_for: &str) -> TestRequest {
+        TestRequest::get()
+            .uri("/auth/login")
+            .peer_addr(peer.parse().unwrap())
+            .insert_header((X_FORWARDED_FOR, forwarded_for))
+    }
+
+    #[actix_web::test]
+    async fn forwarded_for_of_untrusted_peer_is_ignored() {
+        let app = limited_app!(RateLimiter::new(LIMIT));
+
+        for i in 0..LIMIT.burst {
+            let forwarded_for = format!("10.0.0.{}", i);
+            let res = call_service(
+                &app,
+                request_from("127.0.0.1:1337", &forwarded_for).to_request(),
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = call_service(
+            &app,
+            request_from("127.0.0.1:1337", "10.0.0.99").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn forwarded_for_of_trusted_proxy_is_used() {
+        let proxy: IpAddr = "127.0.0.1".parse().unwrap();
+        let app = limited_app!(RateLimiter::new(LIMIT).with_trusted_proxies(vec![proxy]));
+
+        for _ in 0..LIMIT.burst {
+            let res = call_service(
+                &app,
+                request_from("127.0.0.1:1337", "10.0.0.1").to_request(),
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        // whatever the client puts in front of its own address doesn't matter
+        let res = call_service(
+            &app,
+            request_from("127.0.0.1:1337", "10.0.0.2, 10.0.0.1").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let res = call_service(
+            &app,
+            request_from("127.0.0.1:1337", "10.0.0.2").to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn rapid_requests_are_rejected() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(RateLimiter::new(LIMIT)))
+                .service(
+                    web::scope("/auth")
+                        .wrap_fn(rate_limit)
+                        .route("/login", web::get().to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        let request = || {
+            TestRequest::get()
+                .uri("/auth/login")
+                .peer_addr("127.0.0.1:1337".parse().unwrap())
+                .to_request()
+        };
+
+        for _ in 0..LIMIT.burst {
+            let res = call_service(&app, request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = call_service(&app, request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
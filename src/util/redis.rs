@@ -0,0 +1,81 @@
+//! Minimal redis client, just enough to check that the session store is reachable
+//!
+//! The session middleware talks to redis through its own actor and doesn't expose
+//! the connection, so readiness checks open a short-lived connection instead.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// `PING` encoded as a RESP array <https://redis.io/docs/reference/protocol-spec/>
+const PING_COMMAND: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+const PONG_REPLY: &str = "+PONG";
+
+/// Send a `PING` to the redis server at `addr` (`host:port`) and expect a `PONG`
+pub(crate) async fn ping(addr: &str, timeout: Duration) -> anyhow::Result<()> {
+    tokio::time::timeout(timeout, ping_inner(addr))
+        .await
+        .with_context(|| format!("redis at `{}` didn't answer within {:?}", addr, timeout))?
+}
+
+async fn ping_inner(addr: &str) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("couldn't connect to redis at `{}`", addr))?;
+    stream
+        .write_all(PING_COMMAND)
+        .await
+        .context("couldn't send PING to redis")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .await
+        .context("couldn't read reply from redis")?;
+
+    let reply = reply.trim_end();
+    if reply != PONG_REPLY {
+        anyhow::bail!("redis replied with `{}` instead of PONG", reply);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Accept a single connection, read the command and answer with `reply`
+    async fn fake_redis(reply: &'static [u8]) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut command = [0; PING_COMMAND.len()];
+            stream.read_exact(&mut command).await?;
+            stream.write_all(reply).await?;
+            anyhow::Ok(())
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn ping_works() -> anyhow::Result<()> {
+        let addr = fake_redis(b"+PONG\r\n").await?;
+        ping(&addr, TIMEOUT).await
+    }
+
+    #[tokio::test]
+    async fn ping_rejects_error_reply() -> anyhow::Result<()> {
+        let addr = fake_redis(b"-NOAUTH Authentication required.\r\n").await?;
+        assert!(ping(&addr, TIMEOUT).await.is_err());
+        Ok(())
+    }
+}
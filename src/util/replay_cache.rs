@@ -0,0 +1,107 @@
+//! Positive assertions that have been accepted, so the same one can't log in twice
+//!
+//! The response nonce is only unique per association of the OP, so an assertion is
+//! identified by its `op_endpoint`, `assoc_handle` and `response_nonce`.
+//! It only has to be remembered as long as its nonce passes the time checks,
+//! see [`NonceTolerance::window_ms`].
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::openid::nonce::NonceTolerance;
+use parking_lot::Mutex;
+
+/// `(op_endpoint, assoc_handle, response_nonce)` of an assertion
+pub(crate) type ReplayKey<'a> = (&'a str, &'a str, &'a str);
+
+#[derive(Debug)]
+pub(crate) struct ReplayCache {
+    window: Duration,
+    inner: Mutex<HashMap<(String, String, String), Instant>>,
+}
+
+impl ReplayCache {
+    /// Assertions are remembered for `window` after they have been recorded
+    pub(crate) fn new(window: Duration) -> ReplayCache {
+        ReplayCache {
+            window,
+            inner: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Remember assertions as long as their response nonce can pass the time checks
+    pub(crate) fn for_tolerance(tolerance: NonceTolerance) -> ReplayCache {
+        let window_ms = u64::try_from(tolerance.window_ms()).unwrap_or_default();
+        ReplayCache::new(Duration::from_millis(window_ms))
+    }
+    /// Record the assertion identified by `key`, fails if it was recorded within the window
+    ///
+    /// A replay is recorded as well, so the window starts over with every attempt.
+    pub(crate) fn check_and_record(
+        &self,
+        (op_endpoint, assoc_handle, nonce): ReplayKey<'_>,
+    ) -> anyhow::Result<()> {
+        let key = (
+            op_endpoint.to_string(),
+            assoc_handle.to_string(),
+            nonce.to_string(),
+        );
+        let recorded_at = self.inner.lock().insert(key, Instant::now());
+        if recorded_at.is_some_and(|recorded_at| recorded_at.elapsed() < self.window) {
+            anyhow::bail!(
+                "assertion with response nonce {:?} and handle {:?} has already been used",
+                nonce,
+                assoc_handle
+            );
+        }
+        Ok(())
+    }
+    /// Forget the assertions that were recorded longer than the window ago
+    pub(crate) fn remove_expired(&self) {
+        let window = self.window;
+        self.inner
+            .lock()
+            .retain(|_, recorded_at| recorded_at.elapsed() < window);
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ENDPOINT: &str = "https://steamcommunity.com/openid/login";
+    const NONCE: &str = "2023-09-15T11:23:46Z7RPb74voq1sqY2sKMcnOe/rxwQg=";
+
+    #[test]
+    fn repeated_assertion_is_rejected() -> anyhow::Result<()> {
+        let cache = ReplayCache::for_tolerance(NonceTolerance::default());
+        cache.check_and_record((ENDPOINT, "1234567890", NONCE))?;
+        assert!(cache
+            .check_and_record((ENDPOINT, "1234567890", NONCE))
+            .is_err());
+        assert_eq!(cache.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn same_nonce_with_other_handle_is_accepted() -> anyhow::Result<()> {
+        let cache = ReplayCache::for_tolerance(NonceTolerance::default());
+        cache.check_and_record((ENDPOINT, "1234567890", NONCE))?;
+        cache.check_and_record((ENDPOINT, "0987654321", NONCE))?;
+        cache.check_and_record(("https://example.com/openid", "1234567890", NONCE))?;
+        assert_eq!(cache.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn expired_assertions_are_forgotten() -> anyhow::Result<()> {
+        let cache = ReplayCache::new(Duration::ZERO);
+        cache.check_and_record((ENDPOINT, "1234567890", NONCE))?;
+        cache.check_and_record((ENDPOINT, "1234567890", NONCE))?;
+        cache.remove_expired();
+        assert_eq!(cache.len(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! The parts of the steam web api the handlers use, behind a trait so tests can
+//! swap in a [`MockSteamApi`](super::mock_steam_api::MockSteamApi)
+//!
+//! Responses are handed out as json, the handlers only pass them on.
+
+use std::borrow::Cow;
+
+use anyhow::Context;
+use futures_util::future::LocalBoxFuture;
+use futures_util::FutureExt;
+use steam_api_concurrent::{PlayerSummary, SteamId};
+
+pub(crate) trait SteamApi: Send + Sync {
+    /// Summaries of the profiles steam knows along with their id, unknown ids are left out
+    fn player_summaries(
+        &self,
+        steam_ids: Vec<SteamId>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Vec<(SteamId, serde_json::Value)>>>;
+    fn player_bans(
+        &self,
+        steam_ids: Vec<SteamId>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Vec<serde_json::Value>>>;
+    fn steam_level(
+        &self,
+        steam_id: SteamId,
+    ) -> LocalBoxFuture<'_, anyhow::Result<serde_json::Value>>;
+}
+
+/// Pair every summary with the id it belongs to, see [`SteamApi::player_summaries`]
+fn to_summaries(
+    summaries: Vec<PlayerSummary>,
+) -> anyhow::Result<Vec<(SteamId, serde_json::Value)>> {
+    summaries
+        .into_iter()
+        .map(|summary| Ok((summary.steam_id, serde_json::to_value(summary)?)))
+        .collect::<Result<_, serde_json::Error>>()
+        .context("couldn't serialize steam api response")
+}
+
+/// Serialize every element on its own, see [`SteamApi::player_bans`]
+fn to_values<T: serde::Serialize>(values: Vec<T>) -> anyhow::Result<Vec<serde_json::Value>> {
+    values
+        .into_iter()
+        .map(serde_json::to_value)
+        .collect::<Result<_, _>>()
+        .context("couldn't serialize steam api response")
+}
+
+impl SteamApi for steam_api_concurrent::Client {
+    fn player_summaries(
+        &self,
+        steam_ids: Vec<SteamId>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Vec<(SteamId, serde_json::Value)>>> {
+        async move {
+            let resp = self.get_player_summaries(Cow::Owned(steam_ids)).await;
+            to_summaries(resp.context("couldn't fetch from steam api")?.into_inner())
+        }
+        .boxed_local()
+    }
+    fn player_bans(
+        &self,
+        steam_ids: Vec<SteamId>,
+    ) -> LocalBoxFuture<'_, anyhow::Result<Vec<serde_json::Value>>> {
+        async move {
+            let resp = self.get_player_bans(Cow::Owned(steam_ids)).await;
+            to_values(resp.context("couldn't fetch from steam api")?.into_inner())
+        }
+        .boxed_local()
+    }
+    fn steam_level(
+        &self,
+        steam_id: SteamId,
+    ) -> LocalBoxFuture<'_, anyhow::Result<serde_json::Value>> {
+        async move {
+            let resp = self.get_player_steam_level(steam_id).await;
+            serde_json::to_value(resp.context("couldn't fetch from steam api")?.into_inner())
+                .context("couldn't serialize steam api response")
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A summary as the steam web api sends it
+    const SUMMARY: &str = r#"{
+        "steamid": "76561198181282063",
+        "communityvisibilitystate": 3,
+        "profilestate": 1,
+        "personaname": "complainer",
+        "profileurl": "https://steamcommunity.com/id/complainer/",
+        "avatar": "https://avatars.steamstatic.com/fef49e7fa7e1997310d705b2a6158ff8dc1cdfeb.jpg",
+        "avatarmedium": "https://avatars.steamstatic.com/fef49e7fa7e1997310d705b2a6158ff8dc1cdfeb_medium.jpg",
+        "avatarfull": "https://avatars.steamstatic.com/fef49e7fa7e1997310d705b2a6158ff8dc1cdfeb_full.jpg",
+        "avatarhash": "fef49e7fa7e1997310d705b2a6158ff8dc1cdfeb",
+        "lastlogoff": 1694774400,
+        "personastate": 0,
+        "primaryclanid": "103582791429521408",
+        "timecreated": 1425833420,
+        "personastateflags": 0
+    }"#;
+
+    #[test]
+    fn summaries_are_keyed_by_steam_id() -> anyhow::Result<()> {
+        let summary: PlayerSummary = serde_json::from_str(SUMMARY)?;
+        let expected = serde_json::to_value(&summary)?;
+        let summaries = to_summaries(vec![summary])?;
+
+        assert_eq!(summaries, [(SteamId(76561198181282063), expected)]);
+        Ok(())
+    }
+}
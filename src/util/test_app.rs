@@ -0,0 +1,78 @@
+//! The app the handler tests run against and the helpers to drive it
+//!
+//! [`test_app!`] builds the app like [`serve`](crate::server::serve) does, with a cookie
+//! session store in place of redis and a `/test/authenticate` route that logs the session
+//! in as [`STEAM_ID`] without going through an OP.
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use steam_api_concurrent::SteamId;
+
+use crate::api::session::AuthSession;
+use crate::error::AppResponse;
+use crate::state::State;
+use crate::util::rate_limit::RateLimit;
+
+pub(crate) const STEAM_ID: SteamId = SteamId(76561198181282063);
+
+/// Enough for any single test, the rate limiter has tests of its own
+pub(crate) const RATE_LIMIT: RateLimit = RateLimit {
+    burst: 1000,
+    per_sec: 1000.0,
+};
+
+/// Log the session in without going through steam
+pub(crate) async fn authenticate(
+    session: actix_session::Session,
+    data: web::Data<State>,
+) -> AppResponse {
+    session.authenticate(&data, STEAM_ID)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Start the app around `data`
+macro_rules! test_app {
+    ($data:expr) => {
+        actix_web::test::init_service(
+            crate::server::create_app(
+                $data,
+                actix_web::web::Data::new(crate::util::rate_limit::RateLimiter::new(
+                    crate::util::test_app::RATE_LIMIT,
+                )),
+                crate::server::create_cookie_session_mw(actix_web::cookie::Key::generate()),
+            )
+            .route(
+                "/test/authenticate",
+                actix_web::web::get().to(crate::util::test_app::authenticate),
+            ),
+        )
+        .await
+    };
+}
+pub(crate) use test_app;
+
+pub(crate) fn session_cookie<B>(res: &ServiceResponse<B>) -> anyhow::Result<Cookie<'static>> {
+    res.response()
+        .cookies()
+        .next()
+        .map(Cookie::into_owned)
+        .context("response didn't set the session cookie")
+}
+
+/// Target of a `303 See Other`
+pub(crate) fn location<B>(res: &ServiceResponse<B>) -> anyhow::Result<&str> {
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    res.headers()
+        .get(header::LOCATION)
+        .context("redirect without location")?
+        .to_str()
+        .context("location isn't ascii")
+}
+
+/// Path and query of `url`, to request it from the test app
+pub(crate) fn path_and_query(url: &reqwest::Url) -> String {
+    format!("{}?{}", url.path(), url.query().unwrap_or_default())
+}
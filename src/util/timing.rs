@@ -0,0 +1,19 @@
+//! Per-phase timings of the login flow, see [`timed`]
+
+/// Evaluate the expression and log how long that took at debug level
+/// as `[timing] <label> took <n>ms`, only with the `timing` feature.
+///
+/// The request logger only reports the total time of a request,
+/// this tells apart slow round-trips to steam from slowness on our side.
+macro_rules! timed {
+    ($label:expr, $expr:expr) => {{
+        #[cfg(feature = "timing")]
+        let start = ::std::time::Instant::now();
+        let result = $expr;
+        #[cfg(feature = "timing")]
+        ::log::debug!("[timing] {} took {}ms", $label, start.elapsed().as_millis());
+        result
+    }};
+}
+
+pub(crate) use timed;